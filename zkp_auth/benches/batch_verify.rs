@@ -0,0 +1,53 @@
+//Batch verification against checking every proof on its own.
+//Run with: cargo bench --bench batch_verify
+//Each group is timed for a batch of N valid proofs with verify_batch and with N
+//calls to verify_solution, and once more with one bad proof in the batch so the
+//cost of the fallback that finds it shows up too.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+use zkp_auth::{parameter_set, BatchEntry, Group, Ristretto255Group, ZKP, RFC3526_2048_GROUP_ID, RFC5114_1024_GROUP_ID, RFC5114_2048_256_GROUP_ID};
+
+const SIZES: [usize; 3] = [16, 64, 256];
+
+fn entries<G: Group>(zkp: &ZKP<G>, n: usize) -> Vec<BatchEntry<G>> {
+    (0..n).map(|_| {
+        let x = zkp.group.random_scalar();
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+        let (r1, r2, k) = zkp.commit(&x, &y1, &y2, b"bench");
+        let c = zkp.group.random_scalar();
+        let s = zkp.solve(&k, &c, &x);
+        BatchEntry { y1, y2, r1, r2, c, s }
+    }).collect()
+}
+
+fn bench_group<G: Group>(c: &mut Criterion, name: &str, zkp: ZKP<G>) {
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for n in SIZES {
+        let batch = entries(&zkp, n);
+        group.bench_with_input(BenchmarkId::new("one_by_one", n), &batch, |b, batch| {
+            b.iter(|| batch.iter().all(|e| zkp.verify_solution(&e.r1, &e.r2, &e.y1, &e.y2, &e.c, &e.s)))
+        });
+        group.bench_with_input(BenchmarkId::new("verify_batch", n), &batch, |b, batch| {
+            b.iter(|| black_box(zkp.verify_batch(batch)))
+        });
+
+        let mut with_bad = batch.clone();
+        with_bad[n / 2].s = zkp.group.random_scalar();
+        group.bench_with_input(BenchmarkId::new("verify_batch_one_bad", n), &with_bad, |b, batch| {
+            b.iter(|| black_box(zkp.verify_batch(batch)))
+        });
+    }
+    group.finish();
+}
+
+fn batch_verify(c: &mut Criterion) {
+    for id in [RFC5114_1024_GROUP_ID, RFC5114_2048_256_GROUP_ID, RFC3526_2048_GROUP_ID] {
+        bench_group(c, id, ZKP::new(parameter_set(id).unwrap().group().unwrap()));
+    }
+    bench_group(c, "ristretto255", ZKP::new(Ristretto255Group::new()));
+}
+
+criterion_group!(benches, batch_verify);
+criterion_main!(benches);
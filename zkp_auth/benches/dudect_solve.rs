@@ -0,0 +1,95 @@
+//dudect-style timing test for s = k - c * x mod q.
+//Runs solve() on two classes of secrets, a fixed x and random x values, in a
+//random interleaved order and compares the two timing distributions with
+//Welch's t-test. |t| above 4.5 means the running time depends on the secret.
+//
+//Run with: cargo bench --bench dudect_solve
+//The first line times the num_bigint solve() the crate used to have,
+//the second the constant-time limbs in ct.rs that solve() uses now.
+
+use num_bigint::{BigUint, RandBigInt};
+use rand::Rng;
+use std::hint::black_box;
+use std::time::Instant;
+use zkp_auth::ct::{CtModulus, CtScalar};
+use zkp_auth::{parameter_set, RFC5114_2048_256_GROUP_ID};
+
+const SAMPLES: usize = 200_000;
+//calls per sample, so one sample is long enough for the timer
+const REPEAT: usize = 8;
+const THRESHOLD: f64 = 4.5;
+
+//the solve() this crate had before the constant-time scalars
+fn variable_time_solve(k: &BigUint, c: &BigUint, x: &BigUint, q: &BigUint) -> BigUint {
+    if *k >= c * x {
+        return (k - c * x).modpow(&BigUint::from(1u32), q);
+    }
+    q - (c * x - k).modpow(&BigUint::from(1u32), q)
+}
+
+fn ct_solve(q: &CtModulus, k: &CtScalar, c: &CtScalar, x: &CtScalar) -> CtScalar {
+    q.sub(k, &q.mul(c, x))
+}
+
+//Welch's t statistic between the two classes
+fn welch_t(a: &[f64], b: &[f64]) -> f64 {
+    let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+    let var = |v: &[f64], m: f64| v.iter().map(|x| (x - m).powi(2)).sum::<f64>() / (v.len() - 1) as f64;
+    let (ma, mb) = (mean(a), mean(b));
+    (ma - mb) / (var(a, ma) / a.len() as f64 + var(b, mb) / b.len() as f64).sqrt()
+}
+
+//like dudect, drops the slowest samples (interrupts, page faults) before testing
+fn crop(samples: &[f64], percentile: f64) -> Vec<f64> {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let cut = sorted[((sorted.len() - 1) as f64 * percentile) as usize];
+    samples.iter().copied().filter(|s| *s <= cut).collect()
+}
+
+//inputs for every sample are made before timing starts, so generating
+//them does not disturb the caches differently for the two classes
+fn run<T>(name: &str, inputs: &[(bool, T)], measure: impl Fn(&T)) {
+    let (mut fixed, mut random) = (Vec::new(), Vec::new());
+    for (class, input) in inputs {
+        let start = Instant::now();
+        for _ in 0..REPEAT {
+            measure(input);
+        }
+        let t = start.elapsed().as_nanos() as f64;
+        if *class { fixed.push(t) } else { random.push(t) }
+    }
+
+    let t = welch_t(&crop(&fixed, 0.9), &crop(&random, 0.9));
+    let verdict = if t.abs() > THRESHOLD { "timing depends on the secret" } else { "no leak detected" };
+    println!("{:<28} t = {:>8.2}   {}", name, t, verdict);
+}
+
+fn main() {
+    let q = parameter_set(RFC5114_2048_256_GROUP_ID).unwrap().group().unwrap().q;
+    let modulus = CtModulus::new(&q);
+    let mut rng = rand::thread_rng();
+
+    //a small fixed secret, the case a variable-time implementation handles fastest
+    let fixed_x = BigUint::from(1u32);
+
+    //class true is the fixed secret, class false a fresh random one, in random order
+    let inputs: Vec<(bool, (BigUint, BigUint, BigUint))> = (0..SAMPLES).map(|_| {
+        let class = rng.r#gen::<bool>();
+        let x = if class { fixed_x.clone() } else { rng.gen_biguint_below(&q) };
+        (class, (rng.gen_biguint_below(&q), rng.gen_biguint_below(&q), x))
+    }).collect();
+
+    println!("{} samples per test, q is {} bits", SAMPLES, q.bits());
+
+    run("num_bigint solve", &inputs, |(k, c, x)| {
+        black_box(variable_time_solve(black_box(k), black_box(c), black_box(x), &q));
+    });
+
+    let ct_inputs: Vec<(bool, (CtScalar, CtScalar, CtScalar))> = inputs.iter()
+        .map(|(class, (k, c, x))| (*class, (modulus.scalar(k), modulus.scalar(c), modulus.scalar(x))))
+        .collect();
+    run("constant-time solve", &ct_inputs, |(k, c, x)| {
+        black_box(ct_solve(&modulus, black_box(k), black_box(c), black_box(x)));
+    });
+}
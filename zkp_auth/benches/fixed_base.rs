@@ -0,0 +1,39 @@
+//Per-request cost of a verification with and without the shared parameter context.
+//Run with: cargo bench --bench fixed_base
+//For each MODP set one login is verified three ways: building the group for the
+//request as the binaries used to, with a group built once but without tables,
+//and with the &'static ZKP from zkp_auth::context and its fixed-base tables.
+//The prover's exponentiate_pair is timed the same way.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+use zkp_auth::{context, parameter_set, Group, ModpGroup, ZKP, RFC3526_2048_GROUP_ID, RFC5114_1024_GROUP_ID, RFC5114_2048_256_GROUP_ID};
+
+fn fixed_base(c: &mut Criterion) {
+    for id in [RFC5114_1024_GROUP_ID, RFC5114_2048_256_GROUP_ID, RFC3526_2048_GROUP_ID] {
+        let set = parameter_set(id).unwrap();
+        let plain = ZKP::new(set.group().unwrap());
+        let shared = context::modp(id).unwrap();
+
+        let x = plain.group.random_scalar();
+        let (y1, y2) = plain.exponentiate_pair(&x);
+        let (r1, r2, k) = plain.commit(&x, &y1, &y2, b"bench");
+        let challenge = plain.group.random_scalar();
+        let s = plain.solve(&k, &challenge, &x);
+        let verify = |zkp: &ZKP<ModpGroup>| zkp.verify_solution(&r1, &r2, &y1, &y2, &challenge, &s);
+
+        let mut group = c.benchmark_group(id);
+        group.sample_size(20);
+        group.bench_function("verify/group_per_request", |b| {
+            b.iter(|| verify(&ZKP::new(set.group().unwrap())))
+        });
+        group.bench_function("verify/no_tables", |b| b.iter(|| verify(&plain)));
+        group.bench_function("verify/context", |b| b.iter(|| verify(shared)));
+        group.bench_function("exponentiate_pair/no_tables", |b| b.iter(|| black_box(plain.exponentiate_pair(&x))));
+        group.bench_function("exponentiate_pair/context", |b| b.iter(|| black_box(shared.exponentiate_pair(&x))));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, fixed_base);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+//What the server pays to check a puzzle against what a challenge costs it.
+//Run with: cargo bench --bench puzzle_verify
+//check is the HMAC over the puzzle and the hash of the solution, exponentiate_pair is the two
+//modpows of a commitment in the 1024-bit group, about what its subgroup check costs.
+
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+use zkp_auth::puzzle::{self, PuzzlePolicy, Puzzles, SEED_LEN};
+use zkp_auth::zkp_proto::ChallengeRequest;
+use zkp_auth::{parameter_set, Group, ZKP, RFC5114_1024_GROUP_ID};
+
+fn puzzle_verify(c: &mut Criterion) {
+    let zkp = ZKP::new(parameter_set(RFC5114_1024_GROUP_ID).unwrap().group().unwrap());
+    let (r1, r2) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+    let policy = PuzzlePolicy { start_at: 0, min_difficulty: 8, ..PuzzlePolicy::default() };
+    let puzzles = Puzzles::new(policy, [7; 32]);
+    let now = Utc::now();
+    let mut request = ChallengeRequest {
+        user: "did:zkp:bench".to_string(),
+        r1: zkp.group.element_to_bytes(&r1),
+        r2: zkp.group.element_to_bytes(&r2),
+        ..Default::default()
+    };
+    let issued = puzzles.issue(&request.user, 0, [1; SEED_LEN], now).unwrap();
+    puzzle::solve_request(&mut request, issued);
+
+    let mut group = c.benchmark_group("puzzle_verify");
+    //a used puzzle goes through every check before it is refused as used
+    group.bench_function("check", |b| b.iter(|| black_box(puzzles.check(&request, 0, now).is_ok())));
+    group.bench_function("exponentiate_pair", |b| b.iter(|| black_box(zkp.exponentiate_pair(&zkp.group.random_scalar()))));
+    group.finish();
+}
+
+criterion_group!(benches, puzzle_verify);
+criterion_main!(benches);
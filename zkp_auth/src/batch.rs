@@ -0,0 +1,183 @@
+//Batch verification of Chaum-Pedersen proofs.
+//Each proof i claims r1_i = alpha^s_i * y1_i^c_i and r2_i = beta^s_i * y2_i^c_i.
+//With random 128-bit weights a_i and b_i all 2N equations are folded into one
+//  prod r1_i^a_i * r2_i^b_i = alpha^(sum a_i s_i) * beta^(sum b_i s_i) * prod y1_i^(a_i c_i) * y2_i^(b_i c_i)
+//and both sides are computed with a multi-exponentiation. If any single equation
+//is false the folded one holds with probability at most 2^-128 over the weights.
+//This is only sound for elements of the order q subgroup, every element has to
+//come from ZKP::decode_element (or be computed by the caller) before it is batched.
+//When the folded check fails the batch is split in halves until the bad proofs are found.
+
+use crate::group::Group;
+use crate::ZKP;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+
+//bytes of every random weight, 128 bits
+const WEIGHT_LEN: usize = 16;
+//below this many proofs the fallback checks them one by one instead of splitting further
+const SPLIT_THRESHOLD: usize = 4;
+
+//one proof to check: the public keys, the commitment, the challenge and the response
+#[derive(Debug)]
+pub struct BatchEntry<G: Group> {
+    pub y1: G::Element,
+    pub y2: G::Element,
+    pub r1: G::Element,
+    pub r2: G::Element,
+    pub c: G::Scalar,
+    pub s: G::Scalar,
+}
+
+//derive(Clone) would ask for G: Clone, only the values have to be
+impl<G: Group> Clone for BatchEntry<G> {
+    fn clone(&self) -> Self {
+        Self {
+            y1: self.y1.clone(),
+            y2: self.y2.clone(),
+            r1: self.r1.clone(),
+            r2: self.r2.clone(),
+            c: self.c.clone(),
+            s: self.s.clone(),
+        }
+    }
+}
+
+impl<G: Group> ZKP<G> {
+    //Ok if every proof verifies, otherwise the indices of the ones that do not, in order
+    pub fn verify_batch(&self, entries: &[BatchEntry<G>]) -> Result<(), Vec<usize>> {
+        self.verify_batch_with(&mut OsRng, entries)
+    }
+
+    pub fn verify_batch_with<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R, entries: &[BatchEntry<G>]) -> Result<(), Vec<usize>> {
+        if self.batch_holds(rng, entries) {
+            return Ok(());
+        }
+        let mut bad = Vec::new();
+        self.find_bad(rng, entries, 0, &mut bad);
+        Err(bad)
+    }
+
+    //the folded equation for all the entries, true for an empty batch
+    fn batch_holds<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R, entries: &[BatchEntry<G>]) -> bool {
+        if entries.is_empty() {
+            return true;
+        }
+        let group = &self.group;
+        let mut weight = || {
+            let mut bytes = [0u8; WEIGHT_LEN];
+            rng.fill_bytes(&mut bytes);
+            group.scalar_from_hash(&bytes)
+        };
+
+        //the exponents are kept here so the terms below can borrow them
+        let mut left = Vec::with_capacity(2 * entries.len());
+        let mut right = Vec::with_capacity(2 * entries.len());
+        let (mut alpha_exponent, mut beta_exponent) = (None, None);
+        for entry in entries {
+            let (a, b) = (weight(), weight());
+            right.push(group.scalar_mul(&a, &entry.c));
+            right.push(group.scalar_mul(&b, &entry.c));
+            let (sa, sb) = (group.scalar_mul(&a, &entry.s), group.scalar_mul(&b, &entry.s));
+            alpha_exponent = Some(match alpha_exponent { Some(sum) => group.scalar_add(&sum, &sa), None => sa });
+            beta_exponent = Some(match beta_exponent { Some(sum) => group.scalar_add(&sum, &sb), None => sb });
+            left.push(a);
+            left.push(b);
+        }
+        let (alpha_exponent, beta_exponent) = (alpha_exponent.unwrap(), beta_exponent.unwrap());
+
+        let commitments: Vec<_> = entries.iter().zip(left.chunks(2))
+            .flat_map(|(entry, w)| [(&entry.r1, &w[0]), (&entry.r2, &w[1])])
+            .collect();
+        let keys: Vec<_> = entries.iter().zip(right.chunks(2))
+            .flat_map(|(entry, e)| [(&entry.y1, &e[0]), (&entry.y2, &e[1])])
+            .collect();
+        //the generators go through exp_alpha and exp_beta, which use the fixed-base tables if the group has them
+        let generators = group.mul(&group.exp_alpha(&alpha_exponent), &group.exp_beta(&beta_exponent));
+
+        group.multi_exp(&commitments) == group.mul(&group.multi_exp(&keys), &generators)
+    }
+
+    //entries failed the folded check as a whole, offset is their index in the full batch
+    fn find_bad<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R, entries: &[BatchEntry<G>], offset: usize, bad: &mut Vec<usize>) {
+        if entries.len() <= SPLIT_THRESHOLD {
+            for (i, e) in entries.iter().enumerate() {
+                if !self.verify_solution(&e.r1, &e.r2, &e.y1, &e.y2, &e.c, &e.s) {
+                    bad.push(offset + i);
+                }
+            }
+            return;
+        }
+        let (first, second) = entries.split_at(entries.len() / 2);
+        let first_holds = self.batch_holds(rng, first);
+        if !first_holds {
+            self.find_bad(rng, first, offset, bad);
+        }
+        //the whole failed, so if the first half holds the bad proofs are all in the second one
+        if first_holds || !self.batch_holds(rng, second) {
+            self.find_bad(rng, second, offset + first.len(), bad);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Ristretto255Group;
+
+    fn entries<G: Group>(zkp: &ZKP<G>, n: usize) -> Vec<BatchEntry<G>> {
+        (0..n).map(|_| {
+            let x = zkp.group.random_scalar();
+            let (y1, y2) = zkp.exponentiate_pair(&x);
+            let k = zkp.group.random_scalar();
+            let (r1, r2) = zkp.exponentiate_pair(&k);
+            let c = zkp.group.random_scalar();
+            let s = zkp.solve(&k, &c, &x);
+            BatchEntry { y1, y2, r1, r2, c, s }
+        }).collect()
+    }
+
+    fn check_batch<G: Group>(zkp: ZKP<G>) {
+        let mut batch = entries(&zkp, 20);
+        assert_eq!(zkp.verify_batch(&batch), Ok(()));
+        assert_eq!(zkp.verify_batch(&[]), Ok(()));
+
+        //a wrong response, a response for another challenge and a swapped commitment
+        batch[3].s = zkp.group.random_scalar();
+        batch[11].c = zkp.group.random_scalar();
+        batch[19].r2 = batch[18].r2.clone();
+        assert_eq!(zkp.verify_batch(&batch), Err(vec![3, 11, 19]));
+        assert_eq!(zkp.verify_batch(&batch[..4]), Err(vec![3]));
+    }
+
+    #[test]
+    fn test_verify_batch_modp() {
+        check_batch(ZKP::new(ZKP::default_group().unwrap()));
+    }
+
+    #[test]
+    fn test_verify_batch_ristretto() {
+        check_batch(ZKP::new(Ristretto255Group::new()));
+    }
+
+    #[test]
+    fn test_batch_catches_errors_that_cancel() {
+        //two proofs whose errors cancel in the unweighted product must still fail
+        let zkp = ZKP::new(ZKP::default_group().unwrap());
+        let mut batch = entries(&zkp, 2);
+        let shift = zkp.group.alpha().clone();
+        batch[0].r1 = zkp.group.mul(&batch[0].r1, &shift);
+        batch[1].r1 = zkp.group.mul(&batch[1].r1, &zkp.group.exp(&shift, &(&zkp.group.q - 1u32)));
+        assert_eq!(zkp.verify_batch(&batch), Err(vec![0, 1]));
+    }
+
+    #[test]
+    fn test_multi_exp_matches_exp() {
+        let group = ZKP::default_group().unwrap();
+        let bases: Vec<_> = (0..5).map(|_| group.exp(group.alpha(), &group.random_scalar())).collect();
+        let exponents: Vec<_> = (0..5).map(|_| group.random_scalar()).collect();
+        let terms: Vec<_> = bases.iter().zip(&exponents).collect();
+        let expected = terms.iter().fold(num_bigint::BigUint::from(1u32), |acc, (b, e)| group.mul(&acc, &group.exp(b, e)));
+        assert_eq!(group.multi_exp(&terms), expected);
+    }
+}
@@ -1,21 +1,19 @@
-mod ssi;
-//use wallet;
-use ssi::issuer::Issuer;
 use base64::{Engine as _, engine::general_purpose};
-use num_bigint::BigUint;
-use tonic::{transport::Server, Code,Request, Response, Status, codegen::http::request};
-use crate::zkp_auth::{auth_client::AuthClient, ChallengeRequest, RegisterRequest, SolutionRequest};
+use tonic::{codegen::InterceptedService, transport::Channel, Request};
+use tracing::Instrument;
+use ::zkp_auth::zkp_proto::{auth_client::AuthClient, GetPuzzleRequest, GetRegistrationRequest, RegisterRequest, SolutionRequest, ValidateSessionRequest, GetJwksRequest};
+use ::zkp_auth::token::{Jwks, TokenVerifier, ISSUER};
 use std::io::stdin;
 use serde::{Deserialize, Serialize};
 
 //importing the zkp functions i made
-use ::zkp_auth::{ ZKP};
+use ::zkp_auth::{context, Challenge, Commitment, Group, Proof, Response, ZKP, ZkpAuthError, RFC5114_1024_GROUP_ID, RISTRETTO255_GROUP_ID};
+use ::zkp_auth::error::Result;
+use ::zkp_auth::{login, params, puzzle, registration, telemetry};
+use ::zkp_auth::telemetry::RequestId;
 
 use ::zkp_auth::ssi::credential::VerifiableCredential;
 
-pub mod zkp_auth{
-    include!("zkp_proto.rs");
-}
 // Struct to read wallet data from file (matches wallet's WalletData)
 
 #[derive(Serialize, Deserialize)]
@@ -23,17 +21,27 @@ pub struct WalletData {
     pub did: String,
     pub credential: VerifiableCredential,
     pub secret: String,
+    #[serde(default = "default_group_id")]
+    pub group: String,
+}
+
+// wallets created before the group was recorded are in the 1024-bit group
+fn default_group_id() -> String {
+    RFC5114_1024_GROUP_ID.to_string()
 }
 
+// every call of this run carries the same request id, the server logs it with each of them
+type Client = AuthClient<InterceptedService<Channel, RequestId>>;
+
 #[tokio::main]
 async fn main(){
+    // RUST_LOG=debug shows each step, ZKP_AUTH_LOG_FORMAT=json writes it as JSON
+    telemetry::init("warn", false);
     let mut buf = String::new();
 
-    //creating the zkp instance
-    let (alpha, beta, p, q) = ZKP::get_zkp_constants();
-    let zkp = ZKP {alpha: alpha.clone(), beta: beta.clone(), p: p.clone(), q: q.clone()};
-
-    let mut client = AuthClient::connect("http://127.0.0.1:50051").await.expect("could not connect to server");
+    let channel = Channel::from_static("http://127.0.0.1:50051").connect().await.expect("could not connect to server");
+    let request_id = RequestId::new();
+    let mut client = AuthClient::with_interceptor(channel, request_id.clone());
     println!("✓ Connected to the server");
 
     println!("\n🔐 SELF-SOVEREIGN IDENTITY AUTHENTICATION CLIENT");
@@ -96,51 +104,121 @@ async fn main(){
         println!("Login cancelled");
         return;
     }
-    
-    // Parse stored credential params from wallet data
-    let y1_bytes = general_purpose::STANDARD.decode(&wallet_data.credential.proof.y1).unwrap();
-    let y2_bytes = general_purpose::STANDARD.decode(&wallet_data.credential.proof.y2).unwrap();
-    let y1 = BigUint::from_bytes_be(&y1_bytes);
-    let y2 = BigUint::from_bytes_be(&y2_bytes);
+
+    //the shared zkp instance of the group the wallet keys live in
+    let span = tracing::info_span!("login", did = %telemetry::did(&did), group = %wallet_data.group, request_id = %request_id.as_str());
+    let result = async { if wallet_data.group == RISTRETTO255_GROUP_ID {
+        authenticate(&mut client, context::ristretto255(), &did, &wallet_data).await
+    } else {
+        match context::modp(&wallet_data.group) {
+            Ok(zkp) => authenticate(&mut client, zkp, &did, &wallet_data).await,
+            Err(ZkpAuthError::NotFound(_)) => Err(ZkpAuthError::NotFound(format!("group {} in the wallet file", wallet_data.group))),
+            Err(e) => Err(e),
+        }
+    } }.instrument(span).await;
+    if let Err(e) = result {
+        tracing::debug!(error = %e, "login failed");
+        eprintln!("❌ SSI Login failed: {}", e);
+    }
+}
+
+// enrolls the DID the first time, then logs in, the same flow works for every group
+async fn authenticate<G: Group>(client: &mut Client, zkp: &ZKP<G>, did: &str, wallet_data: &WalletData) -> Result<()> {
+    let mut buf = String::new();
     
     // Parse secret from wallet data
-    let secret = wallet_data.secret.parse::<BigUint>().unwrap();
+    let secret_bytes = general_purpose::STANDARD.decode(&wallet_data.secret)?;
+    // a wallet made before beta was hashed into the subgroup can not log in any more
+    let proof = &wallet_data.credential.proof;
+    params::check_current_keys(&wallet_data.group, did, &general_purpose::STANDARD.decode(&proof.y1)?, &general_purpose::STANDARD.decode(&proof.y2)?)?;
+    let secret = zkp.group.scalar_from_bytes(&secret_bytes)
+        .map_err(|e| ZkpAuthError::invalid("the wallet secret", e))?;
+    let (y1, y2) = zkp.exponentiate_pair(&secret);
+    let keys = (zkp.group.element_to_bytes(&y1), zkp.group.element_to_bytes(&y2));
+    wallet_data.credential.check_holder(did, &keys.0, &keys.1)
+        .map_err(|e| ZkpAuthError::Crypto(format!("the wallet secret does not fit its credential, {}", e.reason())))?;
     
-    // === REGISTER WITH SERVER FIRST ===
-    println!("\n📤 Registering DID with server...");
-    let register_request = Request::new(RegisterRequest {
-        user: did.clone(),
-        y1: y1.to_bytes_be(),
-        y2: y2.to_bytes_be(),
-    });
+    // === ENROLL ONCE ===
+    // a returning wallet is known to the server already and goes straight to login
+    tracing::debug!("looking up the enrollment");
+    let request = Request::new(GetRegistrationRequest { did: did.to_string(), session_id: String::new() });
+    match client.get_registration(request).await {
+        Ok(response) => {
+            let registration = response.into_inner().registration.unwrap_or_default();
+            if (registration.y1, registration.y2) != keys {
+                return Err(ZkpAuthError::Crypto(format!("{} is enrolled with other keys than the ones in this wallet", did)));
+            }
+            match chrono::DateTime::from_timestamp(registration.enrolled_at, 0) {
+                Some(enrolled_at) if registration.enrolled_at > 0 => println!("✓ DID enrolled with the server since {}", enrolled_at.to_rfc3339()),
+                _ => println!("✓ DID enrolled with the server"),
+            }
+        },
+        Err(status) if status.code() == tonic::Code::NotFound => enroll(client, zkp, did, &secret, wallet_data).await?,
+        Err(status) => return Err(status.into()),
+    }
     
-    match client.register(register_request).await {
-        Ok(_) => println!("✓ DID registered successfully with server"),
-        Err(e) => {
-            println!("❌ Registration failed: {}", e.message());
-            return;
+    println!("\nChoose a login mode:");
+    println!("1. Interactive (challenge from the server)");
+    println!("2. Non-interactive (one round trip)");
+    stdin().read_line(&mut buf).expect("could not read login mode");
+    let mode = buf.trim().to_string();
+    buf.clear();
+
+    if mode == "2" {
+        println!("\n→ Generating non-interactive zero-knowledge proof with your DID...");
+
+        //the context binds the proof to this DID and this login, the server takes it once and only for a short while
+        let context = login::new_login_context(did, chrono::Utc::now());
+        let (r1, r2, s) = zkp.prove_non_interactive(&secret, &context);
+        let proof = Proof::new(zkp, &r1, &r2, &s);
+
+        let request = Request::new(proof.to_login_request(did.to_string(), context, wallet_data.group.clone()));
+        tracing::debug!("sending a non-interactive proof");
+
+        match client.login(request).await {
+            Ok(response) => {
+                println!("\n✅ Logged in successfully with Self-Sovereign Identity!");
+                println!("🆔 DID: {}", did);
+                println!("🔑 Zero-knowledge proof verified!");
+                let response = response.into_inner();
+                println!("Session: {}", response.session_id);
+                show_session(client, response.session_id).await?;
+                show_token(client, &response.token).await?;
+            },
+            Err(e) => {
+                eprintln!("❌ SSI Login failed: {}", e.message());
+            }
         }
+        return Ok(());
     }
-    
+
     println!("\n→ Generating zero-knowledge proof with your DID...");
     
     // Generate authentication data (replaces wallet.generate_auth_data)
-    let k = ZKP::generate_random_number_less_than(&q);
-    let r1 = ZKP::exponentiate(&alpha, &k, &p);
-    let r2 = ZKP::exponentiate(&beta, &k, &p);
+    // k is derived from the secret and this login, not only from the RNG
+    let context = format!("challenge:{}:{}", did, chrono::Utc::now().timestamp()).into_bytes();
+    let (r1, r2, k) = zkp.commit(&secret, &y1, &y2, &context);
     
     // Request challenge using DID instead of username
-    let request = Request::new(ChallengeRequest{
-        user: did.clone(),  // Use DID for authentication
-        r1: r1.to_bytes_be(),
-        r2: r2.to_bytes_be(),
-    });
+    let commitment = Commitment::new(zkp, &r1, &r2);
+    let mut request = commitment.to_challenge_request(did.to_string(), wallet_data.group.clone());
+
+    // a busy server wants a puzzle solved for this commitment first
+    let puzzle = client.get_puzzle(Request::new(GetPuzzleRequest { did: did.to_string() })).await?.into_inner().puzzle;
+    if let Some(puzzle) = puzzle {
+        println!("🧩 The server is busy, solving a puzzle of {} bits...", puzzle.difficulty);
+        let started = std::time::Instant::now();
+        let difficulty = puzzle.difficulty;
+        puzzle::solve_request(&mut request, puzzle);
+        tracing::debug!(difficulty, elapsed_ms = started.elapsed().as_millis() as u64, "puzzle solved");
+        println!("✓ Puzzle solved in {:?}", started.elapsed());
+    }
     
-    let response = client.create_challenge(request).await
-        .expect("could not request challenge").into_inner();
+    let response = client.create_challenge(Request::new(request)).await?.into_inner();
         
+    let c = Challenge::from(&response).decode(zkp)?;
     let auth_id = response.auth_id;
-    let c = BigUint::from_bytes_be(&response.c);
+    tracing::debug!(login = %telemetry::auth_id(&auth_id), "challenge received, answering it");
     
     // Generate proof (replaces wallet.generate_proof)
     let s = zkp.solve(&k, &c, &secret);
@@ -148,7 +226,7 @@ async fn main(){
     // create a request to send the proof
     let request = Request::new(SolutionRequest {
         auth_id,
-        s: s.to_bytes_be(),
+        s: Response::new(zkp, &s).s().to_vec(),
     });
     
     match client.verify_authentication(request).await {
@@ -158,9 +236,50 @@ async fn main(){
             println!("🆔 DID: {}", did);
             println!("🔑 Zero-knowledge proof verified!");
             println!("Session: {}", response_data.session_id);
+            show_session(client, response_data.session_id).await?;
+            show_token(client, &response_data.token).await?;
         },
         Err(e) => {
             eprintln!("❌ SSI Login failed: {}", e.message());
         }
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+// the first time a wallet meets the server: its keys, a proof that it holds the secret and its credential
+async fn enroll<G: Group>(client: &mut Client, zkp: &ZKP<G>, did: &str, secret: &G::Scalar, wallet_data: &WalletData) -> Result<()> {
+    println!("\n📤 Enrolling DID with server...");
+    tracing::debug!("enrolling");
+    let request = RegisterRequest {
+        credential: serde_json::to_string(&wallet_data.credential)?,
+        ..registration::register_request(zkp, did, secret, wallet_data.group.clone())
+    };
+    match client.register(Request::new(request)).await {
+        Ok(_) => println!("✓ DID enrolled successfully with server"),
+        // another client of the same wallet was quicker
+        Err(status) if status.code() == tonic::Code::AlreadyExists => println!("✓ DID enrolled with the server"),
+        Err(status) => return Err(status.into()),
+    }
+    Ok(())
+}
+
+// asks the server how long the new session lasts
+async fn show_session(client: &mut Client, session_id: String) -> Result<()> {
+    let response = client.validate_session(Request::new(ValidateSessionRequest { session_id })).await?.into_inner();
+    if let Some(session) = response.session
+        && let Some(expires_at) = chrono::DateTime::from_timestamp(session.expires_at, 0)
+    {
+        println!("⏰ Session valid until {} unless refreshed", expires_at.to_rfc3339());
+    }
+    Ok(())
+}
+
+// checks the token the way a service would, with nothing but the published keys
+async fn show_token(client: &mut Client, token: &str) -> Result<()> {
+    let jwks = Jwks::from(&client.get_jwks(Request::new(GetJwksRequest {})).await?.into_inner());
+    let claims = TokenVerifier::new(ISSUER, &jwks).verify(token, chrono::Utc::now())?;
+    if let Some(expires_at) = chrono::DateTime::from_timestamp(claims.exp, 0) {
+        println!("🎫 Token for {} verified offline, valid until {} (scope: {})", claims.sub, expires_at.to_rfc3339(), claims.scope.join(" "));
+    }
+    Ok(())
+}
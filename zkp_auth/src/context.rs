@@ -0,0 +1,69 @@
+//The groups a process works in, built once and shared by everything in it.
+//Building a MODP group decodes the hex parameters, hashes beta into the
+//subgroup and precomputes the fixed-base tables for alpha and beta, which
+//costs far more than a proof. Here that happens the first time a set is asked
+//for and every later caller (the server's handlers, the client, the wallet)
+//gets the same &'static ZKP.
+
+use crate::error::{Result, ZkpAuthError};
+use crate::params::PARAMETER_SETS;
+use crate::{ModpGroup, Ristretto255Group, ZKP};
+use std::sync::OnceLock;
+
+static MODP: [OnceLock<ZKP<ModpGroup>>; PARAMETER_SETS.len()] = [const { OnceLock::new() }; PARAMETER_SETS.len()];
+static RISTRETTO255: OnceLock<ZKP<Ristretto255Group>> = OnceLock::new();
+
+//the named MODP parameter set, with fixed-base tables
+pub fn modp(id: &str) -> Result<&'static ZKP<ModpGroup>> {
+    let index = PARAMETER_SETS.iter().position(|set| set.id == id)
+        .ok_or_else(|| ZkpAuthError::NotFound(format!("parameter set {}", id)))?;
+    if let Some(zkp) = MODP[index].get() {
+        return Ok(zkp);
+    }
+    //two threads may both build it, only one is kept
+    let zkp = ZKP::new(PARAMETER_SETS[index].group()?.with_fixed_base_tables());
+    Ok(MODP[index].get_or_init(|| zkp))
+}
+
+pub fn ristretto255() -> &'static ZKP<Ristretto255Group> {
+    RISTRETTO255.get_or_init(|| ZKP::new(Ristretto255Group::new()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parameter_set, Group, RFC5114_1024_GROUP_ID};
+
+    #[test]
+    fn test_context_is_shared() {
+        let zkp = modp(RFC5114_1024_GROUP_ID).unwrap();
+        assert!(std::ptr::eq(zkp, modp(RFC5114_1024_GROUP_ID).unwrap()));
+        assert!(zkp.group.has_fixed_base_tables());
+        assert!(matches!(modp("rfc0000"), Err(ZkpAuthError::NotFound(_))));
+
+        //the tables give the same powers as modpow
+        let plain = parameter_set(RFC5114_1024_GROUP_ID).unwrap().group().unwrap();
+        for _ in 0..20 {
+            let e = plain.random_scalar();
+            assert_eq!(zkp.exponentiate_pair(&e), (plain.exp(plain.alpha(), &e), plain.exp(plain.beta(), &e)));
+        }
+        let top = &plain.q - 1u32;
+        assert_eq!(zkp.group.exp_alpha(&top), plain.exp(plain.alpha(), &top));
+
+        let ristretto = ristretto255();
+        let e = ristretto.group.random_scalar();
+        assert_eq!(ristretto.exponentiate_pair(&e), (ristretto.group.alpha * e, ristretto.group.beta * e));
+    }
+
+    #[test]
+    fn test_tables_follow_the_generators() {
+        //a group whose generators were changed after the tables were made must not use them
+        let mut group = ZKP::default_group().unwrap().with_fixed_base_tables();
+        group.alpha = group.exp(&group.alpha, &2u32.into());
+        let e = group.random_scalar();
+        assert_eq!(group.exp_alpha(&e), group.exp(&group.alpha, &e));
+        //an exponent wider than the tables falls back as well
+        let wide = &group.p - 1u32;
+        assert_eq!(group.exp_beta(&wide), group.exp(&group.beta, &wide));
+    }
+}
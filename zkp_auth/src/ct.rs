@@ -0,0 +1,304 @@
+//Constant-time arithmetic mod q for the secret side of the protocol.
+//num_bigint trims leading zero limbs and branches on the values it works on,
+//so s = k - c * x mod q computed with it leaks timing about x and k.
+//Here every scalar has the same number of limbs as q and add, sub and mul
+//run the same instructions whatever the values are: carries are propagated
+//with wide arithmetic and the final reductions are masked selects.
+//The modulus itself is public, only the operands are treated as secret.
+
+use num_bigint::BigUint;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+
+//an odd modulus prepared for Montgomery multiplication
+#[derive(Debug, Clone)]
+pub struct CtModulus {
+    m: Vec<u64>,
+    //-m^-1 mod 2^64
+    m_inv: u64,
+    //R^2 mod m where R = 2^(64 * limbs)
+    r2: Vec<u64>,
+}
+
+//a value less than the modulus it was made with, always the full width of the modulus
+#[derive(Clone)]
+pub struct CtScalar {
+    limbs: Vec<u64>,
+}
+
+//the limbs are secret, so they are never printed
+impl std::fmt::Debug for CtScalar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CtScalar({} limbs)", self.limbs.len())
+    }
+}
+
+impl ConstantTimeEq for CtScalar {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.limbs.as_slice().ct_eq(other.limbs.as_slice())
+    }
+}
+
+//a + b * c + carry, returns (low, high)
+#[inline(always)]
+fn mac(a: u64, b: u64, c: u64, carry: u64) -> (u64, u64) {
+    let t = (a as u128) + (b as u128) * (c as u128) + (carry as u128);
+    (t as u64, (t >> 64) as u64)
+}
+
+//a + b + carry, returns (sum, carry)
+#[inline(always)]
+fn adc(a: u64, b: u64, carry: u64) -> (u64, u64) {
+    let t = (a as u128) + (b as u128) + (carry as u128);
+    (t as u64, (t >> 64) as u64)
+}
+
+//a - b - borrow, returns (difference, borrow) with borrow 0 or 1
+#[inline(always)]
+fn sbb(a: u64, b: u64, borrow: u64) -> (u64, u64) {
+    let t = (a as u128).wrapping_sub((b as u128) + (borrow as u128));
+    (t as u64, (t >> 127) as u64)
+}
+
+impl CtModulus {
+    pub fn new(m: &BigUint) -> Self {
+        assert!(m.bit(0), "the modulus must be odd");
+        let m_limbs = m.to_u64_digits();
+        let n = m_limbs.len();
+
+        //Newton iteration doubles the correct low bits every step, 6 steps give 64 bits
+        let mut inv: u64 = 1;
+        for _ in 0..6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(m_limbs[0].wrapping_mul(inv)));
+        }
+
+        let r2 = (BigUint::from(1u32) << (128 * n)) % m;
+        Self {
+            r2: pad(&r2, n),
+            m: m_limbs,
+            m_inv: inv.wrapping_neg(),
+        }
+    }
+
+    pub fn limbs(&self) -> usize {
+        self.m.len()
+    }
+
+    //true if this was made from m
+    pub fn is_modulus(&self, m: &BigUint) -> bool {
+        self.m.iter().copied().eq(m.iter_u64_digits())
+    }
+
+    //x is reduced first if it is not less than the modulus, the arithmetic here only holds values below it
+    //the check subtracts over every limb, for a value in range it runs the same whatever the value is
+    pub fn scalar(&self, x: &BigUint) -> CtScalar {
+        if x.bits() <= 64 * self.limbs() as u64 {
+            let limbs = pad(x, self.limbs());
+            let mut borrow = 0;
+            for (l, m) in limbs.iter().zip(&self.m) {
+                (_, borrow) = sbb(*l, *m, borrow);
+            }
+            if borrow == 1 {
+                return CtScalar { limbs };
+            }
+        }
+        CtScalar { limbs: pad(&(x % self.to_biguint_modulus()), self.limbs()) }
+    }
+
+    fn to_biguint_modulus(&self) -> BigUint {
+        BigUint::from_slice(&u64s_to_u32s(&self.m))
+    }
+
+    //value = high * 2^(64 * limbs) + low with value < 2m, returns value mod m
+    fn reduce_once(&self, low: &[u64], high: u64) -> Vec<u64> {
+        let mut diff = vec![0u64; self.limbs()];
+        let mut borrow = 0;
+        for i in 0..self.limbs() {
+            (diff[i], borrow) = sbb(low[i], self.m[i], borrow);
+        }
+        //value - m went below zero only if there was no high limb to borrow from
+        let underflow = Choice::from((borrow & (high ^ 1)) as u8);
+        low.iter().zip(diff.iter())
+            .map(|(l, d)| u64::conditional_select(d, l, underflow))
+            .collect()
+    }
+
+    pub fn add(&self, a: &CtScalar, b: &CtScalar) -> CtScalar {
+        let mut sum = vec![0u64; self.limbs()];
+        let mut carry = 0;
+        for ((s, a), b) in sum.iter_mut().zip(&a.limbs).zip(&b.limbs) {
+            (*s, carry) = adc(*a, *b, carry);
+        }
+        CtScalar { limbs: self.reduce_once(&sum, carry) }
+    }
+
+    pub fn sub(&self, a: &CtScalar, b: &CtScalar) -> CtScalar {
+        let mut diff = vec![0u64; self.limbs()];
+        let mut borrow = 0;
+        for ((d, a), b) in diff.iter_mut().zip(&a.limbs).zip(&b.limbs) {
+            (*d, borrow) = sbb(*a, *b, borrow);
+        }
+        //add m back when a < b, adding the masked modulus keeps it branch free
+        let mask = borrow.wrapping_neg();
+        let mut carry = 0;
+        for (d, m) in diff.iter_mut().zip(&self.m) {
+            (*d, carry) = adc(*d, m & mask, carry);
+        }
+        CtScalar { limbs: diff }
+    }
+
+    //a * b * R^-1 mod m, CIOS Montgomery multiplication
+    fn mont_mul(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+        let n = self.limbs();
+        let mut t = vec![0u64; n + 2];
+        for &a_i in a.iter().take(n) {
+            let mut carry = 0;
+            for j in 0..n {
+                (t[j], carry) = mac(t[j], a_i, b[j], carry);
+            }
+            let (sum, high) = adc(t[n], carry, 0);
+            t[n] = sum;
+            t[n + 1] = high;
+
+            //adding u * m makes the lowest limb zero so the shift by one limb is exact
+            let u = t[0].wrapping_mul(self.m_inv);
+            let (_, mut carry) = mac(t[0], u, self.m[0], 0);
+            for j in 1..n {
+                (t[j - 1], carry) = mac(t[j], u, self.m[j], carry);
+            }
+            let (sum, high) = adc(t[n], carry, 0);
+            t[n - 1] = sum;
+            t[n] = t[n + 1] + high;
+        }
+        self.reduce_once(&t[..n], t[n])
+    }
+
+    pub fn mul(&self, a: &CtScalar, b: &CtScalar) -> CtScalar {
+        //(a * b * R^-1) * R^2 * R^-1 = a * b
+        let ab = self.mont_mul(&a.limbs, &b.limbs);
+        CtScalar { limbs: self.mont_mul(&ab, &self.r2) }
+    }
+
+    //a * R mod m, values in this form multiply with montgomery_mul and skip the conversion
+    //back until the end of a chain of products, e.g. an exponentiation
+    pub fn to_montgomery(&self, a: &CtScalar) -> CtScalar {
+        CtScalar { limbs: self.mont_mul(&a.limbs, &self.r2) }
+    }
+
+    pub fn from_montgomery(&self, a: &CtScalar) -> CtScalar {
+        let mut one = vec![0u64; self.limbs()];
+        one[0] = 1;
+        CtScalar { limbs: self.mont_mul(&a.limbs, &one) }
+    }
+
+    pub fn montgomery_mul(&self, a: &CtScalar, b: &CtScalar) -> CtScalar {
+        CtScalar { limbs: self.mont_mul(&a.limbs, &b.limbs) }
+    }
+}
+
+impl CtScalar {
+    pub fn to_biguint(&self) -> BigUint {
+        BigUint::from_slice(&u64s_to_u32s(&self.limbs))
+    }
+}
+
+//row[index] without the memory accessed depending on index, every entry is read
+//used for table lookups with digits of a secret exponent
+pub fn ct_select(row: &[CtScalar], index: usize) -> CtScalar {
+    let mut limbs = vec![0u64; row[0].limbs.len()];
+    for (i, entry) in row.iter().enumerate() {
+        let hit = (i as u64).ct_eq(&(index as u64));
+        for (out, limb) in limbs.iter_mut().zip(&entry.limbs) {
+            *out = u64::conditional_select(out, limb, hit);
+        }
+    }
+    CtScalar { limbs }
+}
+
+//the little-endian limbs of x, zero padded to n limbs
+//x must fit, cutting limbs off would silently change its value
+fn pad(x: &BigUint, n: usize) -> Vec<u64> {
+    let mut limbs = x.to_u64_digits();
+    assert!(limbs.len() <= n, "a value of {} limbs does not fit in {}", limbs.len(), n);
+    limbs.resize(n, 0);
+    limbs
+}
+
+fn u64s_to_u32s(limbs: &[u64]) -> Vec<u32> {
+    limbs.iter().flat_map(|l| [*l as u32, (*l >> 32) as u32]).collect()
+}
+
+//equality of two byte strings without an early exit on the first difference
+pub fn ct_eq_bytes(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use num_bigint::RandBigInt;
+
+    fn check_against_biguint(m: &BigUint) {
+        let modulus = CtModulus::new(m);
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            let a = rng.gen_biguint_below(m);
+            let b = rng.gen_biguint_below(m);
+            let (ca, cb) = (modulus.scalar(&a), modulus.scalar(&b));
+
+            assert_eq!(modulus.add(&ca, &cb).to_biguint(), (&a + &b) % m);
+            assert_eq!(modulus.sub(&ca, &cb).to_biguint(), (&a + m - &b) % m);
+            assert_eq!(modulus.mul(&ca, &cb).to_biguint(), (&a * &b) % m);
+
+            let (ma, mb) = (modulus.to_montgomery(&ca), modulus.to_montgomery(&cb));
+            assert_eq!(modulus.from_montgomery(&modulus.montgomery_mul(&ma, &mb)).to_biguint(), (&a * &b) % m);
+        }
+    }
+
+    #[test]
+    fn test_ct_arithmetic() {
+        check_against_biguint(&BigUint::from(11u32));
+        check_against_biguint(&BigUint::from(u64::MAX - 58)); //largest 64-bit prime
+        for set in crate::params::PARAMETER_SETS {
+            check_against_biguint(&set.group().unwrap().q);
+        }
+    }
+
+    #[test]
+    fn test_ct_edge_cases() {
+        let m = BigUint::from(11u32);
+        let modulus = CtModulus::new(&m);
+        let zero = modulus.scalar(&BigUint::from(0u32));
+        let top = modulus.scalar(&BigUint::from(10u32));
+
+        assert_eq!(modulus.sub(&zero, &top).to_biguint(), BigUint::from(1u32));
+        assert_eq!(modulus.add(&top, &top).to_biguint(), BigUint::from(9u32));
+        assert_eq!(modulus.mul(&top, &top).to_biguint(), BigUint::from(1u32));
+        assert_eq!(modulus.mul(&zero, &top).to_biguint(), BigUint::from(0u32));
+
+        assert!(bool::from(top.ct_eq(&modulus.scalar(&BigUint::from(10u32)))));
+        assert!(!bool::from(top.ct_eq(&zero)));
+        let row = [zero.clone(), top.clone(), modulus.scalar(&BigUint::from(3u32))];
+        assert_eq!(ct_select(&row, 1).to_biguint(), BigUint::from(10u32));
+        assert_eq!(ct_select(&row, 2).to_biguint(), BigUint::from(3u32));
+        assert!(ct_eq_bytes(b"abc", b"abc"));
+        assert!(!ct_eq_bytes(b"abc", b"abd"));
+    }
+
+    #[test]
+    fn test_scalars_are_reduced() {
+        let m = BigUint::from(u64::MAX - 58);
+        let modulus = CtModulus::new(&m);
+        //the modulus itself, a value just above it and one wider than the limbs
+        for x in [m.clone(), &m + 3u32, (&m << 130) + 7u32, BigUint::from(u64::MAX)] {
+            assert_eq!(modulus.scalar(&x).to_biguint(), &x % &m, "{}", x);
+        }
+        let three = modulus.scalar(&(&m + 3u32));
+        assert_eq!(modulus.add(&three, &three).to_biguint(), BigUint::from(6u32));
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn test_pad_refuses_wider_values() {
+        pad(&(BigUint::from(1u32) << 64), 1);
+    }
+}
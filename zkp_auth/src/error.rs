@@ -0,0 +1,162 @@
+//The error type of the library, the wallet and the server.
+//Every variant maps onto one gRPC status code so the server can use `?`
+//inside its handlers and the client sees the same reason the server logged.
+
+use crate::validation::ValidationError;
+use thiserror::Error;
+use tonic::{Code, Status};
+
+#[derive(Debug, Clone, Error)]
+pub enum ZkpAuthError {
+    //a proof did not verify or a secret is not usable
+    #[error("crypto error: {0}")]
+    Crypto(String),
+
+    //bytes, hex, base64 or json that do not decode to a valid value
+    #[error("encoding error: {0}")]
+    Encoding(String),
+
+    //files, locks and anything else the state is kept in
+    #[error("storage error: {0}")]
+    Storage(String),
+
+    //a request that is well formed but not allowed at this point
+    #[error("protocol error: {0}")]
+    Protocol(String),
+
+    #[error("not found: {0}")]
+    NotFound(String),
+    //something that can only be created once, e.g. the enrollment of a DID
+    #[error("already exists: {0}")]
+    AlreadyExists(String),
+
+    //a challenge or session that was valid but ran out of time
+    #[error("expired: {0}")]
+    Expired(String),
+
+    //the server has more work queued than it takes, the request can be sent again later
+    #[error("overloaded: {0}")]
+    Overloaded(String),
+}
+
+pub type Result<T> = std::result::Result<T, ZkpAuthError>;
+
+impl ZkpAuthError {
+    //a value received from the other party that failed validation, e.g. "y1 is zero"
+    pub fn invalid(name: &str, error: ValidationError) -> Self {
+        ZkpAuthError::Encoding(format!("{} {}", name, error))
+    }
+
+    //the reason without the variant prefix, this is what goes into a Status
+    pub fn reason(&self) -> &str {
+        match self {
+            ZkpAuthError::Crypto(reason)
+            | ZkpAuthError::Encoding(reason)
+            | ZkpAuthError::Storage(reason)
+            | ZkpAuthError::Protocol(reason)
+            | ZkpAuthError::NotFound(reason)
+            | ZkpAuthError::AlreadyExists(reason)
+            | ZkpAuthError::Expired(reason)
+            | ZkpAuthError::Overloaded(reason) => reason,
+        }
+    }
+
+    pub fn code(&self) -> Code {
+        match self {
+            ZkpAuthError::Crypto(_) => Code::PermissionDenied,
+            ZkpAuthError::Encoding(_) => Code::InvalidArgument,
+            ZkpAuthError::Storage(_) => Code::Internal,
+            ZkpAuthError::Protocol(_) => Code::FailedPrecondition,
+            ZkpAuthError::NotFound(_) => Code::NotFound,
+            ZkpAuthError::AlreadyExists(_) => Code::AlreadyExists,
+            ZkpAuthError::Expired(_) => Code::DeadlineExceeded,
+            ZkpAuthError::Overloaded(_) => Code::ResourceExhausted,
+        }
+    }
+}
+
+impl From<ZkpAuthError> for Status {
+    fn from(error: ZkpAuthError) -> Self {
+        Status::new(error.code(), error.reason())
+    }
+}
+
+//the other direction, for the client: the server's reason comes back as the same variant
+impl From<Status> for ZkpAuthError {
+    fn from(status: Status) -> Self {
+        let reason = status.message().to_string();
+        match status.code() {
+            Code::PermissionDenied | Code::Unauthenticated => ZkpAuthError::Crypto(reason),
+            Code::InvalidArgument => ZkpAuthError::Encoding(reason),
+            Code::Internal | Code::DataLoss => ZkpAuthError::Storage(reason),
+            Code::NotFound => ZkpAuthError::NotFound(reason),
+            Code::AlreadyExists => ZkpAuthError::AlreadyExists(reason),
+            Code::DeadlineExceeded => ZkpAuthError::Expired(reason),
+            Code::ResourceExhausted => ZkpAuthError::Overloaded(reason),
+            _ => ZkpAuthError::Protocol(reason),
+        }
+    }
+}
+
+impl From<std::io::Error> for ZkpAuthError {
+    fn from(error: std::io::Error) -> Self {
+        ZkpAuthError::Storage(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ZkpAuthError {
+    fn from(error: serde_json::Error) -> Self {
+        ZkpAuthError::Encoding(error.to_string())
+    }
+}
+
+impl From<base64::DecodeError> for ZkpAuthError {
+    fn from(error: base64::DecodeError) -> Self {
+        ZkpAuthError::Encoding(error.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for ZkpAuthError {
+    fn from(error: rusqlite::Error) -> Self {
+        ZkpAuthError::Storage(error.to_string())
+    }
+}
+
+impl From<hex::FromHexError> for ZkpAuthError {
+    fn from(error: hex::FromHexError) -> Self {
+        ZkpAuthError::Encoding(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_mapping() {
+        let status = Status::from(ZkpAuthError::invalid("y1", ValidationError::Zero));
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "y1 is zero");
+
+        let status = Status::from(ZkpAuthError::NotFound("DID: did:zkp:x not found in database".to_string()));
+        assert_eq!(status.code(), Code::NotFound);
+
+        let error = ZkpAuthError::from(Status::new(Code::PermissionDenied, "Identity verification failed"));
+        assert!(matches!(error, ZkpAuthError::Crypto(ref reason) if reason == "Identity verification failed"));
+
+        let error = ZkpAuthError::from(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "read only"));
+        assert_eq!(error.code(), Code::Internal);
+        assert_eq!(error.to_string(), "storage error: read only");
+
+        let error = ZkpAuthError::from(Status::from(ZkpAuthError::Overloaded("try again later".to_string())));
+        assert!(matches!(error, ZkpAuthError::Overloaded(ref reason) if reason == "try again later"));
+
+        let status = Status::from(ZkpAuthError::Expired("AuthId: abc expired".to_string()));
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert!(matches!(ZkpAuthError::from(status), ZkpAuthError::Expired(_)));
+
+        let status = Status::from(ZkpAuthError::AlreadyExists("DID: did:zkp:x is already enrolled".to_string()));
+        assert_eq!(status.code(), Code::AlreadyExists);
+        assert!(matches!(ZkpAuthError::from(status), ZkpAuthError::AlreadyExists(_)));
+    }
+}
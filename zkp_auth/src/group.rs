@@ -0,0 +1,571 @@
+//The group the Chaum-Pedersen protocol runs in.
+//ZKP is generic over this trait so the same prover/verifier code works
+//for the multiplicative group mod p and for any other prime order group.
+
+use crate::ct::{ct_eq_bytes, ct_select, CtModulus, CtScalar};
+use crate::validation::{is_probable_prime, ValidationError};
+use num_bigint::{BigUint, RandBigInt};
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+use std::borrow::Cow;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+//rounds of Miller-Rabin used when checking the parameters at start-up
+//the sets are published constants so a few rounds are enough, and 4096-bit rounds are slow
+const PRIMALITY_ROUNDS: usize = 8;
+
+//bits of every exponent handled per step of ModpGroup::multi_exp, a table of 2^4 powers per base
+const MULTI_EXP_WINDOW: u64 = 4;
+//bits of the exponent per row of a fixed-base table, rows hold 2^4 powers each
+const FIXED_BASE_WINDOW: u64 = 4;
+
+pub trait Group: Send + Sync + 'static {
+    //an element of the order q subgroup (y1, y2, r1, r2)
+    type Element: Clone + PartialEq + Debug + Send + Sync;
+    //an integer mod q (x, k, c, s)
+    type Scalar: Clone + PartialEq + Debug + Send + Sync;
+
+    //the two generators of the order q subgroup
+    fn alpha(&self) -> &Self::Element;
+    fn beta(&self) -> &Self::Element;
+
+    //the group operation and its repeated application
+    fn mul(&self, a: &Self::Element, b: &Self::Element) -> Self::Element;
+    fn exp(&self, base: &Self::Element, exponent: &Self::Scalar) -> Self::Element;
+
+    //alpha^e and beta^e, the exponentiations every proof and every check does
+    //groups with precomputed tables for the two generators override these
+    fn exp_alpha(&self, exponent: &Self::Scalar) -> Self::Element {
+        self.exp(self.alpha(), exponent)
+    }
+
+    fn exp_beta(&self, exponent: &Self::Scalar) -> Self::Element {
+        self.exp(self.beta(), exponent)
+    }
+
+    //the product of base^exponent over all terms, for public values only
+    //groups override this with a multi-exponentiation that shares the work between the terms
+    fn multi_exp(&self, terms: &[(&Self::Element, &Self::Scalar)]) -> Self::Element {
+        let mut terms = terms.iter().map(|(base, exponent)| self.exp(base, exponent));
+        let first = terms.next().expect("multi_exp needs at least one term");
+        terms.fold(first, |acc, e| self.mul(&acc, &e))
+    }
+
+    //Ok if e is a non trivial element of the order q subgroup
+    fn validate_element(&self, e: &Self::Element) -> Result<(), ValidationError>;
+
+    fn is_valid_element(&self, e: &Self::Element) -> bool {
+        self.validate_element(e).is_ok()
+    }
+
+    //checks the group itself is sound: the order is prime and both generators have that order
+    fn validate_parameters(&self) -> Result<(), ValidationError>;
+
+    //the canonical encoding, always element_len() bytes
+    fn element_to_bytes(&self, e: &Self::Element) -> Vec<u8>;
+    fn element_len(&self) -> usize;
+
+    //equality that takes the same time wherever the two elements differ
+    fn ct_eq(&self, a: &Self::Element, b: &Self::Element) -> bool {
+        ct_eq_bytes(&self.element_to_bytes(a), &self.element_to_bytes(b))
+    }
+
+    //only parses and range checks, validate_element does the subgroup check
+    //anything but the canonical encoding is rejected
+    fn element_from_bytes(&self, bytes: &[u8]) -> Result<Self::Element, ValidationError>;
+
+    //arithmetic mod q
+    fn scalar_add(&self, a: &Self::Scalar, b: &Self::Scalar) -> Self::Scalar;
+    fn scalar_sub(&self, a: &Self::Scalar, b: &Self::Scalar) -> Self::Scalar;
+    fn scalar_mul(&self, a: &Self::Scalar, b: &Self::Scalar) -> Self::Scalar;
+    //a^-1 mod q, None for zero
+    //only used on public values (see ZKP::extract_witness) so it need not be constant time
+    fn scalar_invert(&self, a: &Self::Scalar) -> Option<Self::Scalar>;
+
+    //s = k - c * x mod q, x and k are secret so this must run in constant time
+    fn solve(&self, k: &Self::Scalar, c: &Self::Scalar, x: &Self::Scalar) -> Self::Scalar {
+        self.scalar_sub(k, &self.scalar_mul(c, x))
+    }
+
+    //a uniformly random scalar from the given RNG
+    fn random_scalar_with<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R) -> Self::Scalar;
+
+    //the same from the operating system's RNG
+    fn random_scalar(&self) -> Self::Scalar {
+        self.random_scalar_with(&mut OsRng)
+    }
+
+    //the canonical encoding, always scalar_len() bytes
+    fn scalar_to_bytes(&self, s: &Self::Scalar) -> Vec<u8>;
+    fn scalar_len(&self) -> usize;
+    //fails if the bytes are not the canonical encoding of a scalar less than q
+    fn scalar_from_bytes(&self, bytes: &[u8]) -> Result<Self::Scalar, ValidationError>;
+    //reduces a hash output mod q, used for Fiat-Shamir challenges
+    fn scalar_from_hash(&self, digest: &[u8]) -> Self::Scalar;
+    //RFC 6979 bits2int: the leftmost bits of the input, as many as q has, read as a big-endian integer
+    //None if that integer is not less than q, the nonce derivation then tries the next candidate
+    fn scalar_from_bits(&self, bits: &[u8]) -> Option<Self::Scalar>;
+
+    //an encoding of the group parameters, hashed into Fiat-Shamir challenges
+    //so a proof made in one group is never accepted in another
+    fn parameters_bytes(&self) -> Vec<u8>;
+}
+
+//the multiplicative group mod p, restricted to the subgroup of order q
+#[derive(Debug, Clone)]
+pub struct ModpGroup {
+    pub p: BigUint,
+    pub q: BigUint, //order of the subgroup
+    pub alpha: BigUint,
+    pub beta: BigUint,
+    //see with_fixed_base_tables, shared between clones
+    tables: Option<Arc<FixedBaseTables>>,
+    //p and q prepared for the arithmetic in ct.rs, made once in new and shared between clones
+    moduli: Arc<Moduli>,
+}
+
+//None for an even value, which no valid group has, CtModulus::new then fails when it is used
+#[derive(Debug)]
+struct Moduli {
+    p: Option<CtModulus>,
+    q: Option<CtModulus>,
+}
+
+impl Moduli {
+    fn new(p: &BigUint, q: &BigUint) -> Self {
+        let prepare = |m: &BigUint| m.bit(0).then(|| CtModulus::new(m));
+        Self { p: prepare(p), q: prepare(q) }
+    }
+}
+
+//the prepared modulus if it was made from m, p and q are public and may have been changed since
+fn prepared<'a>(prepared: &'a Option<CtModulus>, m: &BigUint) -> Cow<'a, CtModulus> {
+    match prepared {
+        Some(modulus) if modulus.is_modulus(m) => Cow::Borrowed(modulus),
+        _ => Cow::Owned(CtModulus::new(m)),
+    }
+}
+
+//powers of one base for exponents below 2^(FIXED_BASE_WINDOW * rows), in Montgomery form
+//rows[i][d] = base^(d * 2^(FIXED_BASE_WINDOW * i)), so base^e is one product of an entry
+//per window of e and needs no squarings
+struct FixedBaseTable {
+    base: BigUint,
+    rows: Vec<Vec<CtScalar>>,
+}
+
+struct FixedBaseTables {
+    p: CtModulus,
+    alpha: FixedBaseTable,
+    beta: FixedBaseTable,
+}
+
+//the tables are large, only their shape is printed
+impl Debug for FixedBaseTables {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FixedBaseTables({} rows)", self.alpha.rows.len())
+    }
+}
+
+impl FixedBaseTable {
+    fn new(p: &CtModulus, base: &BigUint, exponent_bits: u64) -> Self {
+        let one = p.to_montgomery(&p.scalar(&BigUint::from(1u32)));
+        //base^(2^(FIXED_BASE_WINDOW * i)) for the row being built
+        let mut power = p.to_montgomery(&p.scalar(base));
+        let rows = (0..exponent_bits.div_ceil(FIXED_BASE_WINDOW)).map(|_| {
+            let mut row = vec![one.clone(), power.clone()];
+            for _ in 2..(1 << FIXED_BASE_WINDOW) {
+                row.push(p.montgomery_mul(row.last().unwrap(), &power));
+            }
+            power = p.montgomery_mul(row.last().unwrap(), &power);
+            row
+        }).collect();
+        Self { base: base.clone(), rows }
+    }
+
+    //the rows are read with ct_select so which entries are used does not show in the cache,
+    //the exponent may be a secret (x or k)
+    fn exp(&self, p: &CtModulus, exponent: &BigUint) -> BigUint {
+        let mut acc = self.rows[0][0].clone();
+        for (i, row) in self.rows.iter().enumerate() {
+            let digit = (0..FIXED_BASE_WINDOW).rev()
+                .fold(0, |d, j| (d << 1) | exponent.bit(i as u64 * FIXED_BASE_WINDOW + j) as usize);
+            acc = p.montgomery_mul(&acc, &ct_select(row, digit));
+        }
+        p.from_montgomery(&acc).to_biguint()
+    }
+
+    fn covers(&self, base: &BigUint, exponent: &BigUint) -> bool {
+        self.base == *base && exponent.bits() <= self.rows.len() as u64 * FIXED_BASE_WINDOW
+    }
+}
+
+impl ModpGroup {
+    pub fn new(p: BigUint, q: BigUint, alpha: BigUint, beta: BigUint) -> Self {
+        let moduli = Arc::new(Moduli::new(&p, &q));
+        Self { p, q, alpha, beta, tables: None, moduli }
+    }
+
+    fn p_modulus(&self) -> Cow<'_, CtModulus> {
+        prepared(&self.moduli.p, &self.p)
+    }
+
+    fn q_modulus(&self) -> Cow<'_, CtModulus> {
+        prepared(&self.moduli.q, &self.q)
+    }
+
+    //precomputes alpha^e and beta^e for every e < q, exp_alpha and exp_beta are then a few
+    //times faster than modpow. The tables take 2^FIXED_BASE_WINDOW * bits(q) / FIXED_BASE_WINDOW
+    //elements per generator, about 2 MB for the 2048-bit sets and 16 MB for the 4096-bit one.
+    //They remember their base, if alpha or beta is changed afterwards modpow is used again
+    pub fn with_fixed_base_tables(self) -> Self {
+        let p = self.p_modulus().into_owned();
+        let bits = self.q.bits();
+        let tables = FixedBaseTables {
+            alpha: FixedBaseTable::new(&p, &self.alpha, bits),
+            beta: FixedBaseTable::new(&p, &self.beta, bits),
+            p,
+        };
+        Self { tables: Some(Arc::new(tables)), ..self }
+    }
+
+    pub fn has_fixed_base_tables(&self) -> bool {
+        self.tables.is_some()
+    }
+}
+
+impl Group for ModpGroup {
+    type Element = BigUint;
+    type Scalar = BigUint;
+
+    fn alpha(&self) -> &BigUint {
+        &self.alpha
+    }
+
+    fn beta(&self) -> &BigUint {
+        &self.beta
+    }
+
+    fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        (a * b) % &self.p
+    }
+
+    fn exp(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        base.modpow(exponent, &self.p)
+    }
+
+    fn exp_alpha(&self, exponent: &BigUint) -> BigUint {
+        match &self.tables {
+            Some(tables) if tables.alpha.covers(&self.alpha, exponent) => tables.alpha.exp(&tables.p, exponent),
+            _ => self.exp(&self.alpha, exponent),
+        }
+    }
+
+    fn exp_beta(&self, exponent: &BigUint) -> BigUint {
+        match &self.tables {
+            Some(tables) if tables.beta.covers(&self.beta, exponent) => tables.beta.exp(&tables.p, exponent),
+            _ => self.exp(&self.beta, exponent),
+        }
+    }
+
+    //e must be in [2, p-1] and e^q = 1 mod p
+    //Straus' method: one chain of squarings for all the terms, each term multiplies in its
+    //next MULTI_EXP_WINDOW bits from a table of small powers, all of it in Montgomery form
+    //the running time depends on the exponents, this is for verifying and never for secrets
+    fn multi_exp(&self, terms: &[(&BigUint, &BigUint)]) -> BigUint {
+        let p = self.p_modulus();
+        let one = p.to_montgomery(&p.scalar(&BigUint::from(1u32)));
+        //tables[i][d] = base_i^d in Montgomery form
+        let tables: Vec<Vec<CtScalar>> = terms.iter().map(|(base, _)| {
+            let base = p.to_montgomery(&p.scalar(base));
+            let mut row = vec![one.clone(), base.clone()];
+            for _ in 2..(1 << MULTI_EXP_WINDOW) {
+                row.push(p.montgomery_mul(row.last().unwrap(), &base));
+            }
+            row
+        }).collect();
+
+        let bits = terms.iter().map(|(_, e)| e.bits()).max().unwrap_or(0);
+        let mut acc = one;
+        for window in (0..bits.div_ceil(MULTI_EXP_WINDOW)).rev() {
+            for _ in 0..MULTI_EXP_WINDOW {
+                acc = p.montgomery_mul(&acc, &acc);
+            }
+            for ((_, exponent), row) in terms.iter().zip(&tables) {
+                let digit = (0..MULTI_EXP_WINDOW).rev()
+                    .fold(0, |d, i| (d << 1) | exponent.bit(window * MULTI_EXP_WINDOW + i) as usize);
+                if digit != 0 {
+                    acc = p.montgomery_mul(&acc, &row[digit]);
+                }
+            }
+        }
+        p.from_montgomery(&acc).to_biguint()
+    }
+
+    fn validate_element(&self, e: &BigUint) -> Result<(), ValidationError> {
+        let one = BigUint::from(1u32);
+        if *e == BigUint::from(0u32) {
+            return Err(ValidationError::Zero);
+        }
+        if *e == one {
+            return Err(ValidationError::Identity);
+        }
+        if *e >= self.p {
+            return Err(ValidationError::OutOfRange);
+        }
+        if e.modpow(&self.q, &self.p) != one {
+            return Err(ValidationError::NotInSubgroup);
+        }
+        Ok(())
+    }
+
+    //p and q prime, q divides p-1, alpha and beta have order q
+    fn validate_parameters(&self) -> Result<(), ValidationError> {
+        if !is_probable_prime(&self.p, PRIMALITY_ROUNDS) {
+            return Err(ValidationError::NotPrime("p"));
+        }
+        if !is_probable_prime(&self.q, PRIMALITY_ROUNDS) {
+            return Err(ValidationError::NotPrime("q"));
+        }
+        if (&self.p - BigUint::from(1u32)) % &self.q != BigUint::from(0u32) {
+            return Err(ValidationError::OrderDoesNotDivide);
+        }
+        //q is prime so any element other than 1 with e^q = 1 has order exactly q
+        self.validate_element(&self.alpha).map_err(|_| ValidationError::BadGenerator("alpha"))?;
+        self.validate_element(&self.beta).map_err(|_| ValidationError::BadGenerator("beta"))?;
+        Ok(())
+    }
+
+    //big-endian, zero padded on the left to the length of p
+    fn element_to_bytes(&self, e: &BigUint) -> Vec<u8> {
+        pad_be(e, self.element_len())
+    }
+
+    fn element_len(&self) -> usize {
+        byte_len(&self.p)
+    }
+
+    fn element_from_bytes(&self, bytes: &[u8]) -> Result<BigUint, ValidationError> {
+        check_len(bytes, self.element_len())?;
+        let e = BigUint::from_bytes_be(bytes);
+        if e == BigUint::from(0u32) {
+            return Err(ValidationError::Zero);
+        }
+        if e >= self.p {
+            return Err(ValidationError::OutOfRange);
+        }
+        Ok(e)
+    }
+
+    //scalar arithmetic goes through the constant-time limbs in ct.rs,
+    //num_bigint is only used to hold the values in between
+    fn scalar_add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        let q = self.q_modulus();
+        q.add(&q.scalar(a), &q.scalar(b)).to_biguint()
+    }
+
+    fn scalar_sub(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        let q = self.q_modulus();
+        q.sub(&q.scalar(a), &q.scalar(b)).to_biguint()
+    }
+
+    fn scalar_mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        let q = self.q_modulus();
+        q.mul(&q.scalar(a), &q.scalar(b)).to_biguint()
+    }
+
+    //q is prime so a^(q-2) is the inverse
+    fn scalar_invert(&self, a: &BigUint) -> Option<BigUint> {
+        if a.bits() == 0 {
+            return None;
+        }
+        Some(a.modpow(&(&self.q - 2u32), &self.q))
+    }
+
+    //c * x stays in fixed width limbs instead of going back through a BigUint
+    fn solve(&self, k: &BigUint, c: &BigUint, x: &BigUint) -> BigUint {
+        let q = self.q_modulus();
+        let cx = q.mul(&q.scalar(c), &q.scalar(x));
+        q.sub(&q.scalar(k), &cx).to_biguint()
+    }
+
+    fn random_scalar_with<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R) -> BigUint {
+        rng.gen_biguint_below(&self.q)
+    }
+
+    //big-endian, zero padded on the left to the length of q
+    fn scalar_to_bytes(&self, s: &BigUint) -> Vec<u8> {
+        pad_be(s, self.scalar_len())
+    }
+
+    fn scalar_len(&self) -> usize {
+        byte_len(&self.q)
+    }
+
+    fn scalar_from_bytes(&self, bytes: &[u8]) -> Result<BigUint, ValidationError> {
+        check_len(bytes, self.scalar_len())?;
+        let s = BigUint::from_bytes_be(bytes);
+        if s >= self.q {
+            return Err(ValidationError::OutOfRange);
+        }
+        Ok(s)
+    }
+
+    fn scalar_from_hash(&self, digest: &[u8]) -> BigUint {
+        BigUint::from_bytes_be(digest) % &self.q
+    }
+
+    fn scalar_from_bits(&self, bits: &[u8]) -> Option<BigUint> {
+        let z = bits_to_int(bits, self.q.bits());
+        (z < self.q).then_some(z)
+    }
+
+    fn parameters_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for value in [&self.p, &self.q, &self.alpha, &self.beta] {
+            let bytes = value.to_bytes_be();
+            out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+            out.extend_from_slice(&bytes);
+        }
+        out
+    }
+}
+
+//RFC 6979 bits2int, the leftmost qlen bits of the input
+pub fn bits_to_int(bits: &[u8], qlen: u64) -> BigUint {
+    let z = BigUint::from_bytes_be(bits);
+    let blen = 8 * bits.len() as u64;
+    if blen > qlen { z >> (blen - qlen) } else { z }
+}
+
+//big-endian bytes of x, zero padded on the left to len bytes
+fn pad_be(x: &BigUint, len: usize) -> Vec<u8> {
+    let bytes = x.to_bytes_be();
+    let mut out = vec![0u8; len.saturating_sub(bytes.len())];
+    out.extend_from_slice(&bytes);
+    out
+}
+
+fn byte_len(x: &BigUint) -> usize {
+    (x.bits() as usize).div_ceil(8)
+}
+
+//an encoding must have exactly the canonical width
+pub fn check_len(bytes: &[u8], expected: usize) -> Result<(), ValidationError> {
+    if bytes.is_empty() {
+        return Err(ValidationError::Empty);
+    }
+    if bytes.len() != expected {
+        return Err(ValidationError::WrongLength { expected, got: bytes.len() });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toy_group() -> ModpGroup {
+        ModpGroup::new(BigUint::from(23u32), BigUint::from(11u32), BigUint::from(4u32), BigUint::from(9u32))
+    }
+
+    #[test]
+    fn test_modp_encoding() {
+        let group = toy_group();
+
+        let e = BigUint::from(8u32);
+        assert_eq!(group.element_from_bytes(&group.element_to_bytes(&e)), Ok(e));
+        assert_eq!(group.element_from_bytes(&[]), Err(ValidationError::Empty));
+        assert_eq!(group.element_from_bytes(&[0]), Err(ValidationError::Zero));
+        assert_eq!(group.element_from_bytes(&[23]), Err(ValidationError::OutOfRange));
+        //a leading zero byte is not canonical
+        assert_eq!(group.element_from_bytes(&[0, 8]), Err(ValidationError::WrongLength { expected: 1, got: 2 }));
+
+        assert_eq!(group.scalar_from_bytes(&[10]), Ok(BigUint::from(10u32)));
+        assert_eq!(group.scalar_from_bytes(&[11]), Err(ValidationError::OutOfRange));
+        assert_eq!(group.scalar_from_bytes(&[0, 10]), Err(ValidationError::WrongLength { expected: 1, got: 2 }));
+    }
+
+    #[test]
+    fn test_modp_fixed_width() {
+        let group = crate::parameter_set(crate::RFC5114_1024_GROUP_ID).unwrap().group().unwrap();
+        assert_eq!(group.element_len(), 128);
+        assert_eq!(group.scalar_len(), 20);
+
+        //small values are padded, not shortened
+        let two = BigUint::from(2u32);
+        let bytes = group.element_to_bytes(&two);
+        assert_eq!(bytes.len(), 128);
+        assert_eq!(group.element_from_bytes(&bytes), Ok(two.clone()));
+        assert_eq!(group.scalar_to_bytes(&two).len(), 20);
+        assert_eq!(group.element_from_bytes(&[2]), Err(ValidationError::WrongLength { expected: 128, got: 1 }));
+    }
+
+    #[test]
+    fn test_modp_subgroup() {
+        let group = toy_group();
+
+        //4 generates the order 11 subgroup, 5 is a generator of the whole group
+        assert!(group.is_valid_element(&BigUint::from(4u32)));
+        assert!(!group.is_valid_element(&BigUint::from(5u32)));
+        assert!(!group.is_valid_element(&BigUint::from(1u32)));
+        assert!(!group.is_valid_element(&BigUint::from(22u32)));
+
+        assert_eq!(group.validate_element(&BigUint::from(1u32)), Err(ValidationError::Identity));
+        assert_eq!(group.validate_element(&BigUint::from(5u32)), Err(ValidationError::NotInSubgroup));
+        assert_eq!(group.validate_element(&BigUint::from(30u32)), Err(ValidationError::OutOfRange));
+    }
+
+    #[test]
+    fn test_modp_parameters() {
+        assert_eq!(toy_group().validate_parameters(), Ok(()));
+
+        //21 is not prime
+        let group = ModpGroup { p: BigUint::from(21u32), ..toy_group() };
+        assert_eq!(group.validate_parameters(), Err(ValidationError::NotPrime("p")));
+
+        //7 is prime but does not divide 22
+        let group = ModpGroup { q: BigUint::from(7u32), ..toy_group() };
+        assert_eq!(group.validate_parameters(), Err(ValidationError::OrderDoesNotDivide));
+
+        //5 has order 22
+        let group = ModpGroup { beta: BigUint::from(5u32), ..toy_group() };
+        assert_eq!(group.validate_parameters(), Err(ValidationError::BadGenerator("beta")));
+    }
+
+    #[test]
+    fn test_moduli_follow_p_and_q() {
+        //made once in new and shared by clones
+        let group = toy_group();
+        let clone = group.clone();
+        assert!(Arc::ptr_eq(&group.moduli, &clone.moduli));
+        assert!(matches!(clone.p_modulus(), Cow::Borrowed(_)) && matches!(clone.q_modulus(), Cow::Borrowed(_)));
+        //a q changed afterwards is not computed with the modulus of the old one
+        let changed = ModpGroup { q: BigUint::from(7u32), ..toy_group() };
+        assert!(matches!(changed.q_modulus(), Cow::Owned(_)));
+        assert_eq!(changed.scalar_add(&BigUint::from(5u32), &BigUint::from(4u32)), BigUint::from(2u32));
+        //an even p is only refused when it is used, validate_parameters reports it first
+        assert_eq!(ModpGroup::new(BigUint::from(22u32), BigUint::from(11u32), BigUint::from(4u32), BigUint::from(9u32)).validate_parameters(),
+            Err(ValidationError::NotPrime("p")));
+    }
+
+    #[test]
+    fn test_modp_scalars() {
+        let group = toy_group();
+        let a = BigUint::from(3u32);
+        let b = BigUint::from(9u32);
+
+        assert_eq!(group.scalar_add(&a, &b), BigUint::from(1u32));
+        assert_eq!(group.scalar_sub(&a, &b), BigUint::from(5u32));
+        assert_eq!(group.scalar_mul(&a, &b), BigUint::from(5u32));
+        //k - c * x = 3 - 9 * 3 = -24 = 9 mod 11
+        assert_eq!(group.solve(&a, &b, &a), BigUint::from(9u32));
+        //3 * 4 = 12 = 1 mod 11
+        assert_eq!(group.scalar_invert(&a), Some(BigUint::from(4u32)));
+        assert_eq!(group.scalar_invert(&BigUint::from(0u32)), None);
+
+        assert!(group.ct_eq(&BigUint::from(4u32), &BigUint::from(4u32)));
+        assert!(!group.ct_eq(&BigUint::from(4u32), &BigUint::from(16u32)));
+    }
+}
@@ -0,0 +1,111 @@
+//Nothing-up-my-sleeve derivation of generators from a seed string.
+//Chaum-Pedersen is only sound if nobody knows log_alpha(beta), so beta is
+//hashed into the order q subgroup instead of being a known power of alpha.
+//Anyone can rerun the derivation from the seed and check the result.
+//
+//MODP groups, in the style of FIPS 186-4 appendix A.2.3:
+//  for count = 1, 2, ...
+//      W = expand(seed, count), len(p) + 16 bytes so W mod p is close to uniform
+//      g = (W mod p)^((p-1)/q) mod p
+//      if g >= 2 return g
+//where expand concatenates SHA-256(DOMAIN || len(seed) || seed || count || block)
+//for block = 0, 1, ... with len(seed) and block as 4-byte big-endian and count as one byte.
+//g^q = W^(p-1) = 1 so g is always in the subgroup.
+//
+//Ristretto255 uses the hash-to-group map of the curve on SHA-512(seed).
+
+use crate::error::{Result, ZkpAuthError};
+use curve25519_dalek::ristretto::RistrettoPoint;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256, Sha512};
+
+const DOMAIN: &[u8] = b"zkp_auth/hash-to-group/v1";
+
+//the seed every group derives its beta from
+pub fn beta_seed(group_id: &str) -> String {
+    format!("zkp_auth/{}/beta", group_id)
+}
+
+fn expand(seed: &[u8], count: u8, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len + 32);
+    let mut block: u32 = 0;
+    while out.len() < len {
+        let mut hasher = Sha256::new();
+        hasher.update(DOMAIN);
+        hasher.update((seed.len() as u32).to_be_bytes());
+        hasher.update(seed);
+        hasher.update([count]);
+        hasher.update(block.to_be_bytes());
+        out.extend_from_slice(&hasher.finalize());
+        block += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+//hashes a seed to an element of the order q subgroup of Z_p*, q must divide p-1
+pub fn hash_to_subgroup(p: &BigUint, q: &BigUint, seed: &[u8]) -> Result<BigUint> {
+    let one = BigUint::from(1u32);
+    if *q <= one || *p <= *q || (p - &one) % q != BigUint::from(0u32) {
+        return Err(ZkpAuthError::Crypto("q does not divide p-1, there is no order q subgroup to hash into".to_string()));
+    }
+    let cofactor = (p - &one) / q;
+    let len = p.to_bytes_be().len() + 16;
+
+    //a candidate is 1 with probability about 1/q, so this never runs out in practice
+    for count in 1..=u8::MAX {
+        let w = BigUint::from_bytes_be(&expand(seed, count, len)) % p;
+        let g = w.modpow(&cofactor, p);
+        if g > one {
+            return Ok(g);
+        }
+    }
+    Err(ZkpAuthError::Crypto("could not hash the seed into the subgroup".to_string()))
+}
+
+//hashes a seed to a ristretto255 point
+pub fn hash_to_point(seed: &[u8]) -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(seed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_to_subgroup_toy() {
+        let p = BigUint::from(23u32);
+        let q = BigUint::from(11u32);
+
+        //known answers, reproducible from the description at the top of this file
+        assert_eq!(hash_to_subgroup(&p, &q, b"zkp_auth/toy/beta").unwrap(), BigUint::from(4u32));
+        assert_eq!(hash_to_subgroup(&p, &q, b"zkp_auth/toy/gamma").unwrap(), BigUint::from(18u32));
+
+        for seed in [&b"a"[..], b"b", b"c", b""] {
+            let g = hash_to_subgroup(&p, &q, seed).unwrap();
+            assert!(g > BigUint::from(1u32));
+            assert_eq!(g.modpow(&q, &p), BigUint::from(1u32));
+        }
+
+        //parameters without an order q subgroup are an error and not a panic
+        for (p, q) in [(23u32, 7u32), (23, 1), (11, 23)] {
+            let result = hash_to_subgroup(&BigUint::from(p), &BigUint::from(q), b"zkp_auth/toy/beta");
+            assert!(matches!(result, Err(ZkpAuthError::Crypto(_))), "p {} q {}", p, q);
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let short = hex::encode(expand(b"seed", 1, 40));
+        assert_eq!(short, "13871daf14e6b319cf075c9899ece97ae977fceb250068f9e7d6107de8417e27f31da15d366384ee");
+        //a longer output starts with the shorter one
+        assert!(hex::encode(expand(b"seed", 1, 64)).starts_with(&short));
+        assert_ne!(expand(b"seed", 1, 32), expand(b"seed", 2, 32));
+    }
+
+    #[test]
+    fn test_hash_to_point() {
+        let beta = hash_to_point(beta_seed("ristretto255").as_bytes());
+        assert_eq!(hex::encode(beta.compress().as_bytes()), "82f60558ef65ecd6148e52c1a6e41285b8a25cf5f2e1ed5cb6ed9c134ba37a38");
+    }
+}
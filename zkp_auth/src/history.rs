@@ -0,0 +1,147 @@
+//A bounded record of the transcripts a DID has completed, kept by the verifier
+//to notice a prover that uses the same nonce k twice.
+//Two accepting transcripts with the same commitment (r1, r2) and different
+//challenges give away the secret x, see ZKP::extract_witness, so a repeat
+//with another challenge means a prover whose keys are now known.
+//The history is not replay protection: it is bounded and drops the oldest transcript first,
+//so an old proof is forgotten after COMMITMENT_HISTORY logins. A proof is kept from being
+//used twice elsewhere, an interactive challenge is taken once from the ChallengeStore and a
+//login context is retired there (see crate::login); the history only tells a repeat seen in time.
+//Only a digest of the commitment is kept, commitments in the 4096-bit group are 1 KiB.
+
+use crate::proof::{Challenge, Commitment, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+
+//transcripts kept per DID, the oldest is dropped when a new one comes in
+pub const COMMITMENT_HISTORY: usize = 64;
+
+//an accepting transcript, the commitment as its SHA-256 digest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub commitment: [u8; 32],
+    pub challenge: Challenge,
+    pub response: Response,
+}
+
+//what the history says about a transcript it is given
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Seen {
+    //the commitment was not used before
+    New,
+    //the same commitment with the same challenge, an old proof sent again
+    //only while it is in the history, the single-use challenges and login contexts are what refuse a replay
+    Replay,
+    //the same commitment with a different challenge, the nonce was reused
+    //holds the earlier transcript so the caller can extract the witness
+    NonceReuse(Transcript),
+}
+
+//kept with the DID in the user store, so a restart does not forget which nonces were used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitmentHistory {
+    transcripts: VecDeque<Transcript>,
+    capacity: usize,
+}
+
+impl Default for CommitmentHistory {
+    fn default() -> Self {
+        Self::new(COMMITMENT_HISTORY)
+    }
+}
+
+impl CommitmentHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self { transcripts: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn digest(commitment: &Commitment) -> [u8; 32] {
+        Sha256::digest(commitment.to_bytes()).into()
+    }
+
+    //the earlier transcript that used this commitment, if it is still in the history
+    pub fn find(&self, commitment: &Commitment) -> Option<&Transcript> {
+        let digest = Self::digest(commitment);
+        self.transcripts.iter().find(|t| t.commitment == digest)
+    }
+
+    //compares an accepting transcript against the history and keeps it if the commitment is new
+    //repeats are not kept, the first transcript with a commitment is the one that matters
+    pub fn record(&mut self, commitment: &Commitment, challenge: Challenge, response: Response) -> Seen {
+        if let Some(previous) = self.find(commitment) {
+            return if previous.challenge == challenge {
+                Seen::Replay
+            } else {
+                Seen::NonceReuse(previous.clone())
+            };
+        }
+        if self.transcripts.len() == self.capacity {
+            self.transcripts.pop_front();
+        }
+        self.transcripts.push_back(Transcript { commitment: Self::digest(commitment), challenge, response });
+        Seen::New
+    }
+
+    pub fn len(&self) -> usize {
+        self.transcripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transcripts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Group, ModpGroup, ZKP};
+
+    fn transcript(zkp: &ZKP<ModpGroup>, x: &num_bigint::BigUint, k: &num_bigint::BigUint) -> (Commitment, Challenge, Response) {
+        let (r1, r2) = zkp.exponentiate_pair(k);
+        let c = zkp.group.random_scalar();
+        let s = zkp.solve(k, &c, x);
+        (Commitment::new(zkp, &r1, &r2), Challenge::new(zkp, &c), Response::new(zkp, &s))
+    }
+
+    #[test]
+    fn test_history_detects_reuse() {
+        let zkp = ZKP::new(ZKP::default_group().unwrap());
+        let x = zkp.group.random_scalar();
+        let k = zkp.group.random_scalar();
+        let mut history = CommitmentHistory::default();
+
+        let (commitment, c1, s1) = transcript(&zkp, &x, &k);
+        assert_eq!(history.record(&commitment, c1.clone(), s1.clone()), Seen::New);
+        assert_eq!(history.record(&commitment, c1.clone(), s1.clone()), Seen::Replay);
+
+        //same k, fresh challenge: the earlier transcript comes back and the secret falls out
+        let (_, c2, s2) = transcript(&zkp, &x, &k);
+        let Seen::NonceReuse(previous) = history.record(&commitment, c2.clone(), s2.clone()) else {
+            panic!("nonce reuse was not detected");
+        };
+        let witness = zkp.extract_witness(
+            &previous.challenge.decode(&zkp).unwrap(), &previous.response.decode(&zkp).unwrap(),
+            &c2.decode(&zkp).unwrap(), &s2.decode(&zkp).unwrap(),
+        );
+        assert_eq!(witness, Some(x));
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let zkp = ZKP::new(ZKP::default_group().unwrap());
+        let x = zkp.group.random_scalar();
+        let mut history = CommitmentHistory::new(4);
+
+        let (first, c, s) = transcript(&zkp, &x, &zkp.group.random_scalar());
+        history.record(&first, c, s);
+        for _ in 0..4 {
+            let (commitment, c, s) = transcript(&zkp, &x, &zkp.group.random_scalar());
+            assert_eq!(history.record(&commitment, c, s), Seen::New);
+        }
+        assert_eq!(history.len(), 4);
+        //the oldest one was dropped
+        assert!(history.find(&first).is_none());
+    }
+}
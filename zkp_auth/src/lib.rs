@@ -1,74 +1,229 @@
 //This library provides functions to generate zero knowledge proofs
 //and to verify them
+pub mod batch;
+pub mod context;
+pub mod ct;
+pub mod error;
+pub mod group;
+pub mod hash_to_group;
+pub mod history;
+pub mod limit;
+pub mod login;
+pub mod nonce;
+pub mod params;
+pub mod pool;
+pub mod proof;
+pub mod puzzle;
+pub mod registration;
+pub mod ristretto;
+pub mod session;
 pub mod ssi;
+pub mod store;
+pub mod telemetry;
+pub mod token;
+pub mod validation;
 pub mod zkp_proto;
 //pub mod wallet;
 
 
 
 use num_bigint::{BigUint, RandBigInt}; 
-use rand;
-use hex;
-use rand::Rng;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, Rng, RngCore};
+use sha2::{Sha256, Digest};
+
+pub use batch::BatchEntry;
+pub use error::ZkpAuthError;
+pub use group::{Group, ModpGroup};
+pub use proof::{Challenge, Commitment, Proof, Response};
+pub use params::{parameter_set, RFC5114_1024_GROUP_ID, RFC5114_2048_224_GROUP_ID, RFC5114_2048_256_GROUP_ID,
+    RFC3526_2048_GROUP_ID, RFC3526_3072_GROUP_ID, RFC3526_4096_GROUP_ID};
+pub use ristretto::{Ristretto255Group, RISTRETTO255_GROUP_ID};
+pub use validation::ValidationError;
+
+//any cryptographically secure RNG, as one trait so it can be boxed
+//everything random takes one of these in its _with variant, the plain variant uses OsRng
+//a seeded rand::rngs::StdRng replays a protocol run exactly
+pub trait SecureRng: RngCore + CryptoRng + Send {}
+impl<R: RngCore + CryptoRng + Send> SecureRng for R {}
+
+//bytes of fresh randomness mixed into every derived nonce
+pub const NONCE_RANDOMNESS_LEN: usize = 32;
+
+//Chaum-Pedersen prover and verifier, generic over the group it runs in
+//the default is the multiplicative group mod p from get_zkp_constants
+pub struct ZKP<G: Group = ModpGroup> {
+    pub group: G,
+}
 
-pub struct ZKP {
-    pub p: BigUint,
-    pub q: BigUint, //order of the egrouo
-    pub alpha: BigUint,
-    pub beta: BigUint,
+impl<G: Group> ZKP<G> {
+pub fn new(group: G) -> Self {
+    Self { group }
+}
 
+//output = (alpha^exp, beta^exp)
+//with the secret x this gives (y1, y2), with the nonce k it gives (r1, r2)
+pub fn exponentiate_pair(&self, exponent: &G::Scalar) -> (G::Element, G::Element) {
+    (self.group.exp_alpha(exponent), self.group.exp_beta(exponent))
 }
 
-impl ZKP {
-//output = n^exp mod p
-pub fn exponentiate( n: &BigUint, exponent: &BigUint, modulus: &BigUint) -> BigUint {
-    n.modpow(exponent,modulus)
+//decodes an element received from the other party
+//it must be in range and in the order q subgroup before it is used in a proof
+pub fn decode_element(&self, bytes: &[u8]) -> Result<G::Element, ValidationError> {
+    let e = self.group.element_from_bytes(bytes)?;
+    self.group.validate_element(&e)?;
+    Ok(e)
+}
+
+//decodes a scalar received from the other party, it must be less than q
+pub fn decode_scalar(&self, bytes: &[u8]) -> Result<G::Scalar, ValidationError> {
+    self.group.scalar_from_bytes(bytes)
 }
 
 //outpt = s = k - c * x mod q
-pub fn solve( &self, k: &BigUint, c: &BigUint, x: &BigUint) -> BigUint {
-    if *k >= c * x {
-        return (k - c * x).modpow(&BigUint::from(1u32), &self.q);
-    }
-    return &self.q - (c * x - k).modpow(&BigUint::from(1u32), &self.q);
+//x is the long term secret and k the nonce, the group does this in constant time
+pub fn solve( &self, k: &G::Scalar, c: &G::Scalar, x: &G::Scalar) -> G::Scalar {
+    self.group.solve(k, c, x)
 }
 
 // the verfiy function verifies the solution by checking if 
 //r1 = A^s * y1^c and r2 = B^s * y2^c
 
-pub fn verify_solution(&self, r1: &BigUint, r2: &BigUint, y1: &BigUint, y2: &BigUint, c: &BigUint, s: &BigUint) -> bool {
-   let condition1 = *r1 == (&self.alpha.modpow(s, &self.p) * y1.modpow(c, &self.p)).modpow(&BigUint::from(1u32), &self.p);
-   let condition2 = *r2 == (&self.beta.modpow(s, &self.p) * y2.modpow(c, &self.p)).modpow(&BigUint::from(1u32), &self.p);
-   condition1 && condition2
+pub fn verify_solution(&self, r1: &G::Element, r2: &G::Element, y1: &G::Element, y2: &G::Element, c: &G::Scalar, s: &G::Scalar) -> bool {
+   let group = &self.group;
+   let condition1 = group.ct_eq(r1, &group.mul(&group.exp_alpha(s), &group.exp(y1, c)));
+   let condition2 = group.ct_eq(r2, &group.mul(&group.exp_beta(s), &group.exp(y2, c)));
+   //& rather than && so both comparisons always run
+   condition1 & condition2
+}
+
+// special soundness, and why a nonce must never be used twice:
+// two accepting transcripts (r1, r2, c1, s1) and (r1, r2, c2, s2) with the same commitment
+// and different challenges give s1 - s2 = (c2 - c1) * x, so anyone who sees both learns
+// x = (s1 - s2) / (c2 - c1) mod q
+// returns None when the challenges are equal, then the two transcripts say nothing more than one
+pub fn extract_witness(&self, c1: &G::Scalar, s1: &G::Scalar, c2: &G::Scalar, s2: &G::Scalar) -> Option<G::Scalar> {
+    let group = &self.group;
+    let inverse = group.scalar_invert(&group.scalar_sub(c2, c1))?;
+    Some(group.scalar_mul(&group.scalar_sub(s1, s2), &inverse))
+}
+
+// Fiat-Shamir transform: instead of waiting for the verifier to send c,
+// the prover derives it by hashing everything the verifier would have seen
+// c = H(group parameters, y1, y2, r1, r2, context) mod q
+// the context binds the proof to a use (e.g. the DID and the login attempt),
+// so a proof made for one context is rejected in another
+pub fn challenge_hash(&self, y1: &G::Element, y2: &G::Element, r1: &G::Element, r2: &G::Element, context: &[u8]) -> G::Scalar {
+    let mut hasher = Sha256::new();
+    hasher.update(b"zkp_auth/chaum-pedersen/v1");
+    hasher.update(self.group.parameters_bytes());
+    for value in [y1, y2, r1, r2] {
+        //every value is length prefixed so two different transcripts never hash the same bytes
+        let bytes = self.group.element_to_bytes(value);
+        hasher.update((bytes.len() as u32).to_be_bytes());
+        hasher.update(&bytes);
+    }
+    hasher.update((context.len() as u32).to_be_bytes());
+    hasher.update(context);
+
+    self.group.scalar_from_hash(&hasher.finalize())
+}
+
+// the nonce k for a proof by x, derived as in RFC 6979 (see nonce.rs) rather than drawn from the RNG alone
+// h = H(group parameters, y1, y2, context) stands in for the message hash and the randomness is the
+// extra data, so k only repeats if the secret, the keys, the context and the randomness all do
+// a weak or forked RNG can then not leak x as long as the context differs between proofs
+pub fn derive_nonce(&self, x: &G::Scalar, y1: &G::Element, y2: &G::Element, context: &[u8], randomness: &[u8]) -> G::Scalar {
+    let mut hasher = Sha256::new();
+    hasher.update(b"zkp_auth/nonce/v1");
+    hasher.update(self.group.parameters_bytes());
+    for value in [y1, y2] {
+        let bytes = self.group.element_to_bytes(value);
+        hasher.update((bytes.len() as u32).to_be_bytes());
+        hasher.update(&bytes);
+    }
+    hasher.update((context.len() as u32).to_be_bytes());
+    hasher.update(context);
+
+    let h = self.group.scalar_from_hash(&hasher.finalize());
+    nonce::generate_k(&self.group, x, &h, randomness)
+}
+
+// the commitment (r1, r2) and its nonce k, the way every prover should start a proof
+// the context should name what the proof is for, e.g. the DID and the login attempt
+pub fn commit(&self, x: &G::Scalar, y1: &G::Element, y2: &G::Element, context: &[u8]) -> (G::Element, G::Element, G::Scalar) {
+    self.commit_with(&mut OsRng, x, y1, y2, context)
+}
+
+pub fn commit_with<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R, x: &G::Scalar, y1: &G::Element, y2: &G::Element, context: &[u8]) -> (G::Element, G::Element, G::Scalar) {
+    let mut randomness = [0u8; NONCE_RANDOMNESS_LEN];
+    rng.fill_bytes(&mut randomness);
+
+    let k = self.derive_nonce(x, y1, y2, context, &randomness);
+    let (r1, r2) = self.exponentiate_pair(&k);
+    (r1, r2, k)
+}
+
+// the prover runs the whole protocol on its own and outputs (r1, r2, s)
+// the verifier only needs y1, y2 and the same context to check it
+pub fn prove_non_interactive(&self, x: &G::Scalar, context: &[u8]) -> (G::Element, G::Element, G::Scalar) {
+    self.prove_non_interactive_with(&mut OsRng, x, context)
+}
+
+pub fn prove_non_interactive_with<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R, x: &G::Scalar, context: &[u8]) -> (G::Element, G::Element, G::Scalar) {
+    let (y1, y2) = self.exponentiate_pair(x);
+
+    let (r1, r2, k) = self.commit_with(rng, x, &y1, &y2, context);
+
+    let c = self.challenge_hash(&y1, &y2, &r1, &r2, context);
+    let s = self.solve(&k, &c, x);
+    (r1, r2, s)
+}
+
+// recomputes c from the transcript and runs the usual check
+pub fn verify_non_interactive(&self, r1: &G::Element, r2: &G::Element, y1: &G::Element, y2: &G::Element, s: &G::Scalar, context: &[u8]) -> bool {
+    let c = self.challenge_hash(y1, y2, r1, r2, context);
+    self.verify_solution(r1, r2, y1, y2, &c, s)
+}
+}
+
+//helpers for the multiplicative group mod p
+impl ZKP {
+//output = n^exp mod p
+pub fn exponentiate( n: &BigUint, exponent: &BigUint, modulus: &BigUint) -> BigUint {
+    n.modpow(exponent,modulus)
 }
 
 pub fn generate_random_number_less_than(bound: &BigUint) -> BigUint {
-    let mut rnge = rand::thread_rng();
+    ZKP::generate_random_number_less_than_with(&mut OsRng, bound)
+}
 
-    rnge.gen_biguint_below(bound)
+pub fn generate_random_number_less_than_with<R: RngCore + CryptoRng + ?Sized>(rng: &mut R, bound: &BigUint) -> BigUint {
+    rng.gen_biguint_below(bound)
 }
 
 pub fn generate_random_string(size: usize) -> String {
-    rand::thread_rng()
-        .sample_iter(rand::distributions::Alphanumeric)
-        .take(size)
-        .map(char::from)
+    ZKP::generate_random_string_with(&mut OsRng, size)
+}
+
+//alphanumeric, used for the auth_id and the session id
+pub fn generate_random_string_with<R: RngCore + CryptoRng + ?Sized>(rng: &mut R, size: usize) -> String {
+    (0..size)
+        .map(|_| char::from(rng.sample(rand::distributions::Alphanumeric)))
         .collect()
 }
 
-pub fn get_zkp_constants() -> (BigUint, BigUint, BigUint, BigUint){
-    let p = BigUint::from_bytes_be(&hex::decode("B10B8F96A080E01DDE92DE5EAE5D54EC52C99FBCFB06A3C69A6A9DCA52D23B616073E28675A23D189838EF1E2EE652C013ECB4AEA906112324975C3CD49B83BFACCBDD7D90C4BD7098488E9C219A73724EFFD6FAE5644738FAA31A4FF55BCCC0A151AF5F0DC8B4BD45BF37DF365C1A65E68CFDA76D4DA708DF1FB2BC2E4A4371").expect("could not convert hext"));
-    let q = BigUint::from_bytes_be(&hex::decode("F518AA8781A8DF278ABA4E7D64B7CB9D49462353").unwrap());
-       
-    let alpha = BigUint::from_bytes_be(&hex::decode("A4D1CBD5C3FD34126765A442EFB99905F8104DD258AC507FD6406CFF14266D31266FEA1E5C41564B777E690F5504F213160217B4B01B886A5E91547F9E2749F4D7FBD7D3B9A92EE1909D0D2263F80A76A6A24C087A091F531DBF0A0169B6A28AD662A4D18E73AFA32D779D5918D08BC8858F4DCEF97C2A24855E6EEB22B3B2E5").unwrap());
-       
-    //since alpha is a generator, alpha^x is a generator, this is a property in group theory
-    let exp = BigUint::from_bytes_be(
-        &hex::decode("266FEA1E5C41564B777E69").unwrap(),
-    );
+//returns (alpha, beta, p, q) of the default group
+pub fn get_zkp_constants() -> error::Result<(BigUint, BigUint, BigUint, BigUint)> {
+    let group = ZKP::default_group()?;
+    Ok((group.alpha, group.beta, group.p, group.q))
+}
 
-    let beta = alpha.modpow(&exp, &p);
-    (alpha, beta,p, q )
+//the RFC 5114 2048-bit group with a 256-bit subgroup, the one new wallets use
+pub fn default_group() -> error::Result<ModpGroup> {
+    parameter_set(RFC5114_2048_256_GROUP_ID)
+        .ok_or_else(|| ZkpAuthError::NotFound(format!("parameter set {}", RFC5114_2048_256_GROUP_ID)))?
+        .group()
 }
 }
 
@@ -82,7 +237,7 @@ mod test {
         let beta = BigUint::from(9u32);
         let p = BigUint::from(23u32);
         let q = BigUint::from(11u32);
-        let zkp_new = ZKP {p:p.clone(), q, alpha: alpha.clone(), beta: beta.clone()};
+        let zkp_new = ZKP::new(ModpGroup::new(p.clone(), q, alpha.clone(), beta.clone()));
 
         let x = BigUint::from(6u32); //the secret
         let k = BigUint::from(7u32); 
@@ -120,7 +275,7 @@ mod test {
         let beta = BigUint::from(9u32);
         let p = BigUint::from(23u32);
         let q = BigUint::from(11u32);
-        let zkp_new = ZKP {p:p.clone(), q: q.clone(), alpha: alpha.clone(), beta: beta.clone()};;
+        let zkp_new = ZKP::new(ModpGroup::new(p.clone(), q.clone(), alpha.clone(), beta.clone()));
 
         let x = BigUint::from(6u32); //the secret
         let k = ZKP::generate_random_number_less_than(&q);
@@ -152,15 +307,15 @@ mod test {
        
        let alpha = BigUint::from_bytes_be(&hex::decode("A4D1CBD5C3FD34126765A442EFB99905F8104DD258AC507FD6406CFF14266D31266FEA1E5C41564B777E690F5504F213160217B4B01B886A5E91547F9E2749F4D7FBD7D3B9A92EE1909D0D2263F80A76A6A24C087A091F531DBF0A0169B6A28AD662A4D18E73AFA32D779D5918D08BC8858F4DCEF97C2A24855E6EEB22B3B2E5").unwrap());
        
-       //since alpha is a generator, alpha^x is a generator, this is a property in group theory
-       let beta = alpha.modpow(&ZKP::generate_random_number_less_than(&q), &p);
-
-       let zkp = ZKP {
-        p:p.clone(), 
-        q: q.clone(), 
-        alpha: alpha.clone(),
-        beta: beta.clone()
-        };
+       //beta is hashed into the subgroup, a random power of alpha would make its discrete log known
+       let beta = hash_to_group::hash_to_subgroup(&p, &q, b"zkp_auth/test_1024/beta").unwrap();
+
+       let zkp = ZKP::new(ModpGroup::new(
+        p.clone(), 
+        q.clone(), 
+        alpha.clone(),
+        beta.clone()
+        ));
          
 
         let x = ZKP::generate_random_number_less_than(&q); //the secret
@@ -180,6 +335,146 @@ mod test {
         assert!(result);
 
     }
+
+    #[test]
+    fn test_non_interactive() {
+        let (alpha, beta, p, q) = ZKP::get_zkp_constants().unwrap();
+        let zkp = ZKP::new(ModpGroup::new(p.clone(), q.clone(), alpha.clone(), beta.clone()));
+
+        let x = ZKP::generate_random_number_less_than(&q); //the secret
+        let y1 = ZKP::exponentiate(&alpha, &x, &p);
+        let y2 = ZKP::exponentiate(&beta, &x, &p);
+
+        let context = b"did:zkp:test";
+        let (r1, r2, s) = zkp.prove_non_interactive(&x, context);
+        assert!(zkp.verify_non_interactive(&r1, &r2, &y1, &y2, &s, context));
+
+        //the proof is bound to its context
+        assert!(!zkp.verify_non_interactive(&r1, &r2, &y1, &y2, &s, b"did:zkp:other"));
+
+        //false secret
+        let x_fake = ZKP::generate_random_number_less_than(&q);
+        let (r1_fake, r2_fake, s_fake) = zkp.prove_non_interactive(&x_fake, context);
+        assert!(!zkp.verify_non_interactive(&r1_fake, &r2_fake, &y1, &y2, &s_fake, context));
+    }
+
+    #[test]
+    fn test_challenge_hash_is_deterministic() {
+        let (alpha, beta, p, q) = ZKP::get_zkp_constants().unwrap();
+        let zkp = ZKP::new(ModpGroup::new(p.clone(), q.clone(), alpha.clone(), beta.clone()));
+
+        let y1 = ZKP::exponentiate(&alpha, &BigUint::from(5u32), &p);
+        let y2 = ZKP::exponentiate(&beta, &BigUint::from(5u32), &p);
+        let r1 = ZKP::exponentiate(&alpha, &BigUint::from(7u32), &p);
+        let r2 = ZKP::exponentiate(&beta, &BigUint::from(7u32), &p);
+
+        let c1 = zkp.challenge_hash(&y1, &y2, &r1, &r2, b"ctx");
+        let c2 = zkp.challenge_hash(&y1, &y2, &r1, &r2, b"ctx");
+        assert_eq!(c1, c2);
+        assert!(c1 < q);
+
+        //swapping the commitments changes the challenge
+        assert_ne!(c1, zkp.challenge_hash(&y1, &y2, &r2, &r1, b"ctx"));
+    }
+
+    #[test]
+    fn test_derive_nonce_known_answers() {
+        //pins the derivation down, a change here changes every nonce a wallet makes
+        let zkp = ZKP::new(parameter_set(RFC5114_1024_GROUP_ID).unwrap().group().unwrap());
+        let x = BigUint::from(5u32);
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+        let k = zkp.derive_nonce(&x, &y1, &y2, b"did:zkp:test", &[0u8; NONCE_RANDOMNESS_LEN]);
+        assert_eq!(hex::encode(zkp.group.scalar_to_bytes(&k)), "3792f65edd4b21b2ee3dddf6a1da40aed11459da");
+
+        let zkp = ZKP::new(Ristretto255Group::new());
+        let x = zkp.group.scalar_from_hash(b"zkp_auth/test/secret");
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+        let k = zkp.derive_nonce(&x, &y1, &y2, b"did:zkp:test", &[0u8; NONCE_RANDOMNESS_LEN]);
+        assert_eq!(hex::encode(zkp.group.scalar_to_bytes(&k)), "d6d2cba1f2b5296ece9c1480cf437907b414f748f6f70a5e02ad7ad348cac80c");
+    }
+
+    #[test]
+    fn test_derive_nonce_is_hedged() {
+        let zkp = ZKP::new(ZKP::default_group().unwrap());
+        let x = zkp.group.random_scalar();
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+        let k = |context: &[u8], randomness: &[u8]| zkp.derive_nonce(&x, &y1, &y2, context, randomness);
+
+        //deterministic in its inputs
+        assert_eq!(k(b"a", &[1; 32]), k(b"a", &[1; 32]));
+        //fresh randomness gives a fresh nonce for the same proof
+        assert_ne!(k(b"a", &[1; 32]), k(b"a", &[2; 32]));
+        //with an RNG stuck on one output a new context still gives a new nonce
+        assert_ne!(k(b"a", &[0; 32]), k(b"b", &[0; 32]));
+        //and so does another secret
+        let other = zkp.group.random_scalar();
+        assert_ne!(k(b"a", &[0; 32]), zkp.derive_nonce(&other, &y1, &y2, b"a", &[0; 32]));
+
+        //commit gives a nonce that opens the commitment
+        let (r1, r2, k) = zkp.commit(&x, &y1, &y2, b"a");
+        assert_eq!(zkp.exponentiate_pair(&k), (r1, r2));
+    }
+
+    #[test]
+    fn test_seeded_rng_replays_proof() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let run = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let zkp = ZKP::new(ZKP::default_group().unwrap());
+            let x = zkp.group.random_scalar_with(&mut rng);
+            let (r1, r2, s) = zkp.prove_non_interactive_with(&mut rng, &x, b"ctx");
+            (x, r1, r2, s, ZKP::generate_random_string_with(&mut rng, 12))
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+
+        let zkp = ZKP::new(Ristretto255Group::new());
+        let draw = || zkp.group.random_scalar_with(&mut StdRng::seed_from_u64(3));
+        assert_eq!(draw(), draw());
+    }
+
+    #[test]
+    fn test_extract_witness_from_reused_nonce() {
+        let zkp = ZKP::new(ZKP::default_group().unwrap());
+        let x = zkp.group.random_scalar();
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+
+        //the same k answering two different challenges
+        let k = zkp.group.random_scalar();
+        let (r1, r2) = zkp.exponentiate_pair(&k);
+        let (c1, c2) = (zkp.group.random_scalar(), zkp.group.random_scalar());
+        let (s1, s2) = (zkp.solve(&k, &c1, &x), zkp.solve(&k, &c2, &x));
+        assert!(zkp.verify_solution(&r1, &r2, &y1, &y2, &c1, &s1));
+        assert!(zkp.verify_solution(&r1, &r2, &y1, &y2, &c2, &s2));
+
+        //both transcripts verify, and together they give away the secret
+        let recovered = zkp.extract_witness(&c1, &s1, &c2, &s2).unwrap();
+        assert_eq!(recovered, x);
+        assert_eq!(zkp.exponentiate_pair(&recovered), (y1, y2));
+
+        //the same challenge twice reveals nothing
+        assert_eq!(zkp.extract_witness(&c1, &s1, &c1, &s1), None);
+    }
+
+    #[test]
+    fn test_extract_witness_non_interactive() {
+        //a reused nonce in two Fiat-Shamir proofs for different contexts, e.g. a broken RNG
+        let zkp = ZKP::new(Ristretto255Group::new());
+        let x = zkp.group.random_scalar();
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+
+        let k = zkp.group.random_scalar();
+        let (r1, r2) = zkp.exponentiate_pair(&k);
+        let c1 = zkp.challenge_hash(&y1, &y2, &r1, &r2, b"login 1");
+        let c2 = zkp.challenge_hash(&y1, &y2, &r1, &r2, b"login 2");
+        let (s1, s2) = (zkp.solve(&k, &c1, &x), zkp.solve(&k, &c2, &x));
+        assert!(zkp.verify_non_interactive(&r1, &r2, &y1, &y2, &s1, b"login 1"));
+        assert!(zkp.verify_non_interactive(&r1, &r2, &y1, &y2, &s2, b"login 2"));
+
+        assert_eq!(zkp.extract_witness(&c1, &s1, &c2, &s2), Some(x));
+    }
     }
  
 //    The hexadecimal value of the prime is:
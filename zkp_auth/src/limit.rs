@@ -0,0 +1,454 @@
+//Limits on how much one client can make the server do.
+//Every request uses a token from the bucket of the address it came from, this is the tower
+//layer PeerLimitLayer in front of the gRPC service. Challenges and logins also use a token from
+//the bucket of the DID they are for as seen from their address, the server takes those once it
+//knows the DID is registered, so unknown DIDs do not fill the map. A bucket holds at most
+//`burst` tokens and gets `per_minute` back each minute; a request that finds it empty gets
+//RESOURCE_EXHAUSTED.
+//
+//Anyone who knows a DID can ask for its challenges and send failing proofs for it, so
+//everything kept per DID is kept for the address and the DID together: the DID bucket, the
+//open challenges and the failed proofs. Whoever floods a DID or fails proofs for it only
+//limits or locks out their own address and the prover goes on logging in from its own;
+//requests without an address share one count. Behind those each DID has a ceiling, a bucket
+//and a cap on open challenges well above what one address gets, so many addresses together
+//can not make the server do without end for one DID.
+//Once lockout_after proofs fail in a row an address is locked out of the DID for `lockout`,
+//every further failure doubles that up to max_lockout, a proof that verifies from it ends it.
+//Open challenges are capped per address and DID, per DID and overall, a cap can be passed by
+//the requests that check it at the same time, never by more than that.
+//The counters behind GetMetrics are kept here too.
+
+use crate::error::{Result, ZkpAuthError};
+use crate::session::to_chrono;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tonic::body::BoxBody;
+use tonic::codegen::BoxFuture;
+use tonic::codegen::http;
+use tonic::transport::server::TcpConnectInfo;
+use tower::{Layer, Service};
+
+//requests from one address
+pub const PEER_RATE: Rate = Rate { burst: 50, per_minute: 600 };
+//challenges and logins for one DID from one address
+pub const DID_RATE: Rate = Rate { burst: 10, per_minute: 60 };
+//challenges and logins for one DID from all addresses together
+pub const DID_CEILING: Rate = Rate { burst: 200, per_minute: 1200 };
+//failed proofs in a row before a DID is locked out
+pub const LOCKOUT_AFTER: u32 = 5;
+//the first lockout, doubled with every further failure
+pub const LOCKOUT: Duration = Duration::from_secs(30);
+pub const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+//open challenges of one DID from one address, of one DID, and of all of them
+pub const MAX_PENDING_PER_DID: usize = 8;
+pub const PENDING_DID_CEILING: usize = 256;
+pub const MAX_PENDING: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitPolicy {
+    pub per_peer: Rate,
+    pub per_did: Rate,  // For one DID from one address
+    pub did_ceiling: Rate,  // For one DID from all addresses
+    pub lockout_after: u32,
+    pub lockout: Duration,
+    pub max_lockout: Duration,
+    pub max_pending_per_did: usize,  // For one DID from one address
+    pub pending_did_ceiling: usize,  // For one DID from all addresses
+    pub max_pending: usize,
+}
+
+impl Default for LimitPolicy {
+    fn default() -> Self {
+        Self {
+            per_peer: PEER_RATE,
+            per_did: DID_RATE,
+            did_ceiling: DID_CEILING,
+            lockout_after: LOCKOUT_AFTER,
+            lockout: LOCKOUT,
+            max_lockout: MAX_LOCKOUT,
+            max_pending_per_did: MAX_PENDING_PER_DID,
+            pending_did_ceiling: PENDING_DID_CEILING,
+            max_pending: MAX_PENDING,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    at: DateTime<Utc>,
+}
+
+impl Bucket {
+    fn full(rate: Rate, now: DateTime<Utc>) -> Self {
+        Self { tokens: rate.burst as f64, at: now }
+    }
+
+    //the tokens at now, refilled since the last take
+    fn tokens(&self, rate: Rate, now: DateTime<Utc>) -> f64 {
+        let minutes = (now - self.at).num_milliseconds().max(0) as f64 / 60_000.0;
+        (self.tokens + minutes * rate.per_minute as f64).min(rate.burst as f64)
+    }
+
+    fn take(&mut self, rate: Rate, now: DateTime<Utc>) -> bool {
+        let tokens = self.tokens(rate, now);
+        self.at = now.max(self.at);
+        self.tokens = if tokens >= 1.0 { tokens - 1.0 } else { tokens };
+        tokens >= 1.0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    limited_peers: AtomicU64,
+    limited_dids: AtomicU64,
+    lockouts: AtomicU64,
+    refused_locked: AtomicU64,
+    refused_pending: AtomicU64,
+}
+
+//what the limits did since the server started, and what they track now
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub limited_peers: u64,  // requests refused by a peer bucket
+    pub limited_dids: u64,  // challenges and logins refused by a DID bucket or a DID's ceiling
+    pub lockouts: u64,  // times an address was locked out of a DID
+    pub refused_locked: u64,  // requests for a DID from an address that was locked out of it
+    pub refused_pending: u64,  // challenges refused by a pending cap
+    pub peers: usize,  // addresses with a bucket
+    pub dids: usize,  // DIDs with a bucket, once for every address they are asked for from
+    pub locked: usize,  // addresses locked out of a DID now
+}
+
+//a DID as seen from one address, None for a request without a TCP peer address
+type Source = (Option<IpAddr>, String);
+
+#[derive(Debug)]
+pub struct Limiter {
+    policy: LimitPolicy,
+    peers: DashMap<IpAddr, Bucket>,
+    dids: DashMap<Source, Bucket>,
+    ceilings: DashMap<String, Bucket>,
+    failures: DashMap<Source, Failures>,
+    counters: Counters,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new(LimitPolicy::default())
+    }
+}
+
+impl Limiter {
+    pub fn new(policy: LimitPolicy) -> Self {
+        Self { policy, peers: DashMap::new(), dids: DashMap::new(), ceilings: DashMap::new(), failures: DashMap::new(), counters: Counters::default() }
+    }
+
+    pub fn policy(&self) -> &LimitPolicy {
+        &self.policy
+    }
+
+    pub fn check_peer(&self, peer: IpAddr, now: DateTime<Utc>) -> Result<()> {
+        let rate = self.policy.per_peer;
+        if !self.peers.entry(peer).or_insert_with(|| Bucket::full(rate, now)).take(rate, now) {
+            self.counters.limited_peers.fetch_add(1, Ordering::Relaxed);
+            return Err(ZkpAuthError::Overloaded(format!("Too many requests from {}, slow down", peer)));
+        }
+        Ok(())
+    }
+
+    //a challenge or login for a registered DID from peer
+    //the ceiling only gives a token once the bucket of peer did, a flooding address empties its own
+    pub fn check_did(&self, did: &str, peer: Option<IpAddr>, now: DateTime<Utc>) -> Result<()> {
+        self.check_lockout(did, peer, now)?;
+        let (rate, ceiling) = (self.policy.per_did, self.policy.did_ceiling);
+        let refused = if !self.dids.entry((peer, did.to_string())).or_insert_with(|| Bucket::full(rate, now)).take(rate, now) {
+            format!("Too many logins for DID: {} from this address, slow down", did)
+        } else if !self.ceilings.entry(did.to_string()).or_insert_with(|| Bucket::full(ceiling, now)).take(ceiling, now) {
+            format!("Too many logins for DID: {}, try again later", did)
+        } else {
+            return Ok(());
+        };
+        self.counters.limited_dids.fetch_add(1, Ordering::Relaxed);
+        Err(ZkpAuthError::Overloaded(refused))
+    }
+
+    pub fn check_lockout(&self, did: &str, peer: Option<IpAddr>, now: DateTime<Utc>) -> Result<()> {
+        if let Some(failures) = self.failures.get(&(peer, did.to_string()))
+            && let Some(until) = failures.locked_until
+            && now < until
+        {
+            self.counters.refused_locked.fetch_add(1, Ordering::Relaxed);
+            return Err(ZkpAuthError::Overloaded(format!(
+                "DID: {} is locked out for this address until {} after {} failed proofs", did, until.to_rfc3339(), failures.count)));
+        }
+        Ok(())
+    }
+
+    //one more challenge for a DID that has `from_peer` open ones asked from the same address and
+    //`pending` in all, with `total` open overall, see ChallengeStore::pending
+    pub fn check_pending(&self, did: &str, (from_peer, pending): (usize, usize), total: usize) -> Result<()> {
+        let refused = if from_peer >= self.policy.max_pending_per_did {
+            format!("DID: {} has {} open challenges from this address, answer one first", did, from_peer)
+        } else if pending >= self.policy.pending_did_ceiling {
+            format!("DID: {} has {} open challenges, try again later", did, pending)
+        } else if total >= self.policy.max_pending {
+            "The server has too many open challenges, try again later".to_string()
+        } else {
+            return Ok(());
+        };
+        self.counters.refused_pending.fetch_add(1, Ordering::Relaxed);
+        Err(ZkpAuthError::Overloaded(refused))
+    }
+
+    //a proof for the DID from peer failed, returns when peer is locked out until if this locked it
+    pub fn failed(&self, did: &str, peer: Option<IpAddr>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut failures = self.failures.entry((peer, did.to_string())).or_insert(Failures { count: 0, last: now, locked_until: None });
+        failures.count += 1;
+        failures.last = now;
+        let beyond = failures.count.checked_sub(self.policy.lockout_after)?;
+        let lockout = self.policy.lockout.checked_mul(1 << beyond.min(31)).unwrap_or(self.policy.max_lockout).min(self.policy.max_lockout);
+        let until = now + to_chrono(lockout);
+        failures.locked_until = Some(until);
+        self.counters.lockouts.fetch_add(1, Ordering::Relaxed);
+        Some(until)
+    }
+
+    //a proof for the DID from peer verified, the failures from there are forgiven
+    pub fn succeeded(&self, did: &str, peer: Option<IpAddr>) {
+        self.failures.remove(&(peer, did.to_string()));
+    }
+
+    //drops the buckets that refilled, they are the same as none, and the failures that are
+    //max_lockout old and lock nothing; returns how many entries were dropped
+    pub fn sweep(&self, now: DateTime<Utc>) -> usize {
+        let before = self.peers.len() + self.dids.len() + self.ceilings.len() + self.failures.len();
+        let (peer, did, ceiling) = (self.policy.per_peer, self.policy.per_did, self.policy.did_ceiling);
+        self.peers.retain(|_, bucket| bucket.tokens(peer, now) < peer.burst as f64);
+        self.dids.retain(|_, bucket| bucket.tokens(did, now) < did.burst as f64);
+        self.ceilings.retain(|_, bucket| bucket.tokens(ceiling, now) < ceiling.burst as f64);
+        let forget = to_chrono(self.policy.max_lockout);
+        self.failures.retain(|_, failures| failures.locked_until.is_some_and(|until| now < until) || now < failures.last + forget);
+        before - (self.peers.len() + self.dids.len() + self.ceilings.len() + self.failures.len())
+    }
+
+    pub fn metrics(&self, now: DateTime<Utc>) -> Metrics {
+        let counter = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Metrics {
+            limited_peers: counter(&self.counters.limited_peers),
+            limited_dids: counter(&self.counters.limited_dids),
+            lockouts: counter(&self.counters.lockouts),
+            refused_locked: counter(&self.counters.refused_locked),
+            refused_pending: counter(&self.counters.refused_pending),
+            peers: self.peers.len(),
+            dids: self.dids.len(),
+            locked: self.failures.iter().filter(|failures| failures.locked_until.is_some_and(|until| now < until)).count(),
+        }
+    }
+}
+
+//the per-peer limit as a tower layer, e.g. Server::builder().layer(PeerLimitLayer::new(limiter))
+//a request without a TCP peer address (a test, a unix socket) is not limited
+#[derive(Debug, Clone)]
+pub struct PeerLimitLayer {
+    limiter: Arc<Limiter>,
+}
+
+impl PeerLimitLayer {
+    pub fn new(limiter: Arc<Limiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for PeerLimitLayer {
+    type Service = PeerLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PeerLimit { inner, limiter: self.limiter.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerLimit<S> {
+    inner: S,
+    limiter: Arc<Limiter>,
+}
+
+impl<S, B> Service<http::Request<B>> for PeerLimit<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    //a refused request is answered here with its gRPC status, the service never sees it
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let peer = request.extensions().get::<TcpConnectInfo>().and_then(TcpConnectInfo::remote_addr);
+        if let Some(peer) = peer
+            && let Err(e) = self.limiter.check_peer(peer.ip(), Utc::now())
+        {
+            let response = tonic::Status::from(e).to_http();
+            return Box::pin(async move { Ok(response) });
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tower::ServiceExt;
+
+    fn after(now: DateTime<Utc>, millis: i64) -> DateTime<Utc> {
+        now + chrono::Duration::milliseconds(millis)
+    }
+
+    fn policy() -> LimitPolicy {
+        LimitPolicy {
+            per_peer: Rate { burst: 2, per_minute: 60 },
+            per_did: Rate { burst: 1, per_minute: 6 },
+            did_ceiling: Rate { burst: 3, per_minute: 6 },
+            lockout_after: 2,
+            lockout: Duration::from_secs(10),
+            max_lockout: Duration::from_secs(35),
+            max_pending_per_did: 2,
+            pending_did_ceiling: 3,
+            max_pending: 4,
+        }
+    }
+
+    #[test]
+    fn test_buckets_refill() {
+        let limiter = Limiter::new(policy());
+        let now = Utc::now();
+        let (a, b) = (IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2]));
+        assert!(limiter.check_peer(a, now).is_ok());
+        assert!(limiter.check_peer(a, now).is_ok());
+        assert!(matches!(limiter.check_peer(a, now), Err(ZkpAuthError::Overloaded(_))));
+        //another address has its own bucket, and one token comes back each second
+        assert!(limiter.check_peer(b, now).is_ok());
+        assert!(limiter.check_peer(a, after(now, 999)).is_err());
+        assert!(limiter.check_peer(a, after(now, 1000)).is_ok());
+
+        assert!(limiter.check_did("did:zkp:a", None, now).is_ok());
+        assert!(limiter.check_did("did:zkp:a", None, after(now, 9_999)).is_err());
+        assert!(limiter.check_did("did:zkp:a", None, after(now, 10_000)).is_ok());
+        assert_eq!((limiter.metrics(now).limited_peers, limiter.metrics(now).limited_dids), (2, 1));
+
+        //a bucket that refilled is dropped
+        assert_eq!(limiter.sweep(after(now, 1500)), 1);
+        assert_eq!(limiter.sweep(after(now, 60_000)), 3);
+        assert_eq!(limiter.metrics(now).peers + limiter.metrics(now).dids, 0);
+    }
+
+    #[test]
+    fn test_did_buckets_are_per_address_below_a_ceiling() {
+        let limiter = Limiter::new(policy());
+        let now = Utc::now();
+        let address = |last: u8| Some(IpAddr::from([10, 0, 0, last]));
+        assert!(limiter.check_did("did:zkp:a", address(1), now).is_ok());
+        //an address that emptied its bucket does not touch the ceiling or the other addresses
+        for _ in 0..5 {
+            assert!(limiter.check_did("did:zkp:a", address(1), now).is_err());
+        }
+        assert!(limiter.check_did("did:zkp:a", address(2), now).is_ok());
+        assert!(limiter.check_did("did:zkp:a", None, now).is_ok());
+        //three addresses took the three tokens of the ceiling, a fourth has to wait for it
+        let status = limiter.check_did("did:zkp:a", address(3), now).unwrap_err();
+        assert_eq!(status.reason(), "Too many logins for DID: did:zkp:a, try again later");
+        assert!(limiter.check_did("did:zkp:b", address(3), now).is_ok());
+        assert!(limiter.check_did("did:zkp:a", address(3), after(now, 10_000)).is_ok());
+        let metrics = limiter.metrics(now);
+        assert_eq!((metrics.limited_dids, metrics.dids), (6, 5));
+    }
+
+    #[test]
+    fn test_lockout_doubles_up_to_the_maximum() {
+        let limiter = Limiter::new(policy());
+        let now = Utc::now();
+        let peer = Some(IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(limiter.failed("did:zkp:a", peer, now), None);
+        assert_eq!(limiter.failed("did:zkp:a", peer, now), Some(after(now, 10_000)));
+        assert!(matches!(limiter.check_did("did:zkp:a", peer, after(now, 9_999)), Err(ZkpAuthError::Overloaded(_))));
+        assert!(limiter.check_lockout("did:zkp:a", peer, after(now, 10_000)).is_ok());
+        assert_eq!(limiter.failed("did:zkp:a", peer, now), Some(after(now, 20_000)));
+        assert_eq!(limiter.failed("did:zkp:a", peer, now), Some(after(now, 35_000)));
+        let metrics = limiter.metrics(now);
+        assert_eq!((metrics.lockouts, metrics.refused_locked, metrics.locked), (3, 1, 1));
+
+        //a proof that verifies ends it, the count starts over
+        limiter.succeeded("did:zkp:a", peer);
+        assert!(limiter.check_lockout("did:zkp:a", peer, now).is_ok());
+        assert_eq!(limiter.failed("did:zkp:a", peer, now), None);
+        //failures are forgotten once they are max_lockout old
+        assert_eq!(limiter.sweep(after(now, 34_999)), 0);
+        assert_eq!(limiter.sweep(after(now, 35_000)), 1);
+    }
+
+    #[test]
+    fn test_failures_from_one_address_do_not_lock_out_another() {
+        let limiter = Limiter::new(policy());
+        let now = Utc::now();
+        let (attacker, prover) = (Some(IpAddr::from([10, 0, 0, 1])), Some(IpAddr::from([10, 0, 0, 2])));
+        for _ in 0..4 {
+            limiter.failed("did:zkp:a", attacker, now);
+        }
+        assert!(limiter.check_lockout("did:zkp:a", attacker, now).is_err());
+        //the prover's address, and requests without one, are not locked out of the DID
+        assert!(limiter.check_lockout("did:zkp:a", prover, now).is_ok());
+        assert!(limiter.check_lockout("did:zkp:a", None, now).is_ok());
+        //nor is the attacker's address out of other DIDs
+        assert!(limiter.check_lockout("did:zkp:b", attacker, now).is_ok());
+        //a proof that verifies from the prover forgives nothing the attacker did
+        limiter.succeeded("did:zkp:a", prover);
+        assert!(limiter.check_lockout("did:zkp:a", attacker, now).is_err());
+        assert_eq!(limiter.metrics(now).locked, 1);
+    }
+
+    #[test]
+    fn test_pending_caps() {
+        let limiter = Limiter::new(policy());
+        assert!(limiter.check_pending("did:zkp:a", (1, 1), 1).is_ok());
+        assert!(matches!(limiter.check_pending("did:zkp:a", (2, 2), 2), Err(ZkpAuthError::Overloaded(_))));
+        //the challenges other addresses hold only count against the ceiling of the DID
+        assert!(limiter.check_pending("did:zkp:a", (0, 2), 2).is_ok());
+        assert!(matches!(limiter.check_pending("did:zkp:a", (0, 3), 3), Err(ZkpAuthError::Overloaded(_))));
+        assert!(matches!(limiter.check_pending("did:zkp:b", (0, 0), 4), Err(ZkpAuthError::Overloaded(_))));
+        assert_eq!(limiter.metrics(Utc::now()).refused_pending, 3);
+    }
+
+    #[tokio::test]
+    async fn test_layer_lets_requests_without_a_peer_through() {
+        let limiter = Arc::new(Limiter::new(LimitPolicy { per_peer: Rate { burst: 0, per_minute: 1 }, ..policy() }));
+        let service = PeerLimitLayer::new(limiter.clone())
+            .layer(tower::service_fn(|_: http::Request<()>| async { Ok::<_, std::convert::Infallible>(http::Response::new(tonic::body::empty_body())) }));
+        let response = service.oneshot(http::Request::new(())).await.unwrap();
+        assert!(response.headers().get("grpc-status").is_none());
+        assert_eq!(limiter.metrics(Utc::now()), Metrics::default());
+    }
+}
@@ -0,0 +1,90 @@
+//What a non-interactive login is made for.
+//The challenge of a Login proof is hashed from a context, and the server only accepts contexts
+//it can read and check: the login domain, the DID, the time the proof was made and a random nonce,
+//every field length prefixed like the registration contexts. A context in any other form is
+//refused, so a registration or key update proof never verifies as a login, and so is one made
+//more than LOGIN_SKEW away from the server's clock.
+//Within that window the same request could be sent twice, so once a login with a context
+//succeeded the server retires it in the challenge store (ChallengeStore::retire) and refuses it
+//from then on. The commitment history plays no part in this, it is bounded and only there to
+//catch a reused nonce.
+
+use crate::error::{Result, ZkpAuthError};
+use crate::registration;
+use chrono::{DateTime, Utc};
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+const LOGIN_DOMAIN: &[u8] = b"zkp_auth/login/v1";
+//how far the time in a context may be from the server's clock, either way
+pub const LOGIN_SKEW: Duration = Duration::from_secs(60);
+pub const NONCE_LEN: usize = 16;
+
+//the context of a login by did at unix time `at`
+pub fn login_context(did: &str, at: i64, nonce: &[u8; NONCE_LEN]) -> Vec<u8> {
+    registration::context(LOGIN_DOMAIN, &[did.as_bytes(), &at.to_be_bytes(), nonce])
+}
+
+//a fresh context for a login now, for the prover
+pub fn new_login_context(did: &str, now: DateTime<Utc>) -> Vec<u8> {
+    new_login_context_with(&mut OsRng, did, now)
+}
+
+pub fn new_login_context_with<R: RngCore + CryptoRng + ?Sized>(rng: &mut R, did: &str, now: DateTime<Utc>) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+    login_context(did, now.timestamp(), &nonce)
+}
+
+//checks the context is a login context for did made within LOGIN_SKEW of now
+//returns the key the context is retired under once the login succeeded
+pub fn check_context(context: &[u8], did: &str, now: DateTime<Utc>) -> Result<String> {
+    let not_a_login = || ZkpAuthError::Encoding(format!("The context is not a login context for DID: {}", did));
+    //the fields sit at fixed offsets once the DID is known, the context is rebuilt from them
+    let at_offset = LOGIN_DOMAIN.len() + 4 + did.len() + 4;
+    if context.len() != at_offset + 8 + 4 + NONCE_LEN {
+        return Err(not_a_login());
+    }
+    let at = i64::from_be_bytes(context[at_offset..at_offset + 8].try_into().map_err(|_| not_a_login())?);
+    let nonce: [u8; NONCE_LEN] = context[at_offset + 12..].try_into().map_err(|_| not_a_login())?;
+    if login_context(did, at, &nonce) != context {
+        return Err(not_a_login());
+    }
+
+    let skew = LOGIN_SKEW.as_secs() as i64;
+    if (now.timestamp() - at).abs() > skew {
+        return Err(ZkpAuthError::Expired(format!("The login proof for DID: {} was made at {}, more than {}s from the server's clock", did, at, skew)));
+    }
+    Ok(format!("login:{}", hex::encode(Sha256::digest(context))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_login_contexts() {
+        let now = Utc::now();
+        let context = new_login_context("did:zkp:a", now);
+        let key = check_context(&context, "did:zkp:a", now).unwrap();
+        assert!(key.starts_with("login:"));
+        assert_ne!(key, check_context(&new_login_context("did:zkp:a", now), "did:zkp:a", now).unwrap());
+
+        //only for its own DID and only close to the time it was made
+        assert!(matches!(check_context(&context, "did:zkp:b", now), Err(ZkpAuthError::Encoding(_))));
+        assert!(check_context(&context, "did:zkp:a", now + chrono::Duration::seconds(60)).is_ok());
+        assert!(matches!(check_context(&context, "did:zkp:a", now + chrono::Duration::seconds(61)), Err(ZkpAuthError::Expired(_))));
+        assert!(matches!(check_context(&context, "did:zkp:a", now - chrono::Duration::seconds(61)), Err(ZkpAuthError::Expired(_))));
+
+        //the contexts of other proofs, free-form ones and a changed domain are refused
+        let did = "did:zkp:a";
+        let mut other_domain = context.clone();
+        other_domain[LOGIN_DOMAIN.len() - 1] = b'2';
+        for context in [registration::registration_context(did), registration::key_update_context(did, &[1], &[2]),
+            format!("login:{}:{}", did, now.timestamp()).into_bytes(), Vec::new(), other_domain] {
+            assert!(matches!(check_context(&context, did, now), Err(ZkpAuthError::Encoding(_))));
+        }
+    }
+}
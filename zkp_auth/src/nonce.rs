@@ -0,0 +1,113 @@
+//RFC 6979 nonce generation (section 3.2) with the extra data of section 3.6.
+//k is the output of HMAC_DRBG (HMAC-SHA256) keyed with the secret x and the
+//message representative h, so it is a function of what is being proved and
+//not only of the RNG. ZKP::derive_nonce passes fresh randomness as the extra
+//data, this "hedges" the derivation: a good RNG gives unpredictable nonces as
+//usual, a broken or forked one still cannot make two different proofs share k.
+//
+//The generation is generic over the group, x and k use the group's canonical
+//scalar encoding in place of int2octets. For the MODP groups that is exactly
+//int2octets so the RFC's own test vectors apply, see the tests below.
+
+use crate::group::Group;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+//k in [1, q-1] for the secret x and the message representative h (bits2int(H(m)) mod q)
+//extra is k' of section 3.6, empty gives the plain deterministic nonce
+pub fn generate_k<G: Group>(group: &G, x: &G::Scalar, h: &G::Scalar, extra: &[u8]) -> G::Scalar {
+    let x = group.scalar_to_bytes(x);
+    let h = group.scalar_to_bytes(h);
+    let rlen = group.scalar_len();
+
+    //steps b to g
+    let mut v = [0x01u8; 32];
+    let mut k = hmac(&[0x00u8; 32], &[&v, &[0x00], &x, &h, extra]);
+    v = hmac(&k, &[&v]);
+    k = hmac(&k, &[&v, &[0x01], &x, &h, extra]);
+    v = hmac(&k, &[&v]);
+
+    //step h, a candidate that is zero or not less than q is thrown away and the state moved on
+    loop {
+        let mut t = Vec::with_capacity(rlen + 32);
+        while t.len() < rlen {
+            v = hmac(&k, &[&v]);
+            t.extend_from_slice(&v);
+        }
+        if let Some(candidate) = group.scalar_from_bits(&t)
+            && group.scalar_to_bytes(&candidate).iter().any(|b| *b != 0)
+        {
+            return candidate;
+        }
+        k = hmac(&k, &[&v, &[0x00]]);
+        v = hmac(&k, &[&v]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::group::bits_to_int;
+    use crate::ModpGroup;
+    use num_bigint::BigUint;
+    use sha2::Digest;
+
+    fn int(hex: &str) -> BigUint {
+        BigUint::parse_bytes(hex.as_bytes(), 16).unwrap()
+    }
+
+    //only q is used to derive k, p and the generators are placeholders
+    fn order(q: &str) -> ModpGroup {
+        let one = BigUint::from(1u32);
+        ModpGroup::new(int(q), int(q), one.clone(), one)
+    }
+
+    //h = bits2int(SHA-256(message)) mod q, as the RFC computes it
+    fn representative(group: &ModpGroup, message: &[u8]) -> BigUint {
+        bits_to_int(&Sha256::digest(message), group.q.bits()) % &group.q
+    }
+
+    #[test]
+    fn test_rfc6979_detailed_example() {
+        //appendix A.1, a 163-bit q so bits2int has to drop bits
+        let group = order("4000000000000000000020108A2E0CC0D99F8A5EF");
+        let x = int("09A4D6792295A7F730FC3F2B49CBC0F62E862272F");
+        let h = representative(&group, b"sample");
+        assert_eq!(generate_k(&group, &x, &h, b""), int("23AF4074C90A02B3FE61D286D5C87F425E6BDD81B"));
+    }
+
+    #[test]
+    fn test_rfc6979_p256_sha256() {
+        //appendix A.2.5, the order of P-256
+        let group = order("FFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632551");
+        let x = int("C9AFA9D845BA75166B5C215767B1D6934E50C3DB36E89B127B8A622B120F6721");
+
+        let h = representative(&group, b"sample");
+        assert_eq!(generate_k(&group, &x, &h, b""), int("A6E3C57DD01ABE90086538398355DD4C3B17AA873382B0F24D6129493D8AAD60"));
+        let h = representative(&group, b"test");
+        assert_eq!(generate_k(&group, &x, &h, b""), int("D16B6AE827F17175E040871A1C7EC3500192C4C92677336EC2537ACAEE0008E0"));
+    }
+
+    #[test]
+    fn test_extra_data_changes_k() {
+        let group = order("FFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632551");
+        let x = int("C9AFA9D845BA75166B5C215767B1D6934E50C3DB36E89B127B8A622B120F6721");
+        let h = representative(&group, b"sample");
+
+        let plain = generate_k(&group, &x, &h, b"");
+        let hedged = generate_k(&group, &x, &h, &[7u8; 32]);
+        assert_ne!(plain, hedged);
+        assert_eq!(hedged, generate_k(&group, &x, &h, &[7u8; 32]));
+        assert!(hedged < group.q);
+    }
+}
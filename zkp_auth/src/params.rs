@@ -0,0 +1,327 @@
+//Named, standardised parameter sets for the multiplicative group mod p.
+//Every set is looked up by an identifier, which is what the wallet stores
+//and what the client sends to the server in each request.
+
+use crate::error::{Result, ZkpAuthError};
+use crate::group::{Group, ModpGroup};
+use crate::hash_to_group::{beta_seed, hash_to_subgroup};
+use num_bigint::BigUint;
+use std::sync::LazyLock;
+
+pub const RFC5114_1024_GROUP_ID: &str = "rfc5114-1024-160";
+pub const RFC5114_2048_224_GROUP_ID: &str = "rfc5114-2048-224";
+pub const RFC5114_2048_256_GROUP_ID: &str = "rfc5114-2048-256";
+pub const RFC3526_2048_GROUP_ID: &str = "rfc3526-2048";
+pub const RFC3526_3072_GROUP_ID: &str = "rfc3526-3072";
+pub const RFC3526_4096_GROUP_ID: &str = "rfc3526-4096";
+//sets too small for new keys, a server only allows them when they are named
+//the 1024-bit group is below the 2048 bits asked of discrete log groups today
+pub const LEGACY_GROUP_IDS: &[&str] = &[RFC5114_1024_GROUP_ID];
+
+//before beta was hashed into the subgroup it was alpha^LEGACY_BETA_EXPONENT, a power anyone knew
+//keys made then have y2 = y1^LEGACY_BETA_EXPONENT and their proofs no longer verify
+//only the legacy groups were there before, no other group can hold such keys
+pub const LEGACY_BETA_EXPONENT: &str = "266FEA1E5C41564B777E69";
+
+static LEGACY_EXPONENT: LazyLock<BigUint> =
+    LazyLock::new(|| BigUint::from_bytes_be(&hex::decode(LEGACY_BETA_EXPONENT).expect("LEGACY_BETA_EXPONENT is hex")));
+
+//a standard group, parameters are big-endian hex
+#[derive(Debug)]
+pub struct ParameterSet {
+    pub id: &'static str,
+    pub description: &'static str,
+    pub p: &'static str,
+    pub q: &'static str, //order of the subgroup alpha generates
+    pub alpha: &'static str,
+}
+
+pub const PARAMETER_SETS: &[ParameterSet] = &[
+    ParameterSet {
+        id: RFC5114_1024_GROUP_ID,
+        description: "RFC 5114 section 2.1, 1024-bit MODP group with 160-bit prime order subgroup",
+        p: concat!(
+            "B10B8F96A080E01DDE92DE5EAE5D54EC52C99FBCFB06A3C69A6A9DCA52D23B61",
+            "6073E28675A23D189838EF1E2EE652C013ECB4AEA906112324975C3CD49B83BF",
+            "ACCBDD7D90C4BD7098488E9C219A73724EFFD6FAE5644738FAA31A4FF55BCCC0",
+            "A151AF5F0DC8B4BD45BF37DF365C1A65E68CFDA76D4DA708DF1FB2BC2E4A4371",
+        ),
+        q: "F518AA8781A8DF278ABA4E7D64B7CB9D49462353",
+        alpha: concat!(
+            "A4D1CBD5C3FD34126765A442EFB99905F8104DD258AC507FD6406CFF14266D31",
+            "266FEA1E5C41564B777E690F5504F213160217B4B01B886A5E91547F9E2749F4",
+            "D7FBD7D3B9A92EE1909D0D2263F80A76A6A24C087A091F531DBF0A0169B6A28A",
+            "D662A4D18E73AFA32D779D5918D08BC8858F4DCEF97C2A24855E6EEB22B3B2E5",
+        ),
+    },
+    ParameterSet {
+        id: RFC5114_2048_224_GROUP_ID,
+        description: "RFC 5114 section 2.2, 2048-bit MODP group with 224-bit prime order subgroup",
+        p: concat!(
+            "AD107E1E9123A9D0D660FAA79559C51FA20D64E5683B9FD1B54B1597B61D0A75",
+            "E6FA141DF95A56DBAF9A3C407BA1DF15EB3D688A309C180E1DE6B85A1274A0A6",
+            "6D3F8152AD6AC2129037C9EDEFDA4DF8D91E8FEF55B7394B7AD5B7D0B6C12207",
+            "C9F98D11ED34DBF6C6BA0B2C8BBC27BE6A00E0A0B9C49708B3BF8A3170918836",
+            "81286130BC8985DB1602E714415D9330278273C7DE31EFDC7310F7121FD5A074",
+            "15987D9ADC0A486DCDF93ACC44328387315D75E198C641A480CD86A1B9E587E8",
+            "BE60E69CC928B2B9C52172E413042E9B23F10B0E16E79763C9B53DCF4BA80A29",
+            "E3FB73C16B8E75B97EF363E2FFA31F71CF9DE5384E71B81C0AC4DFFE0C10E64F",
+        ),
+        q: "801C0D34C58D93FE997177101F80535A4738CEBCBF389A99B36371EB",
+        alpha: concat!(
+            "AC4032EF4F2D9AE39DF30B5C8FFDAC506CDEBE7B89998CAF74866A08CFE4FFE3",
+            "A6824A4E10B9A6F0DD921F01A70C4AFAAB739D7700C29F52C57DB17C620A8652",
+            "BE5E9001A8D66AD7C17669101999024AF4D027275AC1348BB8A762D0521BC98A",
+            "E247150422EA1ED409939D54DA7460CDB5F6C6B250717CBEF180EB34118E98D1",
+            "19529A45D6F834566E3025E316A330EFBB77A86F0C1AB15B051AE3D428C8F8AC",
+            "B70A8137150B8EEB10E183EDD19963DDD9E263E4770589EF6AA21E7F5F2FF381",
+            "B539CCE3409D13CD566AFBB48D6C019181E1BCFE94B30269EDFE72FE9B6AA4BD",
+            "7B5A0F1C71CFFF4C19C418E1F6EC017981BC087F2A7065B384B890D3191F2BFA",
+        ),
+    },
+    ParameterSet {
+        id: RFC5114_2048_256_GROUP_ID,
+        description: "RFC 5114 section 2.3, 2048-bit MODP group with 256-bit prime order subgroup",
+        p: concat!(
+            "87A8E61DB4B6663CFFBBD19C651959998CEEF608660DD0F25D2CEED4435E3B00",
+            "E00DF8F1D61957D4FAF7DF4561B2AA3016C3D91134096FAA3BF4296D830E9A7C",
+            "209E0C6497517ABD5A8A9D306BCF67ED91F9E6725B4758C022E0B1EF4275BF7B",
+            "6C5BFC11D45F9088B941F54EB1E59BB8BC39A0BF12307F5C4FDB70C581B23F76",
+            "B63ACAE1CAA6B7902D52526735488A0EF13C6D9A51BFA4AB3AD8347796524D8E",
+            "F6A167B5A41825D967E144E5140564251CCACB83E6B486F6B3CA3F7971506026",
+            "C0B857F689962856DED4010ABD0BE621C3A3960A54E710C375F26375D7014103",
+            "A4B54330C198AF126116D2276E11715F693877FAD7EF09CADB094AE91E1A1597",
+        ),
+        q: "8CF83642A709A097B447997640129DA299B1A47D1EB3750BA308B0FE64F5FBD3",
+        alpha: concat!(
+            "3FB32C9B73134D0B2E77506660EDBD484CA7B18F21EF205407F4793A1A0BA125",
+            "10DBC15077BE463FFF4FED4AAC0BB555BE3A6C1B0C6B47B1BC3773BF7E8C6F62",
+            "901228F8C28CBB18A55AE31341000A650196F931C77A57F2DDF463E5E9EC144B",
+            "777DE62AAAB8A8628AC376D282D6ED3864E67982428EBC831D14348F6F2F9193",
+            "B5045AF2767164E1DFC967C1FB3F2E55A4BD1BFFE83B9C80D052B985D182EA0A",
+            "DB2A3B7313D3FE14C8484B1E052588B9B7D2BBD2DF016199ECD06E1557CD0915",
+            "B3353BBB64E0EC377FD028370DF92B52C7891428CDC67EB6184B523D1DB246C3",
+            "2F63078490F00EF8D647D148D47954515E2327CFEF98C582664B4C0F6CC41659",
+        ),
+    },
+    ParameterSet {
+        id: RFC3526_2048_GROUP_ID,
+        description: "RFC 3526 group 14, 2048-bit safe prime, q = (p-1)/2",
+        p: concat!(
+            "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+            "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+            "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+            "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+            "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+            "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+            "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+            "3995497CEA956AE515D2261898FA051015728E5A8AACAA68FFFFFFFFFFFFFFFF",
+        ),
+        q: concat!(
+            "7FFFFFFFFFFFFFFFE487ED5110B4611A62633145C06E0E68948127044533E63A",
+            "0105DF531D89CD9128A5043CC71A026EF7CA8CD9E69D218D98158536F92F8A1B",
+            "A7F09AB6B6A8E122F242DABB312F3F637A262174D31BF6B585FFAE5B7A035BF6",
+            "F71C35FDAD44CFD2D74F9208BE258FF324943328F6722D9EE1003E5C50B1DF82",
+            "CC6D241B0E2AE9CD348B1FD47E9267AFC1B2AE91EE51D6CB0E3179AB1042A95D",
+            "CF6A9483B84B4B36B3861AA7255E4C0278BA3604650C10BE19482F23171B671D",
+            "F1CF3B960C074301CD93C1D17603D147DAE2AEF837A62964EF15E5FB4AAC0B8C",
+            "1CCAA4BE754AB5728AE9130C4C7D02880AB9472D455655347FFFFFFFFFFFFFFF",
+        ),
+        alpha: "02",
+    },
+    ParameterSet {
+        id: RFC3526_3072_GROUP_ID,
+        description: "RFC 3526 group 15, 3072-bit safe prime, q = (p-1)/2",
+        p: concat!(
+            "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+            "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+            "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+            "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+            "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+            "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+            "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+            "3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33",
+            "A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7",
+            "ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864",
+            "D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2",
+            "08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF",
+        ),
+        q: concat!(
+            "7FFFFFFFFFFFFFFFE487ED5110B4611A62633145C06E0E68948127044533E63A",
+            "0105DF531D89CD9128A5043CC71A026EF7CA8CD9E69D218D98158536F92F8A1B",
+            "A7F09AB6B6A8E122F242DABB312F3F637A262174D31BF6B585FFAE5B7A035BF6",
+            "F71C35FDAD44CFD2D74F9208BE258FF324943328F6722D9EE1003E5C50B1DF82",
+            "CC6D241B0E2AE9CD348B1FD47E9267AFC1B2AE91EE51D6CB0E3179AB1042A95D",
+            "CF6A9483B84B4B36B3861AA7255E4C0278BA3604650C10BE19482F23171B671D",
+            "F1CF3B960C074301CD93C1D17603D147DAE2AEF837A62964EF15E5FB4AAC0B8C",
+            "1CCAA4BE754AB5728AE9130C4C7D02880AB9472D45556216D6998B8682283D19",
+            "D42A90D5EF8E5D32767DC2822C6DF785457538ABAE83063ED9CB87C2D370F263",
+            "D5FAD7466D8499EB8F464A702512B0CEE771E9130D697735F897FD036CC50432",
+            "6C3B01399F643532290F958C0BBD90065DF08BABBD30AEB63B84C4605D6CA371",
+            "047127D03A72D598A1EDADFE707E884725C16890549D69657FFFFFFFFFFFFFFF",
+        ),
+        alpha: "02",
+    },
+    ParameterSet {
+        id: RFC3526_4096_GROUP_ID,
+        description: "RFC 3526 group 16, 4096-bit safe prime, q = (p-1)/2",
+        p: concat!(
+            "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+            "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+            "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+            "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+            "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+            "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+            "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+            "3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33",
+            "A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7",
+            "ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864",
+            "D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2",
+            "08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A92108011A723C12A787E6D7",
+            "88719A10BDBA5B2699C327186AF4E23C1A946834B6150BDA2583E9CA2AD44CE8",
+            "DBBBC2DB04DE8EF92E8EFC141FBECAA6287C59474E6BC05D99B2964FA090C3A2",
+            "233BA186515BE7ED1F612970CEE2D7AFB81BDD762170481CD0069127D5B05AA9",
+            "93B4EA988D8FDDC186FFB7DC90A6C08F4DF435C934063199FFFFFFFFFFFFFFFF",
+        ),
+        q: concat!(
+            "7FFFFFFFFFFFFFFFE487ED5110B4611A62633145C06E0E68948127044533E63A",
+            "0105DF531D89CD9128A5043CC71A026EF7CA8CD9E69D218D98158536F92F8A1B",
+            "A7F09AB6B6A8E122F242DABB312F3F637A262174D31BF6B585FFAE5B7A035BF6",
+            "F71C35FDAD44CFD2D74F9208BE258FF324943328F6722D9EE1003E5C50B1DF82",
+            "CC6D241B0E2AE9CD348B1FD47E9267AFC1B2AE91EE51D6CB0E3179AB1042A95D",
+            "CF6A9483B84B4B36B3861AA7255E4C0278BA3604650C10BE19482F23171B671D",
+            "F1CF3B960C074301CD93C1D17603D147DAE2AEF837A62964EF15E5FB4AAC0B8C",
+            "1CCAA4BE754AB5728AE9130C4C7D02880AB9472D45556216D6998B8682283D19",
+            "D42A90D5EF8E5D32767DC2822C6DF785457538ABAE83063ED9CB87C2D370F263",
+            "D5FAD7466D8499EB8F464A702512B0CEE771E9130D697735F897FD036CC50432",
+            "6C3B01399F643532290F958C0BBD90065DF08BABBD30AEB63B84C4605D6CA371",
+            "047127D03A72D598A1EDADFE707E884725C16890549084008D391E0953C3F36B",
+            "C438CD085EDD2D934CE1938C357A711E0D4A341A5B0A85ED12C1F4E5156A2674",
+            "6DDDE16D826F477C97477E0A0FDF6553143E2CA3A735E02ECCD94B27D04861D1",
+            "119DD0C328ADF3F68FB094B867716BD7DC0DEEBB10B8240E68034893EAD82D54",
+            "C9DA754C46C7EEE0C37FDBEE48536047A6FA1AE49A0318CCFFFFFFFFFFFFFFFF",
+        ),
+        alpha: "02",
+    },
+];
+
+//looks a parameter set up by its identifier
+pub fn parameter_set(id: &str) -> Option<&'static ParameterSet> {
+    PARAMETER_SETS.iter().find(|set| set.id == id)
+}
+
+impl ParameterSet {
+    //decodes the parameters and derives beta
+    //alpha comes from the standard, beta is hashed into the subgroup from beta_seed(id)
+    //so its discrete log with respect to alpha is unknown
+    pub fn group(&self) -> Result<ModpGroup> {
+        let p = BigUint::from_bytes_be(&hex::decode(self.p)?);
+        let q = BigUint::from_bytes_be(&hex::decode(self.q)?);
+        let alpha = BigUint::from_bytes_be(&hex::decode(self.alpha)?);
+
+        let beta = hash_to_subgroup(&p, &q, beta_seed(self.id).as_bytes())?;
+        Ok(ModpGroup::new(p, q, alpha, beta))
+    }
+
+    //true for keys made with the beta of before, see LEGACY_BETA_EXPONENT
+    //the exponentiation is mod the p of the shared group, see zkp_auth::context
+    pub fn has_legacy_keys(&self, y1: &[u8], y2: &[u8]) -> Result<bool> {
+        let group = &crate::context::modp(self.id)?.group;
+        Ok(group.exp(&BigUint::from_bytes_be(y1), &LEGACY_EXPONENT) == BigUint::from_bytes_be(y2))
+    }
+}
+
+//refuses keys made with the beta of before, they can not log in any more and only a new wallet helps
+//only the legacy groups are looked at, the others never had that beta
+pub fn check_current_keys(group_id: &str, did: &str, y1: &[u8], y2: &[u8]) -> Result<()> {
+    if !LEGACY_GROUP_IDS.contains(&group_id) {
+        return Ok(());
+    }
+    match parameter_set(group_id) {
+        Some(set) if set.has_legacy_keys(y1, y2)? => Err(ZkpAuthError::Protocol(format!(
+            "DID: {} has keys made with the beta of before it was hashed into the subgroup, they no longer verify, create a new wallet and enroll it", did))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parameter_sets() {
+        let one = BigUint::from(1u32);
+        for set in PARAMETER_SETS {
+            let group = set.group().unwrap();
+            //q divides p-1 and both generators have order q
+            assert_eq!((&group.p - &one) % &group.q, BigUint::from(0u32), "{}", set.id);
+            assert_eq!(group.alpha.modpow(&group.q, &group.p), one, "{}", set.id);
+            assert_eq!(group.beta.modpow(&group.q, &group.p), one, "{}", set.id);
+        }
+    }
+
+    #[test]
+    fn test_beta_known_answers() {
+        use sha2::{Digest, Sha256};
+
+        //sha256 of the big-endian encoding of each derived beta
+        let expected = [
+            (RFC5114_1024_GROUP_ID, "c56a0c33fdd3cffa427f53e7e1adb75949d87ad8d83f15d43a3145bc03a4830c"),
+            (RFC5114_2048_224_GROUP_ID, "f6464318bcae69ef96dcf4c1786a969f4867354fd8d68493fe9fd567d9b7ed31"),
+            (RFC5114_2048_256_GROUP_ID, "3e536a9b821255a63fc30b59e9bc5301c264f6be18fb7db8e2d2eadc7d99959a"),
+            (RFC3526_2048_GROUP_ID, "060e6bc1eca49f0016d25c5eadb9cbad6c0e240c9e2a982909e776f8f3fddbfd"),
+            (RFC3526_3072_GROUP_ID, "1f8bb3a252a0c8443476e376ae5fa18b76be902e1a7c9da7edebcb23bde4e8ac"),
+            (RFC3526_4096_GROUP_ID, "65106ef257780f25a99789ba9c1b4813dbeda82548142cc167aee1b633901c67"),
+        ];
+        for (id, digest) in expected {
+            let group = parameter_set(id).unwrap().group().unwrap();
+            assert_eq!(hex::encode(Sha256::digest(group.beta.to_bytes_be())), digest, "{}", id);
+            assert_ne!(group.beta, group.alpha, "{}", id);
+        }
+
+        //the full value for the 1024-bit group
+        let group = parameter_set(RFC5114_1024_GROUP_ID).unwrap().group().unwrap();
+        assert_eq!(hex::encode_upper(group.beta.to_bytes_be()), concat!(
+            "5A119E3C8BA0F1C727EE7615650498C0D94947CEC5CA906BBDF0D84AEC4C79BC",
+            "494E23FFA74DB38FD4AB6624365F3C44C5DD677A802756F4158FC5A7960B8229",
+            "A663FE2293888B2A9A877B30A74C4DFB6AB322C8D0977E400AD603A8C944E808",
+            "B1AAFFAB790C1A33604DAF9631F21BF59BFDB50F7C9C59930A585DFE92414E92",
+        ));
+    }
+
+    #[test]
+    fn test_legacy_keys_are_refused() {
+        let set = parameter_set(RFC5114_1024_GROUP_ID).unwrap();
+        let group = set.group().unwrap();
+        let x = BigUint::from(12345u32);
+        let legacy_beta = group.alpha.modpow(&BigUint::from_bytes_be(&hex::decode(LEGACY_BETA_EXPONENT).unwrap()), &group.p);
+        let y1 = group.alpha.modpow(&x, &group.p).to_bytes_be();
+        let legacy = legacy_beta.modpow(&x, &group.p).to_bytes_be();
+        let current = group.beta.modpow(&x, &group.p).to_bytes_be();
+
+        assert!(set.has_legacy_keys(&y1, &legacy).unwrap());
+        assert!(!set.has_legacy_keys(&y1, &current).unwrap());
+        let error = check_current_keys(set.id, "did:zkp:a", &y1, &legacy).unwrap_err();
+        assert!(matches!(error, ZkpAuthError::Protocol(_)) && error.reason().contains("create a new wallet"));
+        assert!(check_current_keys(set.id, "did:zkp:a", &y1, &current).is_ok());
+        //groups that are not legacy ones are not looked at, nor is a group without a parameter set
+        let current_set = parameter_set(RFC5114_2048_256_GROUP_ID).unwrap();
+        let current_group = current_set.group().unwrap();
+        let legacy_beta = current_group.alpha.modpow(&LEGACY_EXPONENT, &current_group.p);
+        let (y1, legacy) = (current_group.alpha.modpow(&x, &current_group.p).to_bytes_be(), legacy_beta.modpow(&x, &current_group.p).to_bytes_be());
+        assert!(current_set.has_legacy_keys(&y1, &legacy).unwrap());
+        assert!(check_current_keys(current_set.id, "did:zkp:a", &y1, &legacy).is_ok());
+        assert!(check_current_keys("ristretto255", "did:zkp:a", &y1, &legacy).is_ok());
+    }
+
+    #[test]
+    fn test_parameter_set_lookup() {
+        assert_eq!(parameter_set(RFC5114_2048_256_GROUP_ID).unwrap().id, "rfc5114-2048-256");
+        assert!(parameter_set("rfc5114-512").is_none());
+
+        //the identifiers are unique
+        for (i, a) in PARAMETER_SETS.iter().enumerate() {
+            assert!(PARAMETER_SETS[i + 1..].iter().all(|b| b.id != a.id));
+        }
+    }
+}
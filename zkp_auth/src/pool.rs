@@ -0,0 +1,117 @@
+//A bounded pool for the big integer and curve arithmetic of the server.
+//tonic runs the handlers on tokio's worker threads, and a modpow in the 4096-bit
+//group there stalls every other request on that thread. Jobs run on tokio's
+//blocking threads instead, at most `workers` at a time with at most `queue` more
+//waiting for a worker. A job beyond that is refused at once with
+//ZkpAuthError::Overloaded (RESOURCE_EXHAUSTED), so a burst or one slow batch
+//makes clients back off instead of piling up behind it.
+//A job must own what it works on, the state locks are released before it is handed over.
+
+use crate::error::{Result, ZkpAuthError};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+//jobs that may wait for a worker, per worker
+pub const QUEUE_PER_WORKER: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct VerifierPool {
+    //one permit per job that is running or waiting
+    admitted: Arc<Semaphore>,
+    //one permit per job that is running
+    running: Arc<Semaphore>,
+    workers: usize,
+}
+
+impl Default for VerifierPool {
+    //one worker per core
+    fn default() -> Self {
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::new(workers, workers * QUEUE_PER_WORKER)
+    }
+}
+
+impl VerifierPool {
+    pub fn new(workers: usize, queue: usize) -> Self {
+        let workers = workers.max(1);
+        Self {
+            admitted: Arc::new(Semaphore::new(workers + queue)),
+            running: Arc::new(Semaphore::new(workers)),
+            workers,
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    //runs the job on a blocking thread once a worker is free
+    //the permits go into the job, so a request that is dropped while its job runs
+    //still counts against the pool until the job is done
+    pub async fn run<T, F>(&self, job: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let admitted = self.admitted.clone().try_acquire_owned()
+            .map_err(|_| ZkpAuthError::Overloaded("The server is busy verifying other proofs, try again later".to_string()))?;
+        let running = self.running.clone().acquire_owned().await
+            .map_err(|_| ZkpAuthError::Storage("the verifier pool is closed".to_string()))?;
+        //the job logs into the span of the RPC that sent it
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _permits = (admitted, running);
+            span.in_scope(job)
+        })
+        .await
+        .map_err(|e| ZkpAuthError::Storage(format!("a verification job failed: {}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_pool_runs_jobs() {
+        let pool = VerifierPool::new(2, 2);
+        assert_eq!(pool.run(|| Ok(6 * 7)).await.unwrap(), 42);
+        assert!(matches!(pool.run(|| -> Result<()> { Err(ZkpAuthError::Crypto("no".to_string())) }).await, Err(ZkpAuthError::Crypto(_))));
+
+        //a job that panics fails its own request and gives its worker back
+        let panicked = pool.run(|| -> Result<()> { panic!("job panicked") }).await;
+        assert!(matches!(panicked, Err(ZkpAuthError::Storage(_))));
+        assert_eq!(pool.running.available_permits(), 2);
+        assert_eq!(pool.admitted.available_permits(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_pool_refuses_jobs_beyond_the_queue() {
+        let pool = VerifierPool::new(1, 1);
+        let (release, wait) = mpsc::channel::<()>();
+        let (started, is_started) = mpsc::channel::<()>();
+
+        //one job holds the only worker, a second one waits for it
+        let busy = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || { started.send(()).unwrap(); wait.recv().unwrap(); Ok(1) }).await }
+        });
+        tokio::task::spawn_blocking(move || is_started.recv().unwrap()).await.unwrap();
+        let queued = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| Ok(2)).await }
+        });
+        while pool.admitted.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        //the third is turned away at once
+        assert!(matches!(pool.run(|| Ok(3)).await, Err(ZkpAuthError::Overloaded(_))));
+
+        release.send(()).unwrap();
+        assert_eq!(busy.await.unwrap().unwrap(), 1);
+        assert_eq!(queued.await.unwrap().unwrap(), 2);
+        assert_eq!(pool.run(|| Ok(4)).await.unwrap(), 4);
+    }
+}
@@ -0,0 +1,333 @@
+//The values the prover and verifier exchange, in their canonical encoding.
+//Like a compressed point these hold bytes rather than group elements, so they
+//can be serialised, logged and put into protobuf messages without knowing the
+//group. decode() turns them back into group values and is strict: every field
+//must be exactly the width the group encodes it with and must pass validation.
+//
+//Binary form: one tag byte for the type followed by the fixed width fields
+//  Commitment  0x01 || r1 || r2
+//  Challenge   0x02 || c
+//  Response    0x03 || s
+//  Proof       0x04 || r1 || r2 || s
+//JSON form: the same fields as base64 strings.
+
+use crate::error::{Result, ZkpAuthError};
+use crate::group::Group;
+use crate::zkp_proto::{ChallengeRequest, ChallengeResponse, LoginRequest, ProofOfKnowledge, SolutionRequest};
+use crate::ZKP;
+use serde::{Deserialize, Serialize};
+
+pub const COMMITMENT_TAG: u8 = 0x01;
+pub const CHALLENGE_TAG: u8 = 0x02;
+pub const RESPONSE_TAG: u8 = 0x03;
+pub const PROOF_TAG: u8 = 0x04;
+
+//(r1, r2) = (alpha^k, beta^k), the prover's first message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commitment {
+    #[serde(with = "base64_bytes")]
+    r1: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    r2: Vec<u8>,
+}
+
+//c, chosen by the verifier or hashed from the transcript
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Challenge {
+    #[serde(with = "base64_bytes")]
+    c: Vec<u8>,
+}
+
+//s = k - c * x mod q
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    #[serde(with = "base64_bytes")]
+    s: Vec<u8>,
+}
+
+//a non-interactive proof, the challenge is recomputed from the commitment and the context
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proof {
+    pub commitment: Commitment,
+    pub response: Response,
+}
+
+//splits off the tag and checks the rest is exactly len bytes
+fn untag<'a>(bytes: &'a [u8], tag: u8, len: usize, name: &str) -> Result<&'a [u8]> {
+    match bytes.split_first() {
+        Some((first, rest)) if *first == tag => {
+            if rest.len() != len {
+                return Err(ZkpAuthError::Encoding(format!("{} is {} bytes, expected {}", name, rest.len(), len)));
+            }
+            Ok(rest)
+        }
+        _ => Err(ZkpAuthError::Encoding(format!("{} does not start with tag {:#04x}", name, tag))),
+    }
+}
+
+impl Commitment {
+    pub fn new<G: Group>(zkp: &ZKP<G>, r1: &G::Element, r2: &G::Element) -> Self {
+        Self { r1: zkp.group.element_to_bytes(r1), r2: zkp.group.element_to_bytes(r2) }
+    }
+
+    pub fn r1(&self) -> &[u8] {
+        &self.r1
+    }
+
+    pub fn r2(&self) -> &[u8] {
+        &self.r2
+    }
+
+    //both values must be canonical elements of the order q subgroup
+    pub fn decode<G: Group>(&self, zkp: &ZKP<G>) -> Result<(G::Element, G::Element)> {
+        let r1 = zkp.decode_element(&self.r1).map_err(|e| ZkpAuthError::invalid("r1", e))?;
+        let r2 = zkp.decode_element(&self.r2).map_err(|e| ZkpAuthError::invalid("r2", e))?;
+        Ok((r1, r2))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&[COMMITMENT_TAG][..], &self.r1, &self.r2].concat()
+    }
+
+    pub fn from_bytes<G: Group>(zkp: &ZKP<G>, bytes: &[u8]) -> Result<Self> {
+        let n = zkp.group.element_len();
+        let rest = untag(bytes, COMMITMENT_TAG, 2 * n, "commitment")?;
+        let commitment = Self { r1: rest[..n].to_vec(), r2: rest[n..].to_vec() };
+        commitment.decode(zkp)?;
+        Ok(commitment)
+    }
+}
+
+impl Challenge {
+    pub fn new<G: Group>(zkp: &ZKP<G>, c: &G::Scalar) -> Self {
+        Self { c: zkp.group.scalar_to_bytes(c) }
+    }
+
+    pub fn c(&self) -> &[u8] {
+        &self.c
+    }
+
+    pub fn decode<G: Group>(&self, zkp: &ZKP<G>) -> Result<G::Scalar> {
+        zkp.decode_scalar(&self.c).map_err(|e| ZkpAuthError::invalid("c", e))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&[CHALLENGE_TAG][..], &self.c].concat()
+    }
+
+    pub fn from_bytes<G: Group>(zkp: &ZKP<G>, bytes: &[u8]) -> Result<Self> {
+        let rest = untag(bytes, CHALLENGE_TAG, zkp.group.scalar_len(), "challenge")?;
+        let challenge = Self { c: rest.to_vec() };
+        challenge.decode(zkp)?;
+        Ok(challenge)
+    }
+}
+
+impl Response {
+    pub fn new<G: Group>(zkp: &ZKP<G>, s: &G::Scalar) -> Self {
+        Self { s: zkp.group.scalar_to_bytes(s) }
+    }
+
+    pub fn s(&self) -> &[u8] {
+        &self.s
+    }
+
+    pub fn decode<G: Group>(&self, zkp: &ZKP<G>) -> Result<G::Scalar> {
+        zkp.decode_scalar(&self.s).map_err(|e| ZkpAuthError::invalid("s", e))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&[RESPONSE_TAG][..], &self.s].concat()
+    }
+
+    pub fn from_bytes<G: Group>(zkp: &ZKP<G>, bytes: &[u8]) -> Result<Self> {
+        let rest = untag(bytes, RESPONSE_TAG, zkp.group.scalar_len(), "response")?;
+        let response = Self { s: rest.to_vec() };
+        response.decode(zkp)?;
+        Ok(response)
+    }
+}
+
+impl Proof {
+    pub fn new<G: Group>(zkp: &ZKP<G>, r1: &G::Element, r2: &G::Element, s: &G::Scalar) -> Self {
+        Self { commitment: Commitment::new(zkp, r1, r2), response: Response::new(zkp, s) }
+    }
+
+    //(r1, r2, s), in the order verify_non_interactive takes them
+    pub fn decode<G: Group>(&self, zkp: &ZKP<G>) -> Result<(G::Element, G::Element, G::Scalar)> {
+        let (r1, r2) = self.commitment.decode(zkp)?;
+        Ok((r1, r2, self.response.decode(zkp)?))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&[PROOF_TAG][..], &self.commitment.r1, &self.commitment.r2, &self.response.s].concat()
+    }
+
+    pub fn from_bytes<G: Group>(zkp: &ZKP<G>, bytes: &[u8]) -> Result<Self> {
+        let n = zkp.group.element_len();
+        let rest = untag(bytes, PROOF_TAG, 2 * n + zkp.group.scalar_len(), "proof")?;
+        let proof = Self {
+            commitment: Commitment { r1: rest[..n].to_vec(), r2: rest[n..2 * n].to_vec() },
+            response: Response { s: rest[2 * n..].to_vec() },
+        };
+        proof.decode(zkp)?;
+        Ok(proof)
+    }
+
+    pub fn to_login_request(&self, user: String, context: Vec<u8>, group: String) -> LoginRequest {
+        LoginRequest {
+            user,
+            r1: self.commitment.r1.clone(),
+            r2: self.commitment.r2.clone(),
+            s: self.response.s.clone(),
+            context,
+            group,
+        }
+    }
+}
+
+//the protobuf messages carry the same canonical bytes field by field
+impl Commitment {
+    pub fn to_challenge_request(&self, user: String, group: String) -> ChallengeRequest {
+        ChallengeRequest { user, r1: self.r1.clone(), r2: self.r2.clone(), group, ..Default::default() }
+    }
+}
+
+impl From<&ChallengeRequest> for Commitment {
+    fn from(request: &ChallengeRequest) -> Self {
+        Self { r1: request.r1.clone(), r2: request.r2.clone() }
+    }
+}
+
+impl From<&ChallengeResponse> for Challenge {
+    fn from(response: &ChallengeResponse) -> Self {
+        Self { c: response.c.clone() }
+    }
+}
+
+impl From<&SolutionRequest> for Response {
+    fn from(request: &SolutionRequest) -> Self {
+        Self { s: request.s.clone() }
+    }
+}
+
+impl From<&LoginRequest> for Proof {
+    fn from(request: &LoginRequest) -> Self {
+        Self {
+            commitment: Commitment { r1: request.r1.clone(), r2: request.r2.clone() },
+            response: Response { s: request.s.clone() },
+        }
+    }
+}
+
+impl From<&ProofOfKnowledge> for Proof {
+    fn from(proof: &ProofOfKnowledge) -> Self {
+        Self {
+            commitment: Commitment { r1: proof.r1.clone(), r2: proof.r2.clone() },
+            response: Response { s: proof.s.clone() },
+        }
+    }
+}
+
+impl From<&Proof> for ProofOfKnowledge {
+    fn from(proof: &Proof) -> Self {
+        Self { r1: proof.commitment.r1.clone(), r2: proof.commitment.r2.clone(), s: proof.response.s.clone() }
+    }
+}
+
+//serde helper, byte fields are base64 strings in JSON like the rest of the wallet file
+pub(crate) mod base64_bytes {
+    use base64::{engine::general_purpose, Engine as _};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&general_purpose::STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        general_purpose::STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parameter_set, Ristretto255Group, RFC5114_1024_GROUP_ID};
+
+    fn modp_zkp() -> ZKP {
+        ZKP::new(parameter_set(RFC5114_1024_GROUP_ID).unwrap().group().unwrap())
+    }
+
+    #[test]
+    fn test_proof_round_trip() {
+        let zkp = modp_zkp();
+        let x = zkp.group.random_scalar();
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+        let (r1, r2, s) = zkp.prove_non_interactive(&x, b"ctx");
+        let proof = Proof::new(&zkp, &r1, &r2, &s);
+
+        //binary
+        let bytes = proof.to_bytes();
+        assert_eq!(bytes.len(), 1 + 128 + 128 + 20);
+        assert_eq!(bytes[0], PROOF_TAG);
+        assert_eq!(Proof::from_bytes(&zkp, &bytes).unwrap(), proof);
+
+        //json
+        let json = serde_json::to_string(&proof).unwrap();
+        let parsed: Proof = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, proof);
+
+        //through the protobuf message
+        let request = proof.to_login_request("did:zkp:x".to_string(), b"ctx".to_vec(), String::new());
+        let (r1, r2, s) = Proof::from(&request).decode(&zkp).unwrap();
+        assert!(zkp.verify_non_interactive(&r1, &r2, &y1, &y2, &s, b"ctx"));
+    }
+
+    #[test]
+    fn test_small_values_keep_their_width() {
+        let zkp = modp_zkp();
+        //a scalar with leading zero bytes still encodes to the full width of q
+        let s = num_bigint::BigUint::from(5u32);
+        let response = Response::new(&zkp, &s);
+        assert_eq!(response.s().len(), 20);
+        assert_eq!(Response::from_bytes(&zkp, &response.to_bytes()).unwrap().decode(&zkp).unwrap(), s);
+    }
+
+    #[test]
+    fn test_strict_decoding() {
+        let zkp = modp_zkp();
+        let (r1, r2) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+        let commitment = Commitment::new(&zkp, &r1, &r2);
+        let bytes = commitment.to_bytes();
+
+        //wrong tag, truncated, extended
+        let mut wrong_tag = bytes.clone();
+        wrong_tag[0] = PROOF_TAG;
+        assert!(Commitment::from_bytes(&zkp, &wrong_tag).is_err());
+        assert!(Commitment::from_bytes(&zkp, &bytes[..bytes.len() - 1]).is_err());
+        assert!(Commitment::from_bytes(&zkp, &[&bytes[..], &[0]].concat()).is_err());
+
+        //an extra leading zero still encodes the same number, but it is not canonical
+        let padded = Commitment { r1: [&[0u8][..], commitment.r1()].concat(), r2: commitment.r2().to_vec() };
+        assert!(matches!(padded.decode(&zkp), Err(ZkpAuthError::Encoding(reason)) if reason == "r1 is 129 bytes, expected 128"));
+
+        //the identity is rejected even at the right width
+        let mut one = vec![0u8; 128];
+        one[127] = 1;
+        let identity = Commitment { r1: one, r2: commitment.r2().to_vec() };
+        assert!(identity.decode(&zkp).is_err());
+
+        //bad base64 in json
+        assert!(serde_json::from_str::<Challenge>(r#"{"c":"@@"}"#).is_err());
+    }
+
+    #[test]
+    fn test_ristretto_widths() {
+        let zkp = ZKP::new(Ristretto255Group::new());
+        let (r1, r2) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+        let c = Challenge::new(&zkp, &zkp.group.random_scalar());
+        assert_eq!(Commitment::new(&zkp, &r1, &r2).to_bytes().len(), 65);
+        assert_eq!(Challenge::from_bytes(&zkp, &c.to_bytes()).unwrap(), c);
+    }
+}
@@ -0,0 +1,312 @@
+//Client puzzles (hashcash) in front of CreateChallenge.
+//Every challenge the server gives out is kept until it is answered or swept, a flood of them
+//costs the server memory and a subgroup check each. Once there are start_at open challenges
+//CreateChallenge wants a solved puzzle: the client asks GetPuzzle for one, then searches for a
+//counter so that SHA-256 over the puzzle, its commitment and the counter starts with
+//`difficulty` zero bits, about 2^difficulty hashes. The difficulty rises from min_difficulty at
+//start_at open challenges to max_difficulty at full_at.
+//The client binary is the one solver, with solve_request on the request it built from its
+//commitment; the wallet binary only creates wallets and never asks for a challenge.
+//
+//The server keeps no state for a puzzle it hands out: the tag is an HMAC under a key only the
+//server knows, over the DID, the seed, the difficulty and the expiry. Checking a solution is the
+//HMAC and one hash, far below a modpow, and is done before anything else looks at the request.
+//A solved puzzle is remembered until it expires so it is good for one challenge only.
+
+use crate::error::{Result, ZkpAuthError};
+use crate::session::to_chrono;
+use crate::zkp_proto::{ChallengeRequest, Puzzle};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TAG_DOMAIN: &[u8] = b"zkp_auth/puzzle-tag/v1";
+const SOLUTION_DOMAIN: &[u8] = b"zkp_auth/puzzle/v1";
+//bytes of a puzzle seed
+pub const SEED_LEN: usize = 16;
+//the hardest puzzle, a larger difficulty counts as this one; it bounds the client's solve loop
+//to about 2^32 hashes, and the expect in solve relies on that to find a counter long before
+//the 2^64 of them run out
+pub const MAX_BITS: u32 = 32;
+
+//open challenges from which on a puzzle is needed, and at which it is hardest
+pub const START_AT: usize = 1_000;
+pub const FULL_AT: usize = 50_000;
+pub const MIN_DIFFICULTY: u32 = 12;
+pub const MAX_DIFFICULTY: u32 = 22;
+//how long a puzzle can be solved and used
+pub const PUZZLE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PuzzlePolicy {
+    pub start_at: usize,
+    pub full_at: usize,
+    pub min_difficulty: u32,
+    pub max_difficulty: u32,
+    pub ttl: Duration,
+}
+
+impl Default for PuzzlePolicy {
+    fn default() -> Self {
+        Self { start_at: START_AT, full_at: FULL_AT, min_difficulty: MIN_DIFFICULTY, max_difficulty: MAX_DIFFICULTY, ttl: PUZZLE_TTL }
+    }
+}
+
+impl PuzzlePolicy {
+    //the difficulty for this many open challenges, 0 is no puzzle
+    pub fn difficulty(&self, open: usize) -> u32 {
+        if open < self.start_at {
+            return 0;
+        }
+        let (min, max) = (self.min_difficulty.min(MAX_BITS), self.max_difficulty.clamp(self.min_difficulty, MAX_BITS));
+        if open >= self.full_at {
+            return max;
+        }
+        let steps = (max - min) as usize * (open - self.start_at) / (self.full_at - self.start_at).max(1);
+        min + steps as u32
+    }
+}
+
+//every field is length prefixed like in the challenge hash
+fn hash_fields<D: Digest>(hasher: &mut D, fields: &[&[u8]]) {
+    for field in fields {
+        hasher.update((field.len() as u32).to_be_bytes());
+        hasher.update(field);
+    }
+}
+
+fn tag_mac(key: &[u8], did: &str, seed: &[u8], difficulty: u32, expires_at: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(TAG_DOMAIN);
+    for field in [did.as_bytes(), seed, &difficulty.to_be_bytes(), &expires_at.to_be_bytes()] {
+        mac.update(&(field.len() as u32).to_be_bytes());
+        mac.update(field);
+    }
+    mac
+}
+
+//the hash a solution is judged by, it names the commitment so a solution is no use for another one
+fn solution_hash(puzzle: &Puzzle, r1: &[u8], r2: &[u8], solution: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(SOLUTION_DOMAIN);
+    hash_fields(&mut hasher, &[&puzzle.tag, r1, r2]);
+    hasher.update(solution.to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+pub fn is_solution(puzzle: &Puzzle, r1: &[u8], r2: &[u8], solution: u64) -> bool {
+    leading_zero_bits(&solution_hash(puzzle, r1, r2, solution)) >= puzzle.difficulty.min(MAX_BITS)
+}
+
+//the client side, the first counter that solves the puzzle for this commitment
+//it takes about 2^difficulty hashes
+pub fn solve(puzzle: &Puzzle, r1: &[u8], r2: &[u8]) -> u64 {
+    (0..).find(|solution| is_solution(puzzle, r1, r2, *solution)).expect("a puzzle of at most 32 bits has a solution")
+}
+
+//solves the puzzle for the commitment in the request and puts both in it
+pub fn solve_request(request: &mut ChallengeRequest, puzzle: Puzzle) {
+    request.solution = solve(&puzzle, &request.r1, &request.r2);
+    request.puzzle = Some(puzzle);
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    issued: AtomicU64,
+    solved: AtomicU64,
+    refused: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PuzzleMetrics {
+    pub issued: u64,
+    pub solved: u64,
+    pub refused: u64,
+}
+
+//the server side
+#[derive(Debug)]
+pub struct Puzzles {
+    policy: PuzzlePolicy,
+    key: [u8; 32],
+    //the seeds of solved puzzles, until they expire
+    used: DashMap<Vec<u8>, DateTime<Utc>>,
+    counters: Counters,
+}
+
+impl Puzzles {
+    //the key comes from the server's RNG, puzzles do not outlive the server
+    pub fn new(policy: PuzzlePolicy, key: [u8; 32]) -> Self {
+        Self { policy, key, used: DashMap::new(), counters: Counters::default() }
+    }
+
+    pub fn policy(&self) -> &PuzzlePolicy {
+        &self.policy
+    }
+
+    pub fn with_policy(self, policy: PuzzlePolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    //a puzzle for the DID at this many open challenges, None while no puzzle is needed
+    pub fn issue(&self, did: &str, open: usize, seed: [u8; SEED_LEN], now: DateTime<Utc>) -> Option<Puzzle> {
+        let difficulty = self.policy.difficulty(open);
+        if difficulty == 0 {
+            return None;
+        }
+        let expires_at = (now + to_chrono(self.policy.ttl)).timestamp();
+        let tag = tag_mac(&self.key, did, &seed, difficulty, expires_at).finalize().into_bytes().to_vec();
+        self.counters.issued.fetch_add(1, Ordering::Relaxed);
+        Some(Puzzle { seed: seed.to_vec(), difficulty, expires_at, tag })
+    }
+
+    //a challenge request at this many open challenges, it needs a solved puzzle from this server
+    //for its DID once there are start_at of them; the puzzle's own difficulty is the one it was
+    //issued with, a rise since then does not send the client back
+    pub fn check(&self, request: &ChallengeRequest, open: usize, now: DateTime<Utc>) -> Result<()> {
+        if self.policy.difficulty(open) == 0 {
+            return Ok(());
+        }
+        let checked = self.check_puzzle(request, now);
+        let counter = if checked.is_ok() { &self.counters.solved } else { &self.counters.refused };
+        counter.fetch_add(1, Ordering::Relaxed);
+        checked
+    }
+
+    fn check_puzzle(&self, request: &ChallengeRequest, now: DateTime<Utc>) -> Result<()> {
+        let Some(puzzle) = &request.puzzle else {
+            return Err(ZkpAuthError::Overloaded("The server is under load, solve a puzzle from GetPuzzle first".to_string()));
+        };
+        tag_mac(&self.key, &request.user, &puzzle.seed, puzzle.difficulty, puzzle.expires_at)
+            .verify_slice(&puzzle.tag)
+            .map_err(|_| ZkpAuthError::Crypto(format!("The puzzle was not issued by this server for DID: {}", request.user)))?;
+        if puzzle.expires_at <= now.timestamp() {
+            return Err(ZkpAuthError::Expired("The puzzle expired, ask for a new one".to_string()));
+        }
+        if !is_solution(puzzle, &request.r1, &request.r2, request.solution) {
+            return Err(ZkpAuthError::Crypto(format!("The puzzle solution does not give {} leading zero bits", puzzle.difficulty)));
+        }
+        let expires_at = DateTime::from_timestamp(puzzle.expires_at, 0).unwrap_or(now);
+        match self.used.entry(puzzle.seed.clone()) {
+            Entry::Occupied(_) => Err(ZkpAuthError::Protocol("The puzzle was already used, ask for a new one".to_string())),
+            Entry::Vacant(vacant) => {
+                vacant.insert(expires_at);
+                Ok(())
+            }
+        }
+    }
+
+    //forgets the solved puzzles that expired, they are refused as expired from then on
+    pub fn sweep(&self, now: DateTime<Utc>) -> usize {
+        let before = self.used.len();
+        self.used.retain(|_, expires_at| now < *expires_at);
+        before - self.used.len()
+    }
+
+    pub fn metrics(&self) -> PuzzleMetrics {
+        let counter = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        PuzzleMetrics { issued: counter(&self.counters.issued), solved: counter(&self.counters.solved), refused: counter(&self.counters.refused) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy() -> PuzzlePolicy {
+        PuzzlePolicy { start_at: 10, full_at: 20, min_difficulty: 4, max_difficulty: 8, ttl: Duration::from_secs(60) }
+    }
+
+    fn request(did: &str) -> ChallengeRequest {
+        ChallengeRequest { user: did.to_string(), r1: vec![1; 8], r2: vec![2; 8], ..Default::default() }
+    }
+
+    #[test]
+    fn test_difficulty_rises_with_load() {
+        let policy = policy();
+        assert_eq!([0, 9, 10, 15, 19, 20, 1000].map(|open| policy.difficulty(open)), [0, 0, 4, 6, 7, 8, 8]);
+        let steep = PuzzlePolicy { start_at: 1, full_at: 1, max_difficulty: 64, ..policy };
+        assert_eq!(steep.difficulty(1), MAX_BITS);
+        assert_eq!(leading_zero_bits(&[0, 0, 0x10, 0xff]), 19);
+        assert_eq!(leading_zero_bits(&[0; 4]), 32);
+    }
+
+    #[test]
+    fn test_solved_puzzle_is_good_once() {
+        let puzzles = Puzzles::new(policy(), [7; 32]);
+        let now = Utc::now();
+        assert_eq!(puzzles.issue("did:zkp:a", 9, [1; SEED_LEN], now), None);
+        assert!(puzzles.check(&request("did:zkp:a"), 9, now).is_ok());
+
+        let puzzle = puzzles.issue("did:zkp:a", 20, [1; SEED_LEN], now).unwrap();
+        assert_eq!(puzzle.difficulty, 8);
+        let mut solved = request("did:zkp:a");
+        solve_request(&mut solved, puzzle.clone());
+        assert!(puzzles.check(&solved, 20, now).is_ok());
+        assert!(matches!(puzzles.check(&solved, 20, now), Err(ZkpAuthError::Protocol(_))));
+        //a used puzzle is forgotten once it expired, it is refused as expired then
+        assert_eq!(puzzles.sweep(now), 0);
+        let expired = now + chrono::Duration::seconds(61);
+        assert_eq!(puzzles.sweep(expired), 1);
+        assert!(matches!(puzzles.check(&solved, 20, expired), Err(ZkpAuthError::Expired(_))));
+        assert_eq!(puzzles.metrics(), PuzzleMetrics { issued: 1, solved: 1, refused: 2 });
+    }
+
+    #[test]
+    fn test_puzzle_is_bound_to_its_did() {
+        let puzzles = Puzzles::new(policy(), [7; 32]);
+        let now = Utc::now();
+        let puzzle = puzzles.issue("did:zkp:a", 10, [1; SEED_LEN], now).unwrap();
+        assert!(matches!(puzzles.check(&request("did:zkp:a"), 10, now), Err(ZkpAuthError::Overloaded(_))));
+
+        //another DID, an easier difficulty or another server's key are caught by the tag
+        let mut other = request("did:zkp:b");
+        solve_request(&mut other, puzzle.clone());
+        assert!(matches!(puzzles.check(&other, 10, now), Err(ZkpAuthError::Crypto(_))));
+        let mut easier = request("did:zkp:a");
+        solve_request(&mut easier, Puzzle { difficulty: 1, ..puzzle.clone() });
+        assert!(matches!(puzzles.check(&easier, 10, now), Err(ZkpAuthError::Crypto(_))));
+        let forged = Puzzles::new(policy(), [8; 32]).issue("did:zkp:a", 10, [2; SEED_LEN], now).unwrap();
+        let mut request_forged = request("did:zkp:a");
+        solve_request(&mut request_forged, forged);
+        assert!(matches!(puzzles.check(&request_forged, 10, now), Err(ZkpAuthError::Crypto(_))));
+
+        //a counter that does not give the zero bits is refused and leaves the puzzle unused
+        let mut solved = request("did:zkp:a");
+        solve_request(&mut solved, puzzle);
+        let wrong = (0..).find(|n| !is_solution(solved.puzzle.as_ref().unwrap(), &solved.r1, &solved.r2, *n)).unwrap();
+        let unsolved = ChallengeRequest { solution: wrong, ..solved.clone() };
+        assert!(matches!(puzzles.check(&unsolved, 10, now), Err(ZkpAuthError::Crypto(_))));
+        assert!(puzzles.check(&solved, 10, now).is_ok());
+    }
+
+    #[test]
+    fn test_request_built_like_the_client_passes_the_check() {
+        use crate::{Commitment, Group, ZKP};
+        let puzzles = Puzzles::new(policy(), [7; 32]);
+        let now = Utc::now();
+        let zkp = ZKP::new(ZKP::default_group().unwrap());
+        let (r1, r2) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+        let mut request = Commitment::new(&zkp, &r1, &r2).to_challenge_request("did:zkp:a".to_string(), crate::RFC5114_2048_256_GROUP_ID.to_string());
+        let puzzle = puzzles.issue("did:zkp:a", 20, [3; SEED_LEN], now).unwrap();
+        solve_request(&mut request, puzzle);
+        assert!(puzzles.check(&request, 20, now).is_ok());
+    }
+}
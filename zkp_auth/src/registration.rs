@@ -0,0 +1,134 @@
+//What a registration has to prove before the server keeps a DID's keys.
+//A DID is registered with the keys it was derived from (DID::from_public_keys), and with a
+//non-interactive proof of knowledge of x whose context names the DID, so a proof made for one
+//DID is of no use for another and nobody can register keys they do not hold.
+//New keys for a registered DID need a second proof, made with the current x, whose context
+//names the new keys. The DID keeps the identifier of the keys it was first registered with.
+
+use crate::error::{Result, ZkpAuthError};
+use crate::group::Group;
+use crate::proof::Proof;
+use crate::ssi::credential::DID;
+use crate::zkp_proto::{ProofOfKnowledge, RegisterRequest};
+use crate::ZKP;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+
+const REGISTRATION_DOMAIN: &[u8] = b"zkp_auth/register/v1";
+const KEY_UPDATE_DOMAIN: &[u8] = b"zkp_auth/key-update/v1";
+
+//every field is length prefixed like in the challenge hash, two field lists never give the same bytes
+pub(crate) fn context(domain: &[u8], fields: &[&[u8]]) -> Vec<u8> {
+    let mut context = domain.to_vec();
+    for field in fields {
+        context.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        context.extend_from_slice(field);
+    }
+    context
+}
+
+//the context of the proof that registers a DID, the keys are hashed into c already
+pub fn registration_context(did: &str) -> Vec<u8> {
+    context(REGISTRATION_DOMAIN, &[did.as_bytes()])
+}
+
+//the context of the proof by the current x that hands a DID over to new keys
+pub fn key_update_context(did: &str, y1: &[u8], y2: &[u8]) -> Vec<u8> {
+    context(KEY_UPDATE_DOMAIN, &[did.as_bytes(), y1, y2])
+}
+
+//a first registration must use the DID its keys give
+pub fn check_did(did: &str, y1: &[u8], y2: &[u8]) -> Result<()> {
+    let expected = DID::from_public_keys(y1, y2).to_string();
+    if DID::from_string(did)?.to_string() != expected {
+        return Err(ZkpAuthError::Crypto(format!("DID: {} is not derived from these keys, they give {}", did, expected)));
+    }
+    Ok(())
+}
+
+//checks a proof of knowledge of x for (y1, y2) made for this context
+//returns c and s so the verifier can record the transcript like any other
+pub fn verify<G: Group>(zkp: &ZKP<G>, y1: &G::Element, y2: &G::Element, proof: &Proof, context: &[u8], name: &str) -> Result<(G::Scalar, G::Scalar)> {
+    let (r1, r2, s) = proof.decode(zkp)?;
+    let c = zkp.challenge_hash(y1, y2, &r1, &r2, context);
+    if !zkp.verify_solution(&r1, &r2, y1, y2, &c, &s) {
+        return Err(ZkpAuthError::Crypto(format!("{} does not verify", name)));
+    }
+    Ok((c, s))
+}
+
+//the request that registers x under did, for the prover
+//an enrollment also needs the credential, e.g. RegisterRequest { credential, ..register_request(...) }
+pub fn register_request<G: Group>(zkp: &ZKP<G>, did: &str, x: &G::Scalar, group: String) -> RegisterRequest {
+    register_request_with(&mut OsRng, zkp, did, x, group)
+}
+
+pub fn register_request_with<G: Group, R: RngCore + CryptoRng + ?Sized>(rng: &mut R, zkp: &ZKP<G>, did: &str, x: &G::Scalar, group: String) -> RegisterRequest {
+    let (y1, y2) = zkp.exponentiate_pair(x);
+    let (r1, r2, s) = zkp.prove_non_interactive_with(rng, x, &registration_context(did));
+    RegisterRequest {
+        user: did.to_string(),
+        y1: zkp.group.element_to_bytes(&y1),
+        y2: zkp.group.element_to_bytes(&y2),
+        group,
+        proof: Some(ProofOfKnowledge::from(&Proof::new(zkp, &r1, &r2, &s))),
+        key_update: None,
+        credential: String::new(),
+    }
+}
+
+//the request that moves did from the current x to the new one
+pub fn key_update_request<G: Group>(zkp: &ZKP<G>, did: &str, current: &G::Scalar, new: &G::Scalar, group: String) -> RegisterRequest {
+    let mut request = register_request(zkp, did, new, group);
+    let (r1, r2, s) = zkp.prove_non_interactive(current, &key_update_context(did, &request.y1, &request.y2));
+    request.key_update = Some(ProofOfKnowledge::from(&Proof::new(zkp, &r1, &r2, &s)));
+    request
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{context, RFC5114_1024_GROUP_ID};
+
+    fn keys<G: Group>(zkp: &ZKP<G>, request: &RegisterRequest) -> (G::Element, G::Element) {
+        (zkp.decode_element(&request.y1).unwrap(), zkp.decode_element(&request.y2).unwrap())
+    }
+
+    #[test]
+    fn test_registration_proof_is_bound_to_the_did() {
+        let zkp = context::modp(RFC5114_1024_GROUP_ID).unwrap();
+        let x = zkp.group.random_scalar();
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+        let did = DID::from_public_keys(&zkp.group.element_to_bytes(&y1), &zkp.group.element_to_bytes(&y2)).to_string();
+
+        let request = register_request(zkp, &did, &x, String::new());
+        check_did(&request.user, &request.y1, &request.y2).unwrap();
+        let proof = Proof::from(request.proof.as_ref().unwrap());
+        assert!(verify(zkp, &y1, &y2, &proof, &registration_context(&did), "proof").is_ok());
+
+        //the same proof says nothing about another DID, and the keys give only one DID
+        let other = "did:zkp:00000000000000000000000000000000";
+        assert!(matches!(verify(zkp, &y1, &y2, &proof, &registration_context(other), "proof"), Err(ZkpAuthError::Crypto(_))));
+        assert!(matches!(check_did(other, &request.y1, &request.y2), Err(ZkpAuthError::Crypto(_))));
+        assert!(matches!(check_did("not a did", &request.y1, &request.y2), Err(ZkpAuthError::Encoding(_))));
+    }
+
+    #[test]
+    fn test_key_update_proof_names_the_new_keys() {
+        let zkp = context::ristretto255();
+        let (current, new) = (zkp.group.random_scalar(), zkp.group.random_scalar());
+        let (y1, y2) = zkp.exponentiate_pair(&current);
+        let request = key_update_request(zkp, "did:zkp:a", &current, &new, String::new());
+        let update = Proof::from(request.key_update.as_ref().unwrap());
+        assert!(verify(zkp, &y1, &y2, &update, &key_update_context("did:zkp:a", &request.y1, &request.y2), "key update").is_ok());
+
+        //it can not hand the DID to any other keys
+        let (other1, other2) = keys(zkp, &register_request(zkp, "did:zkp:a", &zkp.group.random_scalar(), String::new()));
+        let context = key_update_context("did:zkp:a", &zkp.group.element_to_bytes(&other1), &zkp.group.element_to_bytes(&other2));
+        assert!(verify(zkp, &y1, &y2, &update, &context, "key update").is_err());
+        //and the new keys come with their own proof
+        let (new1, new2) = keys(zkp, &request);
+        let proof = Proof::from(request.proof.as_ref().unwrap());
+        assert!(verify(zkp, &new1, &new2, &proof, &registration_context("did:zkp:a"), "proof").is_ok());
+    }
+}
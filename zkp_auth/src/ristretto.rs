@@ -0,0 +1,244 @@
+//Chaum-Pedersen over the Ristretto255 prime order group.
+//Points and scalars are 32 bytes and an exponentiation is a scalar
+//multiplication on curve25519, which is much cheaper than a 1024-bit modpow.
+
+use crate::group::{bits_to_int, check_len, Group};
+use crate::hash_to_group::{beta_seed, hash_to_point};
+use crate::validation::ValidationError;
+use curve25519_dalek::constants::{RISTRETTO_BASEPOINT_POINT, RISTRETTO_BASEPOINT_TABLE};
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoBasepointTable, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::{Identity, VartimeMultiscalarMul};
+use rand::{CryptoRng, RngCore};
+use sha2::Sha512;
+use subtle::ConstantTimeEq;
+
+pub const RISTRETTO255_GROUP_ID: &str = "ristretto255";
+
+#[derive(Clone)]
+pub struct Ristretto255Group {
+    pub alpha: RistrettoPoint,
+    pub beta: RistrettoPoint,
+    //precomputed multiples of beta, alpha has the one dalek ships with
+    beta_table: RistrettoBasepointTable,
+}
+
+//the table is large, the points are what matters
+impl std::fmt::Debug for Ristretto255Group {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ristretto255Group").field("alpha", &self.alpha).field("beta", &self.beta).finish()
+    }
+}
+
+impl Ristretto255Group {
+    //alpha is the standard base point, beta is hashed to the curve so
+    //nobody knows its discrete log with respect to alpha
+    pub fn new() -> Self {
+        let beta = hash_to_point(beta_seed(RISTRETTO255_GROUP_ID).as_bytes());
+        Self {
+            alpha: RISTRETTO_BASEPOINT_POINT,
+            beta_table: RistrettoBasepointTable::create(&beta),
+            beta,
+        }
+    }
+}
+
+impl Default for Ristretto255Group {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Group for Ristretto255Group {
+    type Element = RistrettoPoint;
+    type Scalar = Scalar;
+
+    fn alpha(&self) -> &RistrettoPoint {
+        &self.alpha
+    }
+
+    fn beta(&self) -> &RistrettoPoint {
+        &self.beta
+    }
+
+    fn mul(&self, a: &RistrettoPoint, b: &RistrettoPoint) -> RistrettoPoint {
+        a + b
+    }
+
+    fn exp(&self, base: &RistrettoPoint, exponent: &Scalar) -> RistrettoPoint {
+        base * exponent
+    }
+
+    //the tables are only used while alpha and beta are still the points they were made for
+    fn exp_alpha(&self, exponent: &Scalar) -> RistrettoPoint {
+        if self.alpha == RISTRETTO_BASEPOINT_POINT {
+            return RISTRETTO_BASEPOINT_TABLE * exponent;
+        }
+        self.alpha * exponent
+    }
+
+    fn exp_beta(&self, exponent: &Scalar) -> RistrettoPoint {
+        if self.beta == self.beta_table.basepoint() {
+            return &self.beta_table * exponent;
+        }
+        self.beta * exponent
+    }
+
+    fn multi_exp(&self, terms: &[(&RistrettoPoint, &Scalar)]) -> RistrettoPoint {
+        RistrettoPoint::vartime_multiscalar_mul(terms.iter().map(|(_, s)| *s), terms.iter().map(|(p, _)| *p))
+    }
+
+    //every ristretto point is in the prime order group, only the identity is excluded
+    fn validate_element(&self, e: &RistrettoPoint) -> Result<(), ValidationError> {
+        if *e == RistrettoPoint::identity() {
+            return Err(ValidationError::Identity);
+        }
+        Ok(())
+    }
+
+    //the group order is fixed by the curve, only the generators need checking
+    fn validate_parameters(&self) -> Result<(), ValidationError> {
+        self.validate_element(&self.alpha).map_err(|_| ValidationError::BadGenerator("alpha"))?;
+        self.validate_element(&self.beta).map_err(|_| ValidationError::BadGenerator("beta"))?;
+        if self.alpha == self.beta {
+            return Err(ValidationError::BadGenerator("beta"));
+        }
+        Ok(())
+    }
+
+    fn element_to_bytes(&self, e: &RistrettoPoint) -> Vec<u8> {
+        e.compress().to_bytes().to_vec()
+    }
+
+    fn element_len(&self) -> usize {
+        32
+    }
+
+    fn ct_eq(&self, a: &RistrettoPoint, b: &RistrettoPoint) -> bool {
+        a.ct_eq(b).into()
+    }
+
+    fn element_from_bytes(&self, bytes: &[u8]) -> Result<RistrettoPoint, ValidationError> {
+        check_len(bytes, self.element_len())?;
+        let point = CompressedRistretto::from_slice(bytes)
+            .map_err(|_| ValidationError::InvalidEncoding)?
+            .decompress()
+            .ok_or(ValidationError::InvalidEncoding)?;
+        self.validate_element(&point)?;
+        Ok(point)
+    }
+
+    fn scalar_add(&self, a: &Scalar, b: &Scalar) -> Scalar {
+        a + b
+    }
+
+    fn scalar_sub(&self, a: &Scalar, b: &Scalar) -> Scalar {
+        a - b
+    }
+
+    fn scalar_mul(&self, a: &Scalar, b: &Scalar) -> Scalar {
+        a * b
+    }
+
+    fn scalar_invert(&self, a: &Scalar) -> Option<Scalar> {
+        if *a == Scalar::ZERO {
+            return None;
+        }
+        Some(a.invert())
+    }
+
+    fn random_scalar_with<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R) -> Scalar {
+        //dalek wants a sized RNG, a reference to one is
+        Scalar::random(&mut &mut *rng)
+    }
+
+    fn scalar_to_bytes(&self, s: &Scalar) -> Vec<u8> {
+        s.to_bytes().to_vec()
+    }
+
+    fn scalar_len(&self) -> usize {
+        32
+    }
+
+    //only the canonical encoding (less than the group order) is accepted
+    fn scalar_from_bytes(&self, bytes: &[u8]) -> Result<Scalar, ValidationError> {
+        check_len(bytes, self.scalar_len())?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|_| ValidationError::InvalidEncoding)?;
+        Option::from(Scalar::from_canonical_bytes(bytes)).ok_or(ValidationError::InvalidEncoding)
+    }
+
+    //the digest is widened to 64 bytes first so the reduction has no noticeable bias
+    fn scalar_from_hash(&self, digest: &[u8]) -> Scalar {
+        Scalar::hash_from_bytes::<Sha512>(digest)
+    }
+
+    //the order is 2^252 + 27742317777372353535851937790883648493, 253 bits
+    //bits2int is defined on big-endian integers, scalars are encoded little-endian
+    fn scalar_from_bits(&self, bits: &[u8]) -> Option<Scalar> {
+        let mut le = bits_to_int(bits, 253).to_bytes_le();
+        le.resize(32, 0);
+        Option::from(Scalar::from_canonical_bytes(le.try_into().ok()?))
+    }
+
+    fn parameters_bytes(&self) -> Vec<u8> {
+        let mut out = RISTRETTO255_GROUP_ID.as_bytes().to_vec();
+        out.extend_from_slice(self.alpha.compress().as_bytes());
+        out.extend_from_slice(self.beta.compress().as_bytes());
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ZKP;
+
+    #[test]
+    fn test_ristretto_interactive() {
+        let zkp = ZKP::new(Ristretto255Group::new());
+
+        let x = zkp.group.random_scalar(); //the secret
+        let k = zkp.group.random_scalar();
+        let c = zkp.group.random_scalar();
+
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+        let (r1, r2) = zkp.exponentiate_pair(&k);
+
+        let s = zkp.solve(&k, &c, &x);
+        assert!(zkp.verify_solution(&r1, &r2, &y1, &y2, &c, &s));
+
+        //false secret
+        let s_fake = zkp.solve(&k, &c, &zkp.group.random_scalar());
+        assert!(!zkp.verify_solution(&r1, &r2, &y1, &y2, &c, &s_fake));
+    }
+
+    #[test]
+    fn test_ristretto_non_interactive() {
+        let zkp = ZKP::new(Ristretto255Group::new());
+
+        let x = zkp.group.random_scalar();
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+
+        let (r1, r2, s) = zkp.prove_non_interactive(&x, b"ctx");
+        assert!(zkp.verify_non_interactive(&r1, &r2, &y1, &y2, &s, b"ctx"));
+        assert!(!zkp.verify_non_interactive(&r1, &r2, &y1, &y2, &s, b"other"));
+    }
+
+    #[test]
+    fn test_ristretto_encoding() {
+        let group = Ristretto255Group::new();
+
+        let bytes = group.element_to_bytes(&group.beta);
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(group.element_from_bytes(&bytes), Ok(group.beta));
+
+        //the identity and short or non canonical inputs are rejected
+        assert_eq!(group.element_from_bytes(&[0u8; 32]), Err(ValidationError::Identity));
+        assert_eq!(group.element_from_bytes(&bytes[..31]), Err(ValidationError::WrongLength { expected: 32, got: 31 }));
+        assert_eq!(group.scalar_from_bytes(&[0xffu8; 32]), Err(ValidationError::InvalidEncoding));
+
+        let s = group.random_scalar();
+        assert_eq!(group.scalar_from_bytes(&group.scalar_to_bytes(&s)), Ok(s));
+        assert_eq!(group.validate_parameters(), Ok(()));
+    }
+}
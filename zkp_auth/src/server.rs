@@ -1,190 +1,2079 @@
-mod ssi;
-use num_bigint::BigUint;
-use tonic::{transport::Server, Code,Request, Response, Status, codegen::http::request};
-use zkp_auth::auth_server::{Auth, AuthServer}; 
-use zkp_auth::{RegisterRequest, RegisterResponse,ChallengeRequest, ChallengeResponse, SolutionResponse, SolutionRequest};               
-use std::collections::HashMap;
-
-use std::fmt::format;
+//tonic::Status is a large error type and every handler here returns it
+#![allow(clippy::result_large_err)]
+
+use tonic::{transport::Server, Request, Response, Status};
+use ::zkp_auth::zkp_proto::auth_server::{Auth, AuthServer};
+use ::zkp_auth::zkp_proto::{RegisterRequest, RegisterResponse,ChallengeRequest, ChallengeResponse, SolutionResponse, SolutionRequest, LoginRequest, LoginResponse, BatchLoginRequest, BatchLoginResponse, LoginResult};
+use ::zkp_auth::zkp_proto::{SessionInfo, ValidateSessionRequest, ValidateSessionResponse, RefreshSessionRequest, RefreshSessionResponse, LogoutRequest, LogoutResponse, ListSessionsRequest, ListSessionsResponse};
+use ::zkp_auth::zkp_proto::{GetJwksRequest, GetJwksResponse, IssueTokenRequest, IssueTokenResponse};
+use ::zkp_auth::zkp_proto::{RegistrationInfo, GetRegistrationRequest, GetRegistrationResponse};
+use ::zkp_auth::zkp_proto::{GetMetricsRequest, GetMetricsResponse, GetPuzzleRequest, GetPuzzleResponse};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+
 // For the message struct
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, MutexGuard};
+use rand::rngs::OsRng;
 
 //importing the zkp functions i made
-use ::zkp_auth::ZKP;
+use ::zkp_auth::{context, BatchEntry, Group, SecureRng, ZKP, ZkpAuthError, RISTRETTO255_GROUP_ID};
+use ::zkp_auth::error::Result;
+use ::zkp_auth::params::{self, LEGACY_GROUP_IDS, PARAMETER_SETS};
+use ::zkp_auth::proof::{self, Challenge, Commitment, Proof};
+use ::zkp_auth::registration;
+use ::zkp_auth::ssi::credential::VerifiableCredential;
+use ::zkp_auth::history::{CommitmentHistory, Seen};
+use ::zkp_auth::limit::{LimitPolicy, Limiter, PeerLimitLayer, Rate};
+use ::zkp_auth::login::{self, LOGIN_SKEW};
+use ::zkp_auth::pool::{VerifierPool, QUEUE_PER_WORKER};
+use ::zkp_auth::puzzle::{PuzzlePolicy, Puzzles, SEED_LEN};
+use ::zkp_auth::session::{Session, SessionPolicy};
+use ::zkp_auth::telemetry::{self, RpcTraceLayer};
+use ::zkp_auth::store::{ChallengeRecord, ChallengeStore, Enrollment, Retired, SessionStore, StoreConfig, Stores, Taken, UserRecord, UserStore};
+use ::zkp_auth::token::{Claims, TokenIssuer, TokenPolicy};
 
-pub mod zkp_auth{
-    include!("zkp_proto.rs");
-}
+//audit events kept in memory, the oldest is dropped first
+const AUDIT_LOG_LEN: usize = 1024;
+//logins accepted in one BatchLogin call
+const MAX_BATCH: usize = 1024;
+//how long an auth_id can be answered, --challenge-ttl <seconds> changes it
+const CHALLENGE_TTL: Duration = Duration::from_secs(60);
+//how often the sweeper evicts expired challenges
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+//how long a used or expired auth_id is remembered, so a late or repeated answer gets the right error
+//a used login context too, it must outlive the window its time is accepted in
+const RETIRED_FOR: Duration = Duration::from_secs(600);
+const _: () = assert!(2 * LOGIN_SKEW.as_secs() < RETIRED_FOR.as_secs());
+//characters of a session id, about 190 bits since it is a bearer secret
+const SESSION_ID_LEN: usize = 32;
 
 
-#[derive(Debug, Default)]
-struct AuthImpl {
-    pub  userInfo: Mutex<HashMap<String, UserInformation>>,  // Now keyed by DID
-    pub  auth_id_user_hashmap:Mutex<HashMap<String, String>>,  // Maps auth_id to DID
+//the server works with any group, the ones it allows are chosen in main
+//the state is in the stores, memory, a JSON file or SQLite (see zkp_auth::store)
+//a store call never calls another store, the closures given to users.update and users.register
+//only touch the record they are given, then audit_log; tokens is only used outside of them,
+//the sweeper holds rng while it rotates the keys
+//a handler that needs a challenge and then its user takes the challenge out first,
+//and no lock is held across an .await
+struct AuthImpl<G: Group> {
+    pub  groups: HashMap<String, &'static ZKP<G>>,  // Allowed parameter sets, keyed by id, shared from zkp_auth::context
+    pub  users: Arc<dyn UserStore>,  // Registered DIDs
+    pub  challenges: Arc<dyn ChallengeStore>,  // Open interactive logins and retired auth_ids, keyed by auth_id
+    pub  challenge_ttl: Duration,  // How long a challenge can be answered
+    pub  sessions: Arc<dyn SessionStore>,  // Sessions handed out by logins, keyed by session id
+    pub  tokens: TokenIssuer,  // Signs a token for every session, other services check it offline
+    pub  audit_log: Mutex<VecDeque<AuditEvent>>,  // Security relevant events, newest last
+    pub  rng: Mutex<Box<dyn SecureRng>>,  // Source of challenges, auth_ids and session ids
+    pub  pool: VerifierPool,  // Runs every exponentiation, no lock is held while it does
+    pub  limiter: Arc<Limiter>,  // Rate limits, lockouts and challenge caps, shared with the PeerLimitLayer in front
+    pub  puzzles: Puzzles,  // Hands out and checks the puzzles a challenge needs while many are open
   
 }
 
-#[derive(Debug, Default)]
-pub struct UserInformation {
-    pub did: String,        // Store DID instead of username
-    pub y1: BigUint, //these two are used for registration
-    pub y2: BigUint,
+//events an operator has to look at, they are printed and kept in audit_log
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditEvent {
+    //two accepting proofs used the same nonce with different challenges, the secret can be computed from them
+    //witness_recovered is true when the server did so and the result matched y1
+    KeyCompromised { did: String, witness_recovered: bool },
+    //a challenge was asked for with a commitment the DID already used
+    CommitmentReused { did: String },
+    //an accepting proof that was already accepted once
+    ProofReplayed { did: String },
+    //a login attempt for a DID that is marked compromised
+    CompromisedLogin { did: String },
+    //a registered DID was handed over to new keys with a proof by its old ones
+    KeysUpdated { did: String },
+    //the proofs for a DID failed too often in a row, it is refused until then
+    LockedOut { did: String, until: DateTime<Utc> },
+}
+
+impl AuditEvent {
+    //audit events go to their own target, e.g. RUST_LOG=info,audit=warn, with the DID hashed like every other log line
+    fn log(&self) {
+        match self {
+            AuditEvent::KeyCompromised { did, witness_recovered } =>
+                tracing::error!(target: "audit", did = %telemetry::did(did), witness_recovered, "key compromised, a nonce was reused"),
+            AuditEvent::CommitmentReused { did } =>
+                tracing::warn!(target: "audit", did = %telemetry::did(did), "commitment reused"),
+            AuditEvent::ProofReplayed { did } =>
+                tracing::warn!(target: "audit", did = %telemetry::did(did), "proof replayed"),
+            AuditEvent::CompromisedLogin { did } =>
+                tracing::warn!(target: "audit", did = %telemetry::did(did), "login for a compromised DID"),
+            AuditEvent::KeysUpdated { did } =>
+                tracing::warn!(target: "audit", did = %telemetry::did(did), "keys updated"),
+            AuditEvent::LockedOut { did, until } =>
+                tracing::warn!(target: "audit", did = %telemetry::did(did), until = %until.to_rfc3339(), "locked out after failed proofs"),
+        }
+    }
+}
+
+impl<G: Group> AuthImpl<G> {
+    fn new(groups: HashMap<String, &'static ZKP<G>>) -> Self {
+        Self::with_rng(groups, Box::new(OsRng))
+    }
+
+    //a seeded RNG makes every challenge and id the server hands out reproducible
+    fn with_rng(groups: HashMap<String, &'static ZKP<G>>, mut rng: Box<dyn SecureRng>) -> Self {
+        let tokens = TokenIssuer::new(TokenPolicy::default(), rng.as_mut(), Utc::now());
+        let mut puzzle_key = [0u8; 32];
+        rng.fill_bytes(&mut puzzle_key);
+        let Stores { users, challenges, sessions } = Stores::memory(SessionPolicy::default());
+        Self {
+            groups,
+            users,
+            challenges,
+            challenge_ttl: CHALLENGE_TTL,
+            sessions,
+            tokens,
+            audit_log: Mutex::new(VecDeque::new()),
+            rng: Mutex::new(rng),
+            pool: VerifierPool::default(),
+            limiter: Arc::new(Limiter::default()),
+            puzzles: Puzzles::new(PuzzlePolicy::default(), puzzle_key),
+        }
+    }
+
+    fn with_pool(self, pool: VerifierPool) -> Self {
+        Self { pool, ..self }
+    }
+
+    fn with_challenge_ttl(self, challenge_ttl: Duration) -> Self {
+        Self { challenge_ttl, ..self }
+    }
+
+    //the state goes to these stores, what was in the ones before is not carried over
+    fn with_stores(self, stores: Stores) -> Self {
+        let Stores { users, challenges, sessions } = stores;
+        Self { users, challenges, sessions, ..self }
+    }
+
+    //fresh in-memory stores with this session policy, the other backends are given theirs when opened
+    #[cfg(test)]
+    fn with_session_policy(self, policy: SessionPolicy) -> Self {
+        self.with_stores(Stores::memory(policy))
+    }
+
+    fn with_token_policy(self, policy: TokenPolicy) -> Self {
+        Self { tokens: self.tokens.with_policy(policy), ..self }
+    }
+
+    //the puzzles handed out before stay good
+    fn with_puzzle_policy(self, policy: PuzzlePolicy) -> Self {
+        Self { puzzles: self.puzzles.with_policy(policy), ..self }
+    }
+
+    //a fresh limiter, what the one before counted is dropped
+    fn with_limit_policy(self, policy: LimitPolicy) -> Self {
+        Self { limiter: Arc::new(Limiter::new(policy)), ..self }
+    }
+
+    //the lock is only held while the value is drawn
+    fn random<T>(&self, draw: impl FnOnce(&mut dyn SecureRng) -> T) -> Result<T> {
+        let mut rng = lock(&self.rng, "random number generator")?;
+        Ok(draw(rng.as_mut()))
+    }
+
+    fn random_id(&self) -> Result<String> {
+        self.random(|rng| ZKP::generate_random_string_with(rng, 12))
+    }
+
+    fn audit(&self, event: AuditEvent) {
+        event.log();
+        //a poisoned audit log must not hide the event, it was logged above
+        if let Ok(mut log) = self.audit_log.lock() {
+            if log.len() == AUDIT_LOG_LEN {
+                log.pop_front();
+            }
+            log.push_back(event);
+        }
+    }
+
+    //counts a proof from peer that did not verify, the one that locks peer out of the DID is audited
+    fn proof_failed(&self, did: &str, peer: Option<IpAddr>, error: ZkpAuthError) -> ZkpAuthError {
+        if let Some(until) = self.limiter.failed(did, peer, Utc::now()) {
+            self.audit(AuditEvent::LockedOut { did: did.to_string(), until });
+        }
+        error
+    }
+
+    //refuses any login for a DID whose secret is known to have leaked
+    fn check_not_compromised(&self, user_info: &UserRecord) -> Result<()> {
+        if user_info.compromised {
+            self.audit(AuditEvent::CompromisedLogin { did: user_info.did.clone() });
+            return Err(compromised(&user_info.did));
+        }
+        Ok(())
+    }
+
+    //called with every accepting transcript, before access is granted
+    //a commitment seen before with another challenge means the nonce was reused and x can be
+    //computed by anyone who saw both proofs, the DID is marked compromised and the login refused
+    //only accepting transcripts are recorded so nobody without the secret can trigger this
+    //the witness check is the one exponentiation done inside a user store update, it runs once per DID
+    //since the DID is refused from then on
+    fn record_transcript(&self, zkp: &ZKP<G>, user_info: &mut UserRecord, commitment: &Commitment, c: &G::Scalar, s: &G::Scalar) -> Result<()> {
+        match user_info.history.record(commitment, Challenge::new(zkp, c), proof::Response::new(zkp, s)) {
+            Seen::New => Ok(()),
+            //single-use challenges and login contexts refuse a replay before this, it is kept as a second check
+            Seen::Replay => {
+                self.audit(AuditEvent::ProofReplayed { did: user_info.did.clone() });
+                Err(ZkpAuthError::Protocol(format!("Proof for DID: {} was already used", user_info.did)))
+            }
+            Seen::NonceReuse(previous) => {
+                user_info.compromised = true;
+                //confirms the leak by doing what an attacker would, the witness is dropped right away
+                let witness = zkp.extract_witness(&previous.challenge.decode(zkp)?, &previous.response.decode(zkp)?, c, s);
+                let y1 = stored_element(zkp, &user_info.y1, "y1")?;
+                let witness_recovered = witness.is_some_and(|x| zkp.group.ct_eq(&zkp.exponentiate_pair(&x).0, &y1));
+                self.audit(AuditEvent::KeyCompromised { did: user_info.did.clone(), witness_recovered });
+                Err(compromised(&user_info.did))
+            }
+        }
+    }
+
+    //the parameter set a request asks for, if this server allows it
+    //every request names its group, there is no default to fall back to
+    fn zkp_for(&self, group: &str) -> Result<&'static ZKP<G>> {
+        if group.is_empty() {
+            return Err(ZkpAuthError::Encoding("The request names no group, e.g. rfc5114-2048-256.".to_string()));
+        }
+        self.groups.get(group).copied()
+            .ok_or_else(|| ZkpAuthError::Protocol(format!("Group {} is not allowed by this server.", group)))
+    }
+
+    //takes the challenge out for its one and only answer
+    //the store retires the auth_id in the same step, so a second answer sent at the same time
+    //finds it used and not missing
+    fn take_challenge(&self, auth_id: &str, now: DateTime<Utc>) -> Result<ChallengeRecord> {
+        match self.challenges.take(auth_id, now, self.challenge_ttl)? {
+            Taken::Open(challenge) => Ok(challenge),
+            Taken::Retired(Retired::Used) => Err(ZkpAuthError::Protocol(format!("AuthId: {} was already used, ask for a new challenge", auth_id))),
+            Taken::Retired(Retired::Expired) => Err(ZkpAuthError::Expired(format!("AuthId: {} expired, ask for a new challenge", auth_id))),
+            Taken::Unknown => Err(ZkpAuthError::NotFound(format!("AuthId: {} not found in database", auth_id))),
+        }
+    }
+
+    //evicts the challenges that ran out of time and forgets auth_ids retired long ago
+    //returns how many challenges were evicted
+    fn sweep(&self, now: DateTime<Utc>) -> Result<usize> {
+        self.challenges.sweep(now, self.challenge_ttl, RETIRED_FOR)
+    }
+}
+
+//what a login needs from the user store, copied out so the lock is not held while it is checked
+struct LoginKeys<G: Group> {
+    did: String,
+    group: String,
+    zkp: &'static ZKP<G>,
+    y1: G::Element,
+    y2: G::Element,
+    retire: String,  // The key the login context is retired under
+    peer: Option<IpAddr>,  // The address the login came from, failures are counted for it
+}
+
+//a login that passed every check but the proof
+struct PendingLogin<G: Group> {
+    did: String,
+    group: String,
+    zkp: &'static ZKP<G>,
+    commitment: Commitment,
+    entry: BatchEntry<G>,
+    retire: Option<String>,  // The login context, None for an answer to a challenge, which is single use already
+    peer: Option<IpAddr>,  // The address the login came from
+}
+
+impl<G: Group> LoginKeys<G> {
+    //decodes the proof and recomputes c from the transcript, the client never sends it
+    //the subgroup checks of r1 and r2 are exponentiations, this runs in the pool
+    fn decode(self, request: &LoginRequest) -> Result<PendingLogin<G>> {
+        let proof = Proof::from(request);
+        let (r1, r2, s) = proof.decode(self.zkp)?;
+        let c = self.zkp.challenge_hash(&self.y1, &self.y2, &r1, &r2, &request.context);
+        Ok(PendingLogin {
+            did: self.did,
+            group: self.group,
+            zkp: self.zkp,
+            commitment: proof.commitment,
+            entry: BatchEntry { y1: self.y1, y2: self.y2, r1, r2, c, s },
+            retire: Some(self.retire),
+            peer: self.peer,
+        })
+    }
+}
+
+impl<G: Group> PendingLogin<G> {
+    fn verify(&self) -> bool {
+        let entry = &self.entry;
+        self.zkp.verify_solution(&entry.r1, &entry.r2, &entry.y1, &entry.y2, &entry.c, &entry.s)
+    }
+
+    //why the proof did not verify: keys enrolled with the beta of before never can, that is named
+    //as the reason instead of error; only the legacy groups hold such keys and the check is an
+    //exponentiation, so it runs in the pool job that checked the proof
+    fn failure(&self, error: ZkpAuthError) -> ZkpAuthError {
+        let group = &self.zkp.group;
+        params::check_current_keys(&self.group, &self.did, &group.element_to_bytes(&self.entry.y1), &group.element_to_bytes(&self.entry.y2))
+            .err().unwrap_or(error)
+    }
+}
+
+impl<G: Group> AuthImpl<G> {
+    //checks the DID may log in and copies its keys
+    //the context must be a login context of the DID made just now, see zkp_auth::login
+    fn login_keys(&self, request: &LoginRequest, peer: Option<IpAddr>) -> Result<LoginKeys<G>> {
+        if request.r1.is_empty() || request.r2.is_empty() || request.s.is_empty() {
+            return Err(ZkpAuthError::Encoding("Proof values cannot be empty.".to_string()));
+        }
+        let retire = login::check_context(&request.context, &request.user, Utc::now())?;
+
+        let zkp = self.zkp_for(&request.group)?;
+        let user_info = self.registered(&request.user)?;
+        check_group(&user_info, &request.group)?;
+        self.check_not_compromised(&user_info)?;
+        self.limiter.check_did(&user_info.did, peer, Utc::now())?;
+
+        Ok(LoginKeys {
+            did: request.user.clone(),
+            y1: stored_element(zkp, &user_info.y1, "y1")?,
+            y2: stored_element(zkp, &user_info.y2, "y2")?,
+            group: user_info.group,
+            zkp,
+            retire,
+            peer,
+        })
+    }
+
+    fn registered(&self, did: &str) -> Result<UserRecord> {
+        self.users.get(did)?.ok_or_else(|| ZkpAuthError::NotFound(format!("DID: {} not found in database", did)))
+    }
+
+    //after the proof verified: the nonce reuse check, a session and a token for it
+    //the DID is looked up again, it may have been marked compromised while the proof was checked
+    fn finish_login(&self, pending: &PendingLogin<G>) -> Result<(String, String)> {
+        //a login context is good for one login, the same request sent again is refused here
+        if let Some(context) = &pending.retire
+            && !self.challenges.retire(context, Utc::now())?
+        {
+            self.audit(AuditEvent::ProofReplayed { did: pending.did.clone() });
+            return Err(ZkpAuthError::Protocol(format!("Login proof for DID: {} was already used", pending.did)));
+        }
+        self.users.update(&pending.did, &mut |user_info| {
+            self.check_not_compromised(user_info)?;
+            self.record_transcript(pending.zkp, user_info, &pending.commitment, &pending.entry.c, &pending.entry.s)
+        })?;
+        self.limiter.succeeded(&pending.did, pending.peer);
+
+        let session_id = self.random(|rng| ZKP::generate_random_string_with(rng, SESSION_ID_LEN))?;
+        let (session, ended) = self.sessions.create(&pending.did, session_id, Utc::now())?;
+        for old in ended {
+            tracing::info!(session = %telemetry::session(&old.id), max_per_did = self.sessions.policy().max_per_did, "ended the oldest session of the DID");
+        }
+        let (token, _) = self.token_for(&session)?;
+        Ok((session.id, token))
+    }
 
-    pub r1: BigUint,//these two are used for authentication
-    pub r2: BigUint,
+    //a token never outlives the session it was issued for
+    fn token_for(&self, session: &Session) -> Result<(String, Claims)> {
+        let now = Utc::now();
+        self.tokens.issue(&session.did, &session.id, session.created_at, session.expires_at(self.sessions.policy()), now)
+    }
 
-    pub c: BigUint, //the below are used to verify the proof
-    pub s: BigUint,
-    pub session_id: String,
-    
+    //the session a request came with, if it is live
+    fn live_session(&self, session_id: &str) -> Result<Session> {
+        if session_id.is_empty() {
+            return Err(ZkpAuthError::Encoding("Session ID cannot be empty.".to_string()));
+        }
+        self.sessions.validate(session_id, Utc::now())
+    }
+
+    fn session_info(&self, session: &Session) -> SessionInfo {
+        SessionInfo {
+            session_id: session.id.clone(),
+            did: session.did.clone(),
+            created_at: session.created_at.timestamp(),
+            last_seen: session.last_seen.timestamp(),
+            expires_at: session.expires_at(self.sessions.policy()).timestamp(),
+        }
+    }
+}
+
+//a poisoned lock means a handler panicked while holding it, the request fails instead of the server
+fn lock<'a, T>(mutex: &'a Mutex<T>, name: &str) -> Result<MutexGuard<'a, T>> {
+    mutex.lock().map_err(|_| ZkpAuthError::Storage(format!("{} is unavailable", name)))
+}
+
+fn already_enrolled(did: &str) -> ZkpAuthError {
+    ZkpAuthError::AlreadyExists(format!("DID: {} is already enrolled, log in with it or send a key update", did))
+}
+
+fn compromised(did: &str) -> ZkpAuthError {
+    ZkpAuthError::Crypto(format!("DID: {} is compromised by a reused nonce, register new keys", did))
+}
+
+//decodes a group element sent by the client, it must be in range and in the order q subgroup
+//a value from a store, it passed decode_element before it was stored so the subgroup check is not repeated
+fn stored_element<G: Group>(zkp: &ZKP<G>, bytes: &[u8], name: &str) -> Result<G::Element> {
+    zkp.group.element_from_bytes(bytes).map_err(|e| ZkpAuthError::Storage(format!("stored {} {}", name, e)))
+}
+
+fn decode_element<G: Group>(zkp: &ZKP<G>, bytes: &[u8], name: &str) -> Result<G::Element> {
+    zkp.decode_element(bytes).map_err(|e| ZkpAuthError::invalid(name, e))
+}
+
+
+//refuses to start with parameters a proof would not be sound in
+fn check_parameters<G: Group>(groups: &HashMap<String, &'static ZKP<G>>) -> bool {
+    for (id, zkp) in groups {
+        if let Err(e) = zkp.group.validate_parameters() {
+            tracing::error!(group = %id, error = %e, "group parameters are invalid");
+            return false;
+        }
+    }
+    tracing::info!("group parameters checked");
+    true
+}
+
+//a request must use the group the DID was registered in
+fn check_group(user_info: &UserRecord, group: &str) -> Result<()> {
+    if user_info.group != group {
+        return Err(ZkpAuthError::Protocol(format!("DID: {} is registered in group {}, not {}", user_info.did, user_info.group, group)));
+    }
+    Ok(())
+}
+
+//the enrollment of a DID as GetRegistration returns it, the times are unix seconds
+fn registration_info(user_info: &UserRecord, with_credential: bool) -> Result<RegistrationInfo> {
+    let enrollment = user_info.enrollment.as_ref();
+    Ok(RegistrationInfo {
+        did: user_info.did.clone(),
+        group: user_info.group.clone(),
+        y1: user_info.y1.clone(),
+        y2: user_info.y2.clone(),
+        enrolled_at: enrollment.map_or(0, |enrollment| enrollment.enrolled_at.timestamp()),
+        keys_updated_at: enrollment.and_then(|enrollment| enrollment.keys_updated_at).map_or(0, |at| at.timestamp()),
+        compromised: user_info.compromised,
+        credential: match enrollment {
+            Some(enrollment) if with_credential => serde_json::to_string(&enrollment.credential)?,
+            _ => String::new(),
+        },
+    })
 }
 
 #[tonic::async_trait]
-impl Auth for AuthImpl {
+impl<G: Group> Auth for AuthImpl<G> {
 
-    async fn register(&self, request:Request<RegisterRequest>) -> Result<Response<RegisterResponse>,Status> {
-        println!("Processing Registration: {:?}", request);
+    async fn register(&self, request:Request<RegisterRequest>) -> std::result::Result<Response<RegisterResponse>,Status> {
         let request = request.into_inner();//into inner gives us access to the the private field
 
-        let user_identifier = request.user;  // This is now a DID
+        let user_identifier = request.user.clone();  // This is now a DID
+        telemetry::record_did(&user_identifier);
 
         //to prevent empty entries for y1 and y2, which can break the proof
-        if (request.y1.is_empty()|| request.y2.is_empty()) {
-        return Err(Status::new(Code::InvalidArgument, "Public key y cannot be empty."));
+        if request.y1.is_empty()|| request.y2.is_empty() {
+        return Err(ZkpAuthError::Encoding("Public key y cannot be empty.".to_string()).into());
         }
          
         // Check if this is a DID format
-        if user_identifier.starts_with("did:") {
-            println!("🆔 Registering user with DID: {}", user_identifier);
-        } else {
-            println!("⚠️  Warning: Expected DID format (did:method:identifier)");
+        if !user_identifier.starts_with("did:") {
+            tracing::warn!("registration for a user that is not a DID (did:method:identifier)");
         }
 
-        let mut userInfo = UserInformation::default();
-        userInfo.did = user_identifier.clone();
-        userInfo.y1 =  BigUint::from_bytes_be(&request.y1);
-        userInfo.y2 = BigUint::from_bytes_be(&request.y2);
+        let zkp = self.zkp_for(&request.group)?;
+        let group = request.group;
+        let proof = request.proof.as_ref().map(Proof::from)
+            .ok_or_else(|| ZkpAuthError::Encoding("Registration needs a proof of knowledge of x.".to_string()))?;
+        let key_update = request.key_update.as_ref().map(Proof::from);
+        if request.credential.is_empty() {
+            return Err(ZkpAuthError::Encoding("Registration needs the credential issued to the DID for these keys.".to_string()).into());
+        }
+        let credential: VerifiableCredential = serde_json::from_str(&request.credential)
+            .map_err(|e| ZkpAuthError::Encoding(format!("the credential is not valid: {}", e)))?;
+        credential.check_holder(&user_identifier, &request.y1, &request.y2)?;
 
-        let mut userInfo_storage = &mut self.userInfo.lock().unwrap(); //need to dd errror handling
-        
-        userInfo_storage.insert(user_identifier, userInfo);
-        Ok(Response::new(RegisterResponse { }))
+        //a DID enrolls once, after that only a proof by the keys it has now gives it new ones
+        let current = self.users.get(&user_identifier)?;
+        if let Some(current) = &current {
+            if current.compromised {
+                return Err(compromised(&user_identifier).into());
+            }
+            if key_update.is_none() {
+                return Err(already_enrolled(&user_identifier).into());
+            }
+            if current.group != group {
+                return Err(ZkpAuthError::Protocol(format!("DID: {} is registered in group {}, new keys must be in it too", user_identifier, current.group)).into());
+            }
+        } else if key_update.is_some() {
+            return Err(ZkpAuthError::NotFound(format!("DID: {} not found in database, there are no keys to update", user_identifier)).into());
+        }
+
+        //the keys are only stored once they decoded and the proofs verified, the store keeps their bytes
+        let (did, y1, y2) = (user_identifier.clone(), request.y1, request.y2);
+        let (y1, y2, updated_from, (c, s)) = {
+            let proof = proof.clone();
+            self.pool.run(move || {
+                let (new1, new2) = (decode_element(zkp, &y1, "y1")?, decode_element(zkp, &y2, "y2")?);
+                //the first keys of a DID are the ones it is derived from
+                if current.is_none() {
+                    registration::check_did(&did, &y1, &y2)?;
+                }
+                let transcript = registration::verify(zkp, &new1, &new2, &proof, &registration::registration_context(&did), "Registration proof")?;
+                let mut updated_from = None;
+                if let (Some(update), Some(current)) = (key_update, current) {
+                    let (old1, old2) = (stored_element(zkp, &current.y1, "y1")?, stored_element(zkp, &current.y2, "y2")?);
+                    registration::verify(zkp, &old1, &old2, &update, &registration::key_update_context(&did, &y1, &y2), "Key update proof")?;
+                    updated_from = Some((current.y1, current.y2));
+                }
+                Ok((y1, y2, updated_from, transcript))
+            }).await?
+        };
+
+        //the check and the write are one step of the store, no login can slip in between
+        //the record is looked at again, it may have changed while the proofs were checked
+        let now = Utc::now();
+        let mut registered = None;
+        self.users.register(&user_identifier, &mut |existing| {
+            let enrollment = match existing {
+                //registering the same DID again must not clear the flag, its secret is public
+                Some(existing) if existing.compromised => return Err(compromised(&user_identifier)),
+                Some(_) if updated_from.is_none() => return Err(already_enrolled(&user_identifier)),
+                Some(existing) if updated_from.as_ref() != Some(&(existing.y1.clone(), existing.y2.clone())) => {
+                    return Err(ZkpAuthError::Protocol(format!("DID: {} got other keys meanwhile, send the key update again", user_identifier)));
+                }
+                Some(existing) if existing.y1 == y1 && existing.y2 == y2 => {
+                    return Err(ZkpAuthError::Protocol(format!("DID: {} has these keys already", user_identifier)));
+                }
+                Some(existing) => Enrollment {
+                    enrolled_at: existing.enrollment.as_ref().map_or(now, |enrollment| enrollment.enrolled_at),
+                    credential: credential.clone(),
+                    keys_updated_at: Some(now),
+                },
+                None if updated_from.is_some() => {
+                    return Err(ZkpAuthError::NotFound(format!("DID: {} not found in database, there are no keys to update", user_identifier)));
+                }
+                None => Enrollment { enrolled_at: now, credential: credential.clone(), keys_updated_at: None },
+            };
+            //the registration proof is the first transcript of the keys, so a login reusing its nonce is caught
+            //it is never accepted as a login, its context is not a login context (see zkp_auth::login)
+            let mut history = CommitmentHistory::default();
+            history.record(&proof.commitment, Challenge::new(zkp, &c), proof::Response::new(zkp, &s));
+            let user_info = UserRecord {
+                did: user_identifier.clone(),
+                group: group.clone(),
+                y1: y1.clone(),
+                y2: y2.clone(),
+                history,
+                compromised: false,
+                enrollment: Some(enrollment),
+            };
+            registered = Some(user_info.clone());
+            Ok(user_info)
+        })?;
+        let user_info = registered.ok_or_else(|| ZkpAuthError::Storage(format!("DID: {} was not stored", user_identifier)))?;
+
+        //whoever held the old keys is logged out
+        if updated_from.is_some() {
+            self.audit(AuditEvent::KeysUpdated { did: user_identifier.clone() });
+            let revoked = self.sessions.revoke_all(&user_identifier)?;
+            tracing::info!(group = %user_info.group, revoked, "keys updated, the sessions of the old ones ended");
+        } else {
+            tracing::info!(group = %user_info.group, "enrolled");
+        }
+        Ok(Response::new(RegisterResponse { registration: Some(registration_info(&user_info, true)?) }))
 }
 
+    //whether a DID is enrolled, for a wallet deciding between enrolling and logging in
+    async fn get_registration(&self, request:Request<GetRegistrationRequest>) -> std::result::Result<Response<GetRegistrationResponse>,Status> {
+        let request = request.into_inner();
+        telemetry::record_did(&request.did);
+        let user_info = self.registered(&request.did)?;
+        //the credential holds personal data, only a session of the DID itself gets it
+        let with_credential = !request.session_id.is_empty() && {
+            let session = self.live_session(&request.session_id)?;
+            if session.did != user_info.did {
+                return Err(ZkpAuthError::Crypto(format!("The session is not one of DID: {}", user_info.did)).into());
+            }
+            true
+        };
+        Ok(Response::new(GetRegistrationResponse { registration: Some(registration_info(&user_info, with_credential)?) }))
+    }
 
-    async fn create_challenge(&self, request:Request<ChallengeRequest>) -> Result<Response<ChallengeResponse>,Status> {
-        println!("\n=== CHALLENGE SERVICE ===");
-        println!("Processing Challenge request: {:?}", request);
-        let request = request.into_inner();//into inner gives us access to the the private field
 
-        let user_identifier = request.user;  // This is now a DID
-        
-        // Check if this is a DID format
-        if user_identifier.starts_with("did:") {
-            println!("🆔 Creating challenge for DID: {}", user_identifier);
+    //a puzzle to solve before CreateChallenge, none while the server has few open challenges
+    //the server keeps nothing for it, so asking for many costs only the peer's rate limit
+    async fn get_puzzle(&self, request:Request<GetPuzzleRequest>) -> std::result::Result<Response<GetPuzzleResponse>,Status> {
+        let did = request.into_inner().did;
+        telemetry::record_did(&did);
+        if did.is_empty() {
+            return Err(ZkpAuthError::Encoding("DID cannot be empty.".to_string()).into());
+        }
+        let seed = self.random(|rng| {
+            let mut seed = [0u8; SEED_LEN];
+            rng.fill_bytes(&mut seed);
+            seed
+        })?;
+        let puzzle = self.puzzles.issue(&did, self.challenges.len()?, seed, Utc::now());
+        if let Some(puzzle) = &puzzle {
+            tracing::debug!(difficulty = puzzle.difficulty, "puzzle handed out");
         }
+        Ok(Response::new(GetPuzzleResponse { puzzle }))
+    }
+
+    async fn create_challenge(&self, request:Request<ChallengeRequest>) -> std::result::Result<Response<ChallengeResponse>,Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        let request = request.into_inner();//into inner gives us access to the the private field
+
+        let user_identifier = request.user.clone();  // This is now a DID
+        telemetry::record_did(&user_identifier);
         
         //to prevent any empty requests that can break the code
-        if (request.r1.is_empty() || request.r2.is_empty()){
-        return Err(Status::new(Code::InvalidArgument, "Commitments cannot be empty."));
+        if request.r1.is_empty() || request.r2.is_empty(){
+        return Err(ZkpAuthError::Encoding("Commitments cannot be empty.".to_string()).into());
         }
+        //under load the puzzle comes first, it costs two hashes and everything below costs more
+        self.puzzles.check(&request, self.challenges.len()?, Utc::now())?;
         //
+        let zkp = self.zkp_for(&request.group)?;
+        let commitment = Commitment::from(&request);
+        //only a commitment that decodes is stored, the store keeps its bytes
+        {
+            let commitment = commitment.clone();
+            self.pool.run(move || commitment.decode(zkp).map(|_| ())).await?;
+        }
 
-        let mut userInfo_storage = &mut self.userInfo.lock().unwrap(); //need to dd errror handling
-       
-        if let Some(userInfo) = userInfo_storage.get_mut(&user_identifier) {
-            userInfo.r1 = BigUint::from_bytes_be(&request.r1);
-            userInfo.r2 = BigUint::from_bytes_be(&request.r2);
-
-            let (_,_,_, q) = ZKP::get_zkp_constants(); //takes only the q(order) variable returned from the ZKP library 
-
-            let c = ZKP::generate_random_number_less_than(&q);
-            let auth_id = ZKP::generate_random_string(12);
-            
-            //storing c
-            userInfo.c = c.clone();
-
-            //Storing the user id for each DID
-            let mut auth_id_user_hashmap = &mut self.auth_id_user_hashmap.lock().unwrap(); //need to dd errror handling
-            auth_id_user_hashmap.insert(auth_id.clone(), user_identifier);
-            
-            Ok(Response::new(ChallengeResponse { auth_id, c: c.to_bytes_be() } ))
-        } else {
-            Err(Status::new(Code::NotFound, format!("DID: {} not found in database", user_identifier)))
+        //a login that records this commitment after the check below gets it past the check, the
+        //answer to this challenge is then refused by record_transcript as a reused nonce
+        let user_info = self.registered(&user_identifier)?;
+        check_group(&user_info, &request.group)?;
+        self.check_not_compromised(&user_info)?;
+        self.limiter.check_did(&user_identifier, peer, Utc::now())?;
+        //answering a fresh challenge for a used commitment would leak x, so no challenge is given
+        //this alone does not mark the DID compromised, anyone who saw the old transcript can send it
+        if user_info.history.find(&commitment).is_some() {
+            self.audit(AuditEvent::CommitmentReused { did: user_identifier.clone() });
+            return Err(ZkpAuthError::Protocol(format!("Commitment for DID: {} was already used, a nonce must never be reused", user_identifier)).into());
         }
 
-}
+        //every open challenge is a map entry until it is answered or swept, so there are only so many
+        //an address that holds many open for the DID only stops itself, see zkp_auth::limit
+        self.limiter.check_pending(&user_identifier, self.challenges.pending(&user_identifier, peer)?, self.challenges.len()?)?;
 
+        //drawn only once every check passed, a refused request leaves the RNG as it was
+        let c = self.random(|rng| zkp.group.random_scalar_with(rng))?;
+        let auth_id = self.random_id()?;
+        telemetry::record_login(&auth_id);
 
-      async fn verify_authentication(&self, request:Request<SolutionRequest>) -> Result<Response<SolutionResponse>,Status> {
-        
-        println!("\n=== ZKP VERIFIER ===");
-       
+        //storing the challenge under its own auth_id
+        let challenge = ChallengeRecord { did: user_identifier, group: user_info.group, commitment, c: Challenge::new(zkp, &c), created_at: Utc::now(), peer };
+        self.challenges.insert(&auth_id, challenge)?;
+        tracing::debug!("challenge created");
+
+        Ok(Response::new(ChallengeResponse { auth_id, c: Challenge::new(zkp, &c).c().to_vec() } ))
+}
 
-        println!("Processing Verification request: {:?}", request);
+
+      async fn verify_authentication(&self, request:Request<SolutionRequest>) -> std::result::Result<Response<SolutionResponse>,Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
         let request = request.into_inner();//into inner gives us access to the the private field
 
-        let auth_id = request.auth_id;
-        println!("  Verifying proof for user: {}", auth_id);
+        let auth_id = request.auth_id.clone();
+        telemetry::record_login(&auth_id);
         
         //preventing an empty solution
         if auth_id.is_empty() {
-        return Err(Status::new(Code::InvalidArgument, "Authentication ID cannot be empty."));
+        return Err(ZkpAuthError::Encoding("Authentication ID cannot be empty.".to_string()).into());
         }
 
         if request.s.is_empty() {
-        return Err(Status::new(Code::InvalidArgument, "Solution 's' cannot be empty."));
+        return Err(ZkpAuthError::Encoding("Solution 's' cannot be empty.".to_string()).into());
         }
         ///////
         
-        let mut auth_id_user_hashmap = &mut self.auth_id_user_hashmap.lock().unwrap(); //need to dd errror handling
-
-        if let Some(user_identifier) = auth_id_user_hashmap.get(&auth_id) {
-            let mut userInfo_storage = &mut self.userInfo.lock().unwrap(); //need to dd errror handling
-            let userInfo = userInfo_storage.get_mut(user_identifier).expect("auth id not found");
-
-            let s = BigUint::from_bytes_be(&request.s);
-
-            //creating the zkp instance
-            let (alpha, beta, p, q) = ZKP::get_zkp_constants();
-            let zkp = ZKP {alpha, beta, p, q};
-            
-            //creating the veriication result usinf the verfiy solution function
-            let verification_result = zkp.verify_solution(&userInfo.r1, &userInfo.r2, &userInfo.y1, &userInfo.y2, &userInfo.c, &s);
-            println!("Veification result: {}", verification_result);
-
-            if verification_result {
-    let session_id = ZKP::generate_random_string(12);
-    
-    println!("  ✅ Proof verified successfully!");
-    println!("  ✅ Self-Sovereign Identity assertion confirmed for DID: {}", user_identifier);
-    println!("  → Granting access with session: {}", session_id);
-    
-    Ok(Response::new(SolutionResponse{session_id}))
-} else {
-    println!("  ❌ Proof verification failed!");
-    Err(Status::new(Code::PermissionDenied, 
-    format!("Identity verification failed for auth_id: {}", auth_id)))
-}
-           
-        } else {
-            Err(Status::new(Code::NotFound, format!("AuthId: {} not found in database", auth_id)))
+        //the auth_id is used up here whatever happens next, a wrong answer cannot be corrected
+        //the challenge is taken out of the map before the user entry is looked at
+        let ChallengeRecord { did: user_identifier, group, commitment, c, created_at, .. } = self.take_challenge(&auth_id, Utc::now())?;
+        telemetry::record_did(&user_identifier);
+        tracing::debug!(answered_after_ms = (Utc::now() - created_at).num_milliseconds(), "challenge answered");
+        let zkp = self.zkp_for(&group)?;
+        let s = proof::Response::from(&request).decode(zkp)?;
+
+        let user_info = self.users.get(&user_identifier)?
+            .ok_or_else(|| ZkpAuthError::NotFound(format!("DID: {} for auth_id: {} not found in database", user_identifier, auth_id)))?;
+        check_group(&user_info, &group)?;
+        self.check_not_compromised(&user_info)?;
+        //this address may have been locked out of the DID since the challenge was given
+        self.limiter.check_lockout(&user_identifier, peer, Utc::now())?;
+        let pending = PendingLogin {
+            did: user_identifier.clone(),
+            group,
+            zkp,
+            entry: BatchEntry {
+                y1: stored_element(zkp, &user_info.y1, "y1")?,
+                y2: stored_element(zkp, &user_info.y2, "y2")?,
+                r1: stored_element(zkp, commitment.r1(), "r1")?,
+                r2: stored_element(zkp, commitment.r2(), "r2")?,
+                c: c.decode(zkp)?,
+                s,
+            },
+            commitment,
+            retire: None,
+            peer,
+        };
+
+        //creating the veriication result usinf the verfiy solution function
+        let failed = ZkpAuthError::Crypto(format!("Identity verification failed for auth_id: {}", auth_id));
+        let (pending, verification_result) = self.pool.run(move || {
+            let verified = if pending.verify() { Ok(()) } else { Err(pending.failure(failed)) };
+            Ok((pending, verified))
+        }).await?;
+
+        match verification_result {
+            Ok(()) => {
+                let (session_id, token) = self.finish_login(&pending)?;
+                tracing::info!(session = %telemetry::session(&session_id), "proof verified, session granted");
+
+                Ok(Response::new(SolutionResponse{session_id, token}))
+            }
+            Err(e) => {
+                tracing::warn!("proof verification failed");
+                Err(self.proof_failed(&user_identifier, peer, e).into())
+            }
         }
-    
 }
+
+    //one round trip login with a non-interactive (Fiat-Shamir) proof
+    async fn login(&self, request:Request<LoginRequest>) -> std::result::Result<Response<LoginResponse>,Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        let request = request.into_inner();
+        telemetry::record_did(&request.user);
+
+        let keys = self.login_keys(&request, peer)?;
+        let (pending, verification_result) = self.pool.run(move || {
+            let pending = keys.decode(&request)?;
+            let verified = if pending.verify() {
+                Ok(())
+            } else {
+                Err(pending.failure(ZkpAuthError::Crypto(format!("Identity verification failed for DID: {}", pending.did))))
+            };
+            Ok((pending, verified))
+        }).await?;
+
+        match verification_result {
+            Ok(()) => {
+                let (session_id, token) = self.finish_login(&pending)?;
+                tracing::info!(session = %telemetry::session(&session_id), "proof verified, session granted");
+
+                Ok(Response::new(LoginResponse{session_id, token}))
+            }
+            Err(e) => {
+                tracing::warn!("proof verification failed");
+                Err(self.proof_failed(&pending.did, peer, e).into())
+            }
+        }
+    }
+
+    //many non-interactive logins, the proofs of each group are checked as one batch
+    //a login that fails only fails its own result, the others still get a session
+    async fn batch_login(&self, request:Request<BatchLoginRequest>) -> std::result::Result<Response<BatchLoginResponse>,Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        let logins = request.into_inner().logins;
+        tracing::debug!(logins = logins.len(), "batch login");
+        if logins.len() > MAX_BATCH {
+            return Err(ZkpAuthError::Encoding(format!("A batch holds at most {} logins, got {}", MAX_BATCH, logins.len())).into());
+        }
+
+        //every check that needs the user store, the logins are grouped by the group they are in
+        let mut outcomes: Vec<Option<Result<(String, String)>>> = (0..logins.len()).map(|_| None).collect();
+        let mut batches: HashMap<String, Vec<(usize, LoginKeys<G>, LoginRequest)>> = HashMap::new();
+        for (i, login) in logins.into_iter().enumerate() {
+            match self.login_keys(&login, peer) {
+                Ok(keys) => batches.entry(keys.group.clone()).or_default().push((i, keys, login)),
+                Err(e) => outcomes[i] = Some(Err(e)),
+            }
+        }
+
+        for (group, batch) in batches {
+            let zkp = self.zkp_for(&group)?;
+            let dids: HashMap<usize, String> = batch.iter().map(|(i, keys, _)| (*i, keys.did.clone())).collect();
+            //decoding and checking the proofs of a group is one job
+            let checked = self.pool.run(move || {
+                let decoded: Vec<_> = batch.into_iter().map(|(i, keys, login)| (i, keys.decode(&login))).collect();
+                let entries: Vec<_> = decoded.iter().filter_map(|(_, pending)| Some(pending.as_ref().ok()?.entry.clone())).collect();
+                let bad = zkp.verify_batch(&entries).err().unwrap_or_default();
+                tracing::info!(group = %group, proofs = entries.len(), failed = bad.len(), "batch checked");
+
+                //bad holds indices into entries, which skipped the logins that did not decode
+                //a login whose proof failed is marked so its failure is counted
+                let mut j = 0;
+                Ok(decoded.into_iter().map(|(i, pending)| match pending {
+                    Ok(pending) => {
+                        j += 1;
+                        if bad.contains(&(j - 1)) {
+                            (i, Err(pending.failure(ZkpAuthError::Crypto(format!("Identity verification failed for DID: {}", pending.did)))), true)
+                        } else {
+                            (i, Ok(pending), false)
+                        }
+                    }
+                    Err(e) => (i, Err(e), false),
+                }).collect::<Vec<_>>())
+            }).await;
+
+            match checked {
+                Ok(checked) => for (i, pending, failed) in checked {
+                    let pending = pending.map_err(|e| if failed { self.proof_failed(&dids[&i], peer, e) } else { e });
+                    outcomes[i] = Some(pending.and_then(|pending| self.finish_login(&pending)));
+                },
+                //a busy pool fails this group's logins, the groups already checked keep their sessions
+                Err(e) => for i in dids.into_keys() {
+                    outcomes[i] = Some(Err(e.clone()));
+                },
+            }
+        }
+
+        let results = outcomes.into_iter().map(|outcome| match outcome {
+            Some(Ok((session_id, token))) => LoginResult { session_id, code: tonic::Code::Ok as i32, error: String::new(), token },
+            Some(Err(e)) => LoginResult { session_id: String::new(), code: e.code() as i32, error: e.reason().to_string(), token: String::new() },
+            None => unreachable!("every login has an outcome"),
+        }).collect();
+        Ok(Response::new(BatchLoginResponse { results }))
+    }
+
+    //for the services behind this one: the session is live and belongs to the DID in the answer
+    //validating is not activity, it does not move the idle deadline
+    async fn validate_session(&self, request:Request<ValidateSessionRequest>) -> std::result::Result<Response<ValidateSessionResponse>,Status> {
+        let session = self.live_session(&request.into_inner().session_id)?;
+        telemetry::record_did(&session.did);
+        Ok(Response::new(ValidateSessionResponse { session: Some(self.session_info(&session)) }))
+    }
+
+    async fn refresh_session(&self, request:Request<RefreshSessionRequest>) -> std::result::Result<Response<RefreshSessionResponse>,Status> {
+        let session_id = request.into_inner().session_id;
+        telemetry::record_did(&self.live_session(&session_id)?.did);
+        let session = self.sessions.refresh(&session_id, Utc::now())?;
+        Ok(Response::new(RefreshSessionResponse { session: Some(self.session_info(&session)) }))
+    }
+
+    //a live session can end itself, or every session of its DID
+    async fn logout(&self, request:Request<LogoutRequest>) -> std::result::Result<Response<LogoutResponse>,Status> {
+        let request = request.into_inner();
+        let session = self.live_session(&request.session_id)?;
+        telemetry::record_did(&session.did);
+        let revoked = if request.everywhere {
+            self.sessions.revoke_all(&session.did)?
+        } else {
+            self.sessions.revoke(&session.id)?;
+            1
+        };
+        tracing::info!(session = %telemetry::session(&session.id), everywhere = request.everywhere, revoked, "logged out");
+        Ok(Response::new(LogoutResponse { revoked: revoked as u32 }))
+    }
+
+    //only a live session of the DID may see the DID's sessions
+    async fn list_sessions(&self, request:Request<ListSessionsRequest>) -> std::result::Result<Response<ListSessionsResponse>,Status> {
+        let session = self.live_session(&request.into_inner().session_id)?;
+        telemetry::record_did(&session.did);
+        let sessions = self.sessions.list(&session.did, Utc::now())?.iter().map(|session| self.session_info(session)).collect();
+        Ok(Response::new(ListSessionsResponse { sessions }))
+    }
+
+    //the keys that tokens are checked with, anyone may fetch them
+    async fn get_jwks(&self, _request:Request<GetJwksRequest>) -> std::result::Result<Response<GetJwksResponse>,Status> {
+        Ok(Response::new(GetJwksResponse::from(&self.tokens.jwks())))
+    }
+
+    //a fresh token for a live session, it does not count as activity of the session
+    async fn issue_token(&self, request:Request<IssueTokenRequest>) -> std::result::Result<Response<IssueTokenResponse>,Status> {
+        let session = self.live_session(&request.into_inner().session_id)?;
+        telemetry::record_did(&session.did);
+        let (token, claims) = self.token_for(&session)?;
+        Ok(Response::new(IssueTokenResponse { token, expires_at: claims.exp }))
+    }
+
+    //for the operator, the numbers say nothing about any one DID
+    async fn get_metrics(&self, _request:Request<GetMetricsRequest>) -> std::result::Result<Response<GetMetricsResponse>,Status> {
+        let metrics = self.limiter.metrics(Utc::now());
+        let puzzles = self.puzzles.metrics();
+        let pending = self.challenges.len()?;
+        Ok(Response::new(GetMetricsResponse {
+            limited_peers: metrics.limited_peers,
+            limited_dids: metrics.limited_dids,
+            lockouts: metrics.lockouts,
+            refused_locked: metrics.refused_locked,
+            refused_pending: metrics.refused_pending,
+            pending_challenges: pending as u64,
+            tracked_peers: metrics.peers as u64,
+            tracked_dids: metrics.dids as u64,
+            locked_dids: metrics.locked as u64,
+            puzzles_issued: puzzles.issued,
+            puzzles_solved: puzzles.solved,
+            puzzles_refused: puzzles.refused,
+            puzzle_difficulty: self.puzzles.policy().difficulty(pending),
+        }))
+    }
 }
 
 #[tokio::main] //this makes it an synchronous function
 async fn main(){
     let addy = "127.0.0.1:50051".to_string();
-    println!("🔐 SSI Authentication Server - Self-Sovereign Identity with Zero-Knowledge Proofs");
-    println!("📋 This server verifies Decentralized Identifiers (DIDs) using ZKP");
-    println!("The server is running here {}", addy);
+    let args: Vec<String> = std::env::args().collect();
+    //RUST_LOG overrides the level, e.g. RUST_LOG=debug or RUST_LOG=info,audit=warn
+    telemetry::init("info", args.iter().any(|arg| arg == "--log-json"));
+    tracing::info!(address = %addy, "SSI authentication server, verifying DIDs with zero-knowledge proofs");
+
+    let settings = match Settings::from_args(&args) {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return;
+        }
+    };
+    tracing::info!(groups = %settings.group_ids.join(","), workers = settings.pool.workers(), challenge_ttl = ?settings.challenge_ttl, "verifier settings");
+    tracing::info!(idle = ?settings.session_policy.idle_timeout, lifetime = ?settings.session_policy.absolute_timeout,
+        max_per_did = settings.session_policy.max_per_did, "session settings");
+    let limits = &settings.limit_policy;
+    tracing::info!(peer_rate = limits.per_peer.per_minute, peer_burst = limits.per_peer.burst, did_rate = limits.per_did.per_minute,
+        did_burst = limits.per_did.burst, did_ceiling_rate = limits.did_ceiling.per_minute, did_ceiling_burst = limits.did_ceiling.burst,
+        lockout_after = limits.lockout_after, lockout = ?limits.lockout, max_lockout = ?limits.max_lockout,
+        max_pending_per_did = limits.max_pending_per_did, pending_did_ceiling = limits.pending_did_ceiling, max_pending = limits.max_pending, "limits");
+    let puzzles = &settings.puzzle_policy;
+    tracing::info!(start_at = puzzles.start_at, full_at = puzzles.full_at, min_bits = puzzles.min_difficulty,
+        max_bits = puzzles.max_difficulty, "puzzles");
+    let stores = match settings.store.open(settings.session_policy) {
+        Ok(stores) => stores,
+        Err(e) => {
+            tracing::error!(store = %settings.store, error = %e, "could not open the store");
+            return;
+        }
+    };
+    tracing::info!(store = %settings.store, "keeping state");
+
+    //ristretto255 runs on its own since it is a different kind of group
+    if settings.group_ids.iter().any(|id| id == RISTRETTO255_GROUP_ID) {
+        if settings.group_ids.len() > 1 {
+            tracing::error!("ristretto255 cannot be combined with MODP groups");
+            return;
+        }
+        let groups = HashMap::from([(RISTRETTO255_GROUP_ID.to_string(), context::ristretto255())]);
+        serve(addy, settings.auth_impl(groups, stores)).await;
+        return;
+    }
+
+    //the groups and their fixed-base tables are built once here, every request then borrows them
+    let mut groups = HashMap::new();
+    tracing::info!("precomputing fixed-base tables");
+    for id in &settings.group_ids {
+        match context::modp(id) {
+            Ok(zkp) => { groups.insert(id.clone(), zkp); },
+            Err(ZkpAuthError::NotFound(_)) => {
+                tracing::error!(group = %id, "unsupported group");
+                return;
+            }
+            Err(e) => {
+                tracing::error!(group = %id, error = %e, "could not load group");
+                return;
+            }
+        }
+    }
+    serve(addy, settings.auth_impl(groups, stores)).await;
+}
+
+//what can be set on the command line, everything has a default
+//  --group <id,id,...>        the allowed groups, by default every MODP parameter set but the legacy
+//                             1024-bit one, which is only allowed when it is named
+//  --workers <n>              proofs checked at the same time, by default one per core
+//  --challenge-ttl <seconds>  how long a client has to answer a challenge
+//  --session-idle <seconds>   a session not used for this long ends
+//  --session-lifetime <seconds> no session lives longer than this
+//  --max-sessions <n>         sessions a DID may have at the same time
+//  --token-ttl <seconds>      how long a token is good for, never longer than its session
+//  --key-rotation <seconds>   how long a token signing key is used before the next one
+//  --store <store>            memory (the default), json:<path> or sqlite:<path>
+//  --peer-rate <n>            requests a minute from one address
+//  --peer-burst <n>           requests one address may send at once
+//  --did-rate <n>             challenges and logins a minute for one DID from one address
+//  --did-burst <n>            challenges and logins for one DID from one address at once
+//  --did-ceiling-rate <n>     challenges and logins a minute for one DID from all addresses
+//  --did-ceiling-burst <n>    challenges and logins for one DID from all addresses at once
+//  --lockout-after <n>        failed proofs in a row from one address before it is locked out of the DID
+//  --lockout <seconds>        the first lockout, doubled with every further failure
+//  --max-lockout <seconds>    the longest lockout
+//  --max-pending-per-did <n>  open challenges one DID may have from one address
+//  --pending-did-ceiling <n>  open challenges one DID may have from all addresses
+//  --max-pending <n>          open challenges in total
+//  --puzzle-start <n>         open challenges from which on a challenge needs a solved puzzle
+//  --puzzle-full <n>          open challenges at which the puzzle is hardest
+//  --puzzle-min-bits <n>      the easiest puzzle, in leading zero bits
+//  --puzzle-max-bits <n>      the hardest puzzle, at most 32
+//  --log-json                 one JSON object per log line, like ZKP_AUTH_LOG_FORMAT=json
+struct Settings {
+    group_ids: Vec<String>,
+    pool: VerifierPool,
+    challenge_ttl: Duration,
+    session_policy: SessionPolicy,
+    token_policy: TokenPolicy,
+    store: StoreConfig,
+    limit_policy: LimitPolicy,
+    puzzle_policy: PuzzlePolicy,
+}
+
+impl Settings {
+    fn from_args(args: &[String]) -> std::result::Result<Self, String> {
+        let group_ids = match flag(args, "--group") {
+            Some(list) => list.split(',').map(|id| id.trim().to_string()).collect(),
+            None => PARAMETER_SETS.iter().filter(|set| !LEGACY_GROUP_IDS.contains(&set.id)).map(|set| set.id.to_string()).collect(),
+        };
+        let pool = match positive_flag(args, "--workers")? {
+            Some(workers) => VerifierPool::new(workers as usize, workers as usize * QUEUE_PER_WORKER),
+            None => VerifierPool::default(),
+        };
+        let seconds = |name, default| positive_flag(args, name).map(|value| value.map_or(default, Duration::from_secs));
+        let defaults = SessionPolicy::default();
+        let token_defaults = TokenPolicy::default();
+        let limits = LimitPolicy::default();
+        let puzzles = PuzzlePolicy::default();
+        let count = |name, default| positive_flag(args, name).map(|value| value.map_or(default, |n| n as usize));
+        let rate = |rate_name, burst_name, default: Rate| -> std::result::Result<Rate, String> {
+            Ok(Rate { per_minute: count(rate_name, default.per_minute as usize)? as u32, burst: count(burst_name, default.burst as usize)? as u32 })
+        };
+        Ok(Self {
+            group_ids,
+            pool,
+            challenge_ttl: seconds("--challenge-ttl", CHALLENGE_TTL)?,
+            session_policy: SessionPolicy {
+                idle_timeout: seconds("--session-idle", defaults.idle_timeout)?,
+                absolute_timeout: seconds("--session-lifetime", defaults.absolute_timeout)?,
+                max_per_did: positive_flag(args, "--max-sessions")?.map_or(defaults.max_per_did, |n| n as usize),
+            },
+            token_policy: TokenPolicy {
+                ttl: seconds("--token-ttl", token_defaults.ttl)?,
+                rotate_every: seconds("--key-rotation", token_defaults.rotate_every)?,
+                ..token_defaults
+            },
+            store: match flag(args, "--store") {
+                Some(store) => store.parse().map_err(|e: ZkpAuthError| e.reason().to_string())?,
+                None => StoreConfig::default(),
+            },
+            limit_policy: LimitPolicy {
+                per_peer: rate("--peer-rate", "--peer-burst", limits.per_peer)?,
+                per_did: rate("--did-rate", "--did-burst", limits.per_did)?,
+                did_ceiling: rate("--did-ceiling-rate", "--did-ceiling-burst", limits.did_ceiling)?,
+                lockout_after: count("--lockout-after", limits.lockout_after as usize)? as u32,
+                lockout: seconds("--lockout", limits.lockout)?,
+                max_lockout: seconds("--max-lockout", limits.max_lockout)?,
+                max_pending_per_did: count("--max-pending-per-did", limits.max_pending_per_did)?,
+                pending_did_ceiling: count("--pending-did-ceiling", limits.pending_did_ceiling)?,
+                max_pending: count("--max-pending", limits.max_pending)?,
+            },
+            puzzle_policy: PuzzlePolicy {
+                start_at: count("--puzzle-start", puzzles.start_at)?,
+                full_at: count("--puzzle-full", puzzles.full_at)?,
+                min_difficulty: count("--puzzle-min-bits", puzzles.min_difficulty as usize)? as u32,
+                max_difficulty: count("--puzzle-max-bits", puzzles.max_difficulty as usize)? as u32,
+                ..puzzles
+            },
+        })
+    }
+
+    //the stores were opened with session_policy
+    fn auth_impl<G: Group>(&self, groups: HashMap<String, &'static ZKP<G>>, stores: Stores) -> AuthImpl<G> {
+        AuthImpl::new(groups)
+            .with_pool(self.pool.clone())
+            .with_challenge_ttl(self.challenge_ttl)
+            .with_stores(stores)
+            .with_token_policy(self.token_policy.clone())
+            .with_limit_policy(self.limit_policy)
+            .with_puzzle_policy(self.puzzle_policy)
+    }
+}
 
-    let auth_impl = AuthImpl::default();
+//the value after a command line flag
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1))
+}
+
+fn positive_flag(args: &[String], name: &str) -> std::result::Result<Option<u64>, String> {
+    match flag(args, name) {
+        Some(value) => match value.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(Some(n)),
+            _ => Err(format!("{} takes a positive number, got {}", name, value)),
+        },
+        None => Ok(None),
+    }
+}
 
-    Server::builder().add_service(AuthServer::new(auth_impl))
+async fn serve<G: Group>(addy: String, auth_impl: AuthImpl<G>) {
+    if !check_parameters(&auth_impl.groups) {
+        return;
+    }
+    let auth_impl = Arc::new(auth_impl);
+
+    //expired challenges and sessions are dropped in the background, using one is refused even before that
+    //the token signing key is rotated there too
+    let sweeper = auth_impl.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match sweeper.sweep(Utc::now()) {
+                Ok(0) => {}
+                Ok(evicted) => tracing::debug!(evicted, "evicted expired challenges"),
+                Err(e) => tracing::error!(error = %e, "could not evict expired challenges"),
+            }
+            match sweeper.sessions.sweep(Utc::now()) {
+                Ok(0) => {}
+                Ok(ended) => tracing::debug!(ended, "dropped ended sessions"),
+                Err(e) => tracing::error!(error = %e, "could not drop ended sessions"),
+            }
+            match sweeper.random(|rng| sweeper.tokens.maintain(rng, Utc::now())) {
+                Ok(Some(kid)) => tracing::info!(kid = %kid, signs_in = ?sweeper.tokens.policy().overlap, "published a token signing key"),
+                Ok(None) => {}
+                Err(e) => tracing::error!(error = %e, "could not rotate the token signing key"),
+            }
+            sweeper.limiter.sweep(Utc::now());
+            sweeper.puzzles.sweep(Utc::now());
+        }
+    });
+
+    //every request runs in a span, then takes a token of its address before it reaches a handler
+    Server::builder().layer(RpcTraceLayer).layer(PeerLimitLayer::new(auth_impl.limiter.clone())).add_service(AuthServer::from_arc(auth_impl))
     .serve(addy.parse().expect("could not convert address"))
     .await
     .unwrap();
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::zkp_auth::history::COMMITMENT_HISTORY;
+    use ::zkp_auth::{parameter_set, RFC5114_1024_GROUP_ID};
+    use num_bigint::BigUint;
+    use ::zkp_auth::ssi::credential::DID;
+    use ::zkp_auth::ssi::issuer::Issuer;
+    use ::zkp_auth::zkp_proto::ProofOfKnowledge;
+
+    fn auth_impl() -> AuthImpl<::zkp_auth::ModpGroup> {
+        let set = parameter_set(RFC5114_1024_GROUP_ID).unwrap();
+        AuthImpl::new(HashMap::from([(set.id.to_string(), context::modp(set.id).unwrap())]))
+    }
+
+    //the group the tests run in, named by every request
+    fn group_id() -> String {
+        RFC5114_1024_GROUP_ID.to_string()
+    }
+
+    //the DID the keys of x give
+    fn did_for(zkp: &ZKP<::zkp_auth::ModpGroup>, x: &BigUint) -> String {
+        let (y1, y2) = zkp.exponentiate_pair(x);
+        DID::from_public_keys(&zkp.group.element_to_bytes(&y1), &zkp.group.element_to_bytes(&y2)).to_string()
+    }
+
+    //the request with a credential the issuer gave its keys, made out to the DID it names
+    fn enroll(request: RegisterRequest) -> Request<RegisterRequest> {
+        let (mut credential, _) = Issuer::issue_credential("test", &request.y1, &request.y2).unwrap();
+        credential.credential_subject.id = request.user.clone();
+        Request::new(RegisterRequest { credential: serde_json::to_string(&credential).unwrap(), ..request })
+    }
+
+    fn register_request(zkp: &ZKP<::zkp_auth::ModpGroup>, x: &BigUint) -> Request<RegisterRequest> {
+        enroll(registration::register_request(zkp, &did_for(zkp, x), x, group_id()))
+    }
+
+    #[tokio::test]
+    async fn test_register_rejects_degenerate_keys() {
+        let auth = auth_impl();
+        let zkp = &auth.groups[RFC5114_1024_GROUP_ID];
+        let (y1, _) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+        let y1 = zkp.group.element_to_bytes(&y1);
+        let p_minus_one = (&zkp.group.p - BigUint::from(1u32)).to_bytes_be();
+        let len = zkp.group.element_len();
+        let mut one = vec![0u8; len];
+        one[len - 1] = 1;
+
+        let cases = [
+            (vec![0u8; len], "y2 is zero".to_string()),
+            (one, "y2 is the identity element".to_string()),
+            (zkp.group.p.to_bytes_be(), "y2 is out of range".to_string()),
+            //p-1 has order 2 so it is not in the subgroup
+            (p_minus_one, "y2 is not in the order q subgroup".to_string()),
+            //encodings are fixed width, a short one is rejected before its value is looked at
+            (vec![1], format!("y2 is 1 bytes, expected {}", len)),
+        ];
+        for (y2, reason) in cases {
+            //the proof is only looked at once the keys decoded
+            let user = DID::from_public_keys(&y1, &y2).to_string();
+            let request = RegisterRequest { user, y1: y1.clone(), y2, proof: Some(ProofOfKnowledge::default()), group: group_id(), ..Default::default() };
+            let status = auth.register(enroll(request)).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert_eq!(status.message(), reason);
+        }
+        assert!(auth.users.is_empty().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_register_needs_a_proof_for_its_did() {
+        let auth = auth_impl();
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        let did = did_for(zkp, &x);
+
+        //a request names its group, none is assumed
+        let status = auth.register(enroll(registration::register_request(zkp, &did, &x, String::new()))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(status.message(), "The request names no group, e.g. rfc5114-2048-256.");
+
+        //a DID the keys do not give, a proof made for another DID and no proof at all
+        let status = auth.register(enroll(registration::register_request(zkp, "did:zkp:test", &x, group_id()))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(status.message(), format!("DID: did:zkp:test is not derived from these keys, they give {}", did));
+        let mut request = registration::register_request(zkp, &did, &x, group_id());
+        request.proof = registration::register_request(zkp, "did:zkp:test", &x, group_id()).proof;
+        assert_eq!(auth.register(enroll(request.clone())).await.unwrap_err().message(), "Registration proof does not verify");
+        request.proof = None;
+        assert_eq!(auth.register(enroll(request.clone())).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        //and without the credential, or with one for other keys
+        request.proof = registration::register_request(zkp, &did, &x, group_id()).proof;
+        assert_eq!(auth.register(Request::new(request.clone())).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        let other = enroll(registration::register_request(zkp, &did, &zkp.group.random_scalar(), group_id())).into_inner().credential;
+        let status = auth.register(Request::new(RegisterRequest { credential: other, ..request })).await.unwrap_err();
+        assert_eq!(status.message(), "the credential names other keys than y1 and y2");
+        assert!(auth.users.is_empty().unwrap());
+
+        //the registration proof sent again as a login is not made for a login context
+        let request = registration::register_request(zkp, &did, &x, group_id());
+        auth.register(enroll(request.clone())).await.unwrap();
+        let proof = Proof::from(request.proof.as_ref().unwrap());
+        let replay = proof.to_login_request(did.clone(), registration::registration_context(&did), group_id());
+        assert_eq!(auth.login(Request::new(replay)).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        //a DID enrolls once
+        let status = auth.register(register_request(zkp, &x)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+        assert_eq!(auth.users.get(&did).unwrap().unwrap().history.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_registration_proof_refused_as_login_after_history_evicted_it() {
+        let limits = LimitPolicy { per_did: Rate { burst: 128, per_minute: 128 }, ..LimitPolicy::default() };
+        let auth = auth_impl().with_limit_policy(limits);
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        let did = did_for(zkp, &x);
+        let request = registration::register_request(zkp, &did, &x, group_id());
+        auth.register(enroll(request.clone())).await.unwrap();
+
+        //once the logins pushed it out of the history the login domain still keeps it out
+        logins(&auth, &x, COMMITMENT_HISTORY + 1).await;
+        let proof = Proof::from(request.proof.as_ref().unwrap());
+        assert!(auth.users.get(&did).unwrap().unwrap().history.find(&proof.commitment).is_none());
+        let replay = proof.to_login_request(did.clone(), registration::registration_context(&did), group_id());
+        let status = auth.login(Request::new(replay)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(status.message(), format!("The context is not a login context for DID: {}", did));
+        assert!(!auth.users.get(&did).unwrap().unwrap().compromised);
+    }
+
+    #[tokio::test]
+    async fn test_registered_did_needs_a_key_update_proof() {
+        let auth = auth_impl();
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, intruder, new) = (zkp.group.random_scalar(), zkp.group.random_scalar(), zkp.group.random_scalar());
+        let did = did_for(zkp, &x);
+        auth.register(register_request(zkp, &x)).await.unwrap();
+        let session_id = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"before")).await.unwrap().into_inner().session_id;
+
+        //someone else's keys, with a valid proof of their own, do not take the DID over
+        let takeover = registration::register_request(zkp, &did, &intruder, group_id());
+        let status = auth.register(enroll(takeover)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+        assert_eq!(status.message(), format!("DID: {} is already enrolled, log in with it or send a key update", did));
+        let forged = registration::key_update_request(zkp, &did, &intruder, &new, group_id());
+        assert_eq!(auth.register(enroll(forged)).await.unwrap_err().message(), "Key update proof does not verify");
+
+        //the holder of x can move the DID to new keys, the old ones and their sessions are done
+        let enrolled_at = auth.users.get(&did).unwrap().unwrap().enrollment.unwrap().enrolled_at;
+        let update = auth.register(enroll(registration::key_update_request(zkp, &did, &x, &new, group_id()))).await.unwrap().into_inner();
+        let registration = update.registration.unwrap();
+        assert_eq!((registration.enrolled_at, registration.keys_updated_at > 0), (enrolled_at.timestamp(), true));
+        assert_eq!(auth.audit_log.lock().unwrap().back(), Some(&AuditEvent::KeysUpdated { did: did.clone() }));
+        assert_eq!(auth.validate_session(validate(&session_id)).await.unwrap_err().code(), tonic::Code::NotFound);
+        assert!(auth.login(login_request_as(zkp, did.clone(), &new, &zkp.group.random_scalar(), b"after")).await.is_ok());
+        assert_eq!(auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"after")).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+        //a DID nobody registered has no keys to update
+        let other = zkp.group.random_scalar();
+        let update = registration::key_update_request(zkp, &did_for(zkp, &other), &other, &new, group_id());
+        assert_eq!(auth.register(enroll(update)).await.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_get_registration() {
+        let auth = auth_impl();
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, other) = (zkp.group.random_scalar(), zkp.group.random_scalar());
+        let did = did_for(zkp, &x);
+        let get = |did: &str, session_id: &str| Request::new(GetRegistrationRequest { did: did.to_string(), session_id: session_id.to_string() });
+        assert_eq!(auth.get_registration(get(&did, "")).await.unwrap_err().code(), tonic::Code::NotFound);
+
+        let request = register_request(zkp, &x);
+        let credential = request.get_ref().credential.clone();
+        let (y1, y2) = (request.get_ref().y1.clone(), request.get_ref().y2.clone());
+        let enrolled = auth.register(request).await.unwrap().into_inner().registration.unwrap();
+        assert_eq!((enrolled.credential.as_str(), enrolled.keys_updated_at, enrolled.compromised), (credential.as_str(), 0, false));
+
+        //anyone may see the keys, the credential needs a session of the DID
+        let registration = auth.get_registration(get(&did, "")).await.unwrap().into_inner().registration.unwrap();
+        assert_eq!((registration.y1, registration.y2, registration.enrolled_at), (y1, y2, enrolled.enrolled_at));
+        assert!(registration.credential.is_empty());
+        let session_id = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"own")).await.unwrap().into_inner().session_id;
+        let registration = auth.get_registration(get(&did, &session_id)).await.unwrap().into_inner().registration.unwrap();
+        assert_eq!(registration.credential, credential);
+
+        auth.register(register_request(zkp, &other)).await.unwrap();
+        let session_id = auth.login(login_request(zkp, &other, &zkp.group.random_scalar(), b"other")).await.unwrap().into_inner().session_id;
+        assert_eq!(auth.get_registration(get(&did, &session_id)).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_challenge_rejects_degenerate_commitments() {
+        let auth = auth_impl();
+        let zkp = &auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        auth.register(register_request(zkp, &x)).await.unwrap();
+
+        let (r1, _) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+        let mut one = vec![0u8; zkp.group.element_len()];
+        *one.last_mut().unwrap() = 1;
+        let request = Request::new(ChallengeRequest {
+            user: did_for(zkp, &x),
+            r1: zkp.group.element_to_bytes(&r1),
+            r2: one,
+            group: group_id(),
+            ..Default::default()
+        });
+        let status = auth.create_challenge(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(status.message(), "r2 is the identity element");
+    }
+
+    //a login context made now, the label picks its nonce so a test can send the same one twice
+    fn login_context(did: &str, label: &[u8]) -> Vec<u8> {
+        use sha2::Digest;
+        let nonce = sha2::Sha256::digest(label)[..login::NONCE_LEN].try_into().unwrap();
+        login::login_context(did, Utc::now().timestamp(), &nonce)
+    }
+
+    fn login_request(zkp: &ZKP<::zkp_auth::ModpGroup>, x: &BigUint, k: &BigUint, label: &[u8]) -> Request<LoginRequest> {
+        login_request_as(zkp, did_for(zkp, x), x, k, label)
+    }
+
+    //a proof by x for a login as did, which need not be the DID of x
+    fn login_request_as(zkp: &ZKP<::zkp_auth::ModpGroup>, did: String, x: &BigUint, k: &BigUint, label: &[u8]) -> Request<LoginRequest> {
+        let (y1, y2) = zkp.exponentiate_pair(x);
+        let (r1, r2) = zkp.exponentiate_pair(k);
+        let context = login_context(&did, label);
+        let c = zkp.challenge_hash(&y1, &y2, &r1, &r2, &context);
+        let proof = Proof::new(zkp, &r1, &r2, &zkp.solve(k, &c, x));
+        Request::new(proof.to_login_request(did, context, group_id()))
+    }
+
+    #[tokio::test]
+    async fn test_nonce_reuse_marks_did_compromised() {
+        let auth = auth_impl();
+        let zkp = &auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        auth.register(register_request(zkp, &x)).await.unwrap();
+
+        let k = zkp.group.random_scalar();
+        auth.login(login_request(zkp, &x, &k, b"login 1")).await.unwrap();
+
+        //the same proof again is a replay, refused but the secret is safe
+        let status = auth.login(login_request(zkp, &x, &k, b"login 1")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(auth.audit_log.lock().unwrap().back(), Some(&AuditEvent::ProofReplayed { did: did_for(zkp, &x) }));
+        assert!(!auth.users.get(&did_for(zkp, &x)).unwrap().unwrap().compromised);
+
+        //the same k for another context gives a second challenge, x can now be computed
+        let status = auth.login(login_request(zkp, &x, &k, b"login 2")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(auth.users.get(&did_for(zkp, &x)).unwrap().unwrap().compromised);
+        assert!(auth.audit_log.lock().unwrap().contains(&AuditEvent::KeyCompromised { did: did_for(zkp, &x), witness_recovered: true }));
+
+        //even a correct proof with a fresh nonce is refused from now on, and so is registering again
+        let status = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"login 3")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(auth.audit_log.lock().unwrap().back(), Some(&AuditEvent::CompromisedLogin { did: did_for(zkp, &x) }));
+        assert!(auth.register(register_request(zkp, &x)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keys_of_the_old_beta_are_named_as_such() {
+        let auth = auth_impl();
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        let did = did_for(zkp, &x);
+        auth.register(register_request(zkp, &x)).await.unwrap();
+        //an enrollment from before beta was hashed into the subgroup, y2 = (alpha^e)^x
+        let exp = BigUint::from_bytes_be(&hex::decode(params::LEGACY_BETA_EXPONENT).unwrap());
+        auth.users.update(&did, &mut |user_info| {
+            user_info.y2 = zkp.group.element_to_bytes(&zkp.group.exp(&zkp.group.exp(zkp.group.alpha(), &exp), &x));
+            Ok(())
+        }).unwrap();
+
+        let status = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"old")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert!(status.message().contains("create a new wallet and enroll it"), "{}", status.message());
+        //any other key that does not fit is the usual failure
+        auth.users.update(&did, &mut |user_info| {
+            user_info.y2 = zkp.group.element_to_bytes(&zkp.exponentiate_pair(&zkp.group.random_scalar()).1);
+            Ok(())
+        }).unwrap();
+        let status = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"other")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_login_contexts_are_checked_and_single_use() {
+        let limits = LimitPolicy { per_did: Rate { burst: 128, per_minute: 128 }, ..LimitPolicy::default() };
+        let auth = auth_impl().with_limit_policy(limits);
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        let did = did_for(zkp, &x);
+        auth.register(register_request(zkp, &x)).await.unwrap();
+
+        //a captured login is refused even after its transcript left the commitment history
+        let captured = login_request(zkp, &x, &zkp.group.random_scalar(), b"captured");
+        auth.login(Request::new(captured.get_ref().clone())).await.unwrap();
+        logins(&auth, &x, COMMITMENT_HISTORY + 1).await;
+        assert!(auth.users.get(&did).unwrap().unwrap().history.find(&Proof::from(captured.get_ref()).commitment).is_none());
+        let status = auth.login(captured).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(status.message(), format!("Login proof for DID: {} was already used", did));
+
+        //a context the server cannot read, or one made too long ago, is refused before the proof is checked
+        let sign = |context: Vec<u8>| {
+            let (r1, r2, s) = zkp.prove_non_interactive(&x, &context);
+            Request::new(Proof::new(zkp, &r1, &r2, &s).to_login_request(did.clone(), context, group_id()))
+        };
+        let free_form = sign(format!("login:{}:{}", did, Utc::now().timestamp()).into_bytes());
+        assert_eq!(auth.login(free_form).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        let stale = sign(login::new_login_context(&did, Utc::now() - chrono::Duration::from_std(LOGIN_SKEW * 2).unwrap()));
+        assert_eq!(auth.login(stale).await.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+        assert!(auth.login(sign(login::new_login_context(&did, Utc::now()))).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_challenge_refused_for_used_commitment() {
+        let auth = auth_impl();
+        let zkp = &auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        auth.register(register_request(zkp, &x)).await.unwrap();
+
+        let k = zkp.group.random_scalar();
+        let (r1, r2) = zkp.exponentiate_pair(&k);
+        let challenge_request = || Request::new(Commitment::new(zkp, &r1, &r2).to_challenge_request(did_for(zkp, &x), group_id()));
+
+        let response = auth.create_challenge(challenge_request()).await.unwrap().into_inner();
+        let c = Challenge::from(&response).decode(zkp).unwrap();
+        let s = proof::Response::new(zkp, &zkp.solve(&k, &c, &x)).s().to_vec();
+        auth.verify_authentication(Request::new(SolutionRequest { auth_id: response.auth_id, s })).await.unwrap();
+
+        //a second challenge for the same commitment would let x be computed, so none is given
+        let status = auth.create_challenge(challenge_request()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(auth.audit_log.lock().unwrap().back(), Some(&AuditEvent::CommitmentReused { did: did_for(zkp, &x) }));
+        assert!(!auth.users.get(&did_for(zkp, &x)).unwrap().unwrap().compromised);
+    }
+
+    #[tokio::test]
+    async fn test_batch_login_reports_each_result() {
+        let auth = auth_impl();
+        let zkp = &auth.groups[RFC5114_1024_GROUP_ID];
+        let mut logins = Vec::new();
+        for _ in 0..6 {
+            let x = zkp.group.random_scalar();
+            auth.register(register_request(zkp, &x)).await.unwrap();
+            let did = did_for(zkp, &x);
+            logins.push(login_request(zkp, &x, &zkp.group.random_scalar(), did.as_bytes()).into_inner());
+        }
+        let refused = logins[2].user.clone();
+        //a proof for another context, a DID nobody registered and a bad encoding
+        logins[2].context = login_context(&refused, b"elsewhere");
+        logins[4] = login_request_as(zkp, "did:zkp:nobody".to_string(), &zkp.group.random_scalar(), &zkp.group.random_scalar(), b"nobody").into_inner();
+        logins[5].s = vec![1];
+
+        let results = auth.batch_login(Request::new(BatchLoginRequest { logins })).await.unwrap().into_inner().results;
+        let codes: Vec<_> = results.iter().map(|r| tonic::Code::from(r.code)).collect();
+        use tonic::Code::*;
+        assert_eq!(codes, [Ok, Ok, PermissionDenied, Ok, NotFound, InvalidArgument]);
+        assert!(results.iter().all(|r| r.session_id.is_empty() == (r.code != 0)));
+        assert_eq!(results[2].error, format!("Identity verification failed for DID: {}", refused));
+    }
+
+    #[tokio::test]
+    async fn test_batch_login_is_bounded() {
+        let auth = auth_impl();
+        let logins = vec![LoginRequest::default(); MAX_BATCH + 1];
+        let status = auth.batch_login(Request::new(BatchLoginRequest { logins })).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_busy_pool_pushes_back() {
+        let auth = auth_impl().with_pool(VerifierPool::new(1, 0));
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        auth.register(register_request(zkp, &x)).await.unwrap();
+
+        //a slow job holds the only worker
+        let (release, wait) = std::sync::mpsc::channel::<()>();
+        let (started, is_started) = std::sync::mpsc::channel::<()>();
+        let pool = auth.pool.clone();
+        let slow = tokio::spawn(async move { pool.run(move || { started.send(()).unwrap(); wait.recv().unwrap(); Ok(()) }).await });
+        tokio::task::spawn_blocking(move || is_started.recv().unwrap()).await.unwrap();
+
+        //the login is turned away instead of waiting behind it, and the user store is not locked meanwhile
+        let status = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"busy")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        auth.users.update(&did_for(zkp, &x), &mut |_| Ok(())).unwrap();
+
+        release.send(()).unwrap();
+        slow.await.unwrap().unwrap();
+        assert!(auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"busy")).await.is_ok());
+    }
+
+    fn metrics(auth: &AuthImpl<::zkp_auth::ModpGroup>) -> impl std::future::Future<Output = std::result::Result<Response<GetMetricsResponse>, Status>> + '_ {
+        auth.get_metrics(Request::new(GetMetricsRequest {}))
+    }
+
+    #[tokio::test]
+    async fn test_failed_proofs_lock_a_did_out() {
+        let limits = LimitPolicy { lockout_after: 2, lockout: Duration::from_secs(60), ..LimitPolicy::default() };
+        let auth = auth_impl().with_limit_policy(limits);
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, wrong, other) = (zkp.group.random_scalar(), zkp.group.random_scalar(), zkp.group.random_scalar());
+        let did = did_for(zkp, &x);
+        auth.register(register_request(zkp, &x)).await.unwrap();
+        auth.register(register_request(zkp, &other)).await.unwrap();
+
+        //a wrong answer to a challenge and a wrong non-interactive proof both count
+        let k = zkp.group.random_scalar();
+        let (r1, r2) = zkp.exponentiate_pair(&k);
+        let request = Commitment::new(zkp, &r1, &r2).to_challenge_request(did.clone(), group_id());
+        let response = auth.create_challenge(Request::new(request)).await.unwrap().into_inner();
+        assert_eq!(auth.verify_authentication(solution(zkp, &wrong, &k, &response)).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert_eq!(auth.login(login_request_as(zkp, did.clone(), &wrong, &zkp.group.random_scalar(), b"wrong")).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert!(matches!(auth.audit_log.lock().unwrap().back(), Some(AuditEvent::LockedOut { did: locked, .. }) if *locked == did));
+
+        //now even the right proof from the same address (none here) is refused, the other DID is not affected
+        //a prover at another address is not, see limit::test
+        let status = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"right")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(status.message().starts_with(&format!("DID: {} is locked out for this address until", did)));
+        assert!(auth.login(login_request(zkp, &other, &zkp.group.random_scalar(), b"other")).await.is_ok());
+        let metrics = metrics(&auth).await.unwrap().into_inner();
+        assert_eq!((metrics.lockouts, metrics.refused_locked, metrics.locked_dids), (1, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_logins_and_challenges_are_limited() {
+        let limits = LimitPolicy { per_did: Rate { burst: 3, per_minute: 1 }, max_pending_per_did: 2, max_pending: 3, ..LimitPolicy::default() };
+        let auth = auth_impl().with_limit_policy(limits);
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let xs: Vec<BigUint> = (0..3).map(|_| zkp.group.random_scalar()).collect();
+        for x in &xs {
+            auth.register(register_request(zkp, x)).await.unwrap();
+        }
+        let challenge = |x: &BigUint| {
+            let (r1, r2) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+            Request::new(Commitment::new(zkp, &r1, &r2).to_challenge_request(did_for(zkp, x), group_id()))
+        };
+
+        //two open challenges for a DID, a third one waits until one is answered
+        let first = auth.create_challenge(challenge(&xs[0])).await.unwrap().into_inner();
+        auth.create_challenge(challenge(&xs[0])).await.unwrap();
+        let status = auth.create_challenge(challenge(&xs[0])).await.unwrap_err();
+        assert_eq!((status.code(), status.message()), (tonic::Code::ResourceExhausted, format!("DID: {} has 2 open challenges from this address, answer one first", did_for(zkp, &xs[0])).as_str()));
+        //three open challenges in all
+        auth.create_challenge(challenge(&xs[1])).await.unwrap();
+        assert_eq!(auth.create_challenge(challenge(&xs[2])).await.unwrap_err().message(), "The server has too many open challenges, try again later");
+        auth.challenges.take(&first.auth_id, Utc::now(), CHALLENGE_TTL).unwrap();
+        auth.create_challenge(challenge(&xs[2])).await.unwrap();
+
+        //the first DID used its three tokens, logins count too
+        let status = auth.login(login_request(zkp, &xs[0], &zkp.group.random_scalar(), b"fourth")).await.unwrap_err();
+        assert_eq!(status.message(), format!("Too many logins for DID: {} from this address, slow down", did_for(zkp, &xs[0])));
+        assert!(auth.login(login_request(zkp, &xs[1], &zkp.group.random_scalar(), b"second")).await.is_ok());
+        let metrics = metrics(&auth).await.unwrap().into_inner();
+        assert_eq!((metrics.limited_dids, metrics.refused_pending, metrics.pending_challenges, metrics.tracked_dids), (1, 2, 3, 3));
+    }
+
+    //what a request from ip carries once it came over TCP, remote_addr reads it
+    async fn connect_info(ip: [u8; 4]) -> tonic::transport::server::TcpConnectInfo {
+        use tonic::transport::server::Connected;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind((ip, 0).into()).unwrap();
+        let _client = socket.connect(listener.local_addr().unwrap()).await.unwrap();
+        listener.accept().await.unwrap().0.connect_info()
+    }
+
+    #[tokio::test]
+    async fn test_one_address_flooding_a_did_does_not_stop_another() {
+        let limits = LimitPolicy { per_did: Rate { burst: 3, per_minute: 1 }, max_pending_per_did: 2, ..LimitPolicy::default() };
+        let auth = auth_impl().with_limit_policy(limits);
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        let did = did_for(zkp, &x);
+        auth.register(register_request(zkp, &x)).await.unwrap();
+        let (attacker, prover) = (connect_info([127, 0, 0, 2]).await, connect_info([127, 0, 0, 3]).await);
+        let challenge = |from: &tonic::transport::server::TcpConnectInfo| {
+            let (r1, r2) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+            let mut request = Request::new(Commitment::new(zkp, &r1, &r2).to_challenge_request(did.clone(), group_id()));
+            request.extensions_mut().insert(from.clone());
+            request
+        };
+
+        //the attacker holds as many challenges open as it may and empties its bucket for the DID
+        auth.create_challenge(challenge(&attacker)).await.unwrap();
+        auth.create_challenge(challenge(&attacker)).await.unwrap();
+        let status = auth.create_challenge(challenge(&attacker)).await.unwrap_err();
+        assert!(status.message().ends_with("open challenges from this address, answer one first"));
+        let status = auth.create_challenge(challenge(&attacker)).await.unwrap_err();
+        assert_eq!((status.code(), status.message()), (tonic::Code::ResourceExhausted, format!("Too many logins for DID: {} from this address, slow down", did).as_str()));
+
+        //the prover still gets a challenge and logs in with it
+        let response = auth.create_challenge(challenge(&prover)).await.unwrap().into_inner();
+        assert_eq!(auth.challenges.pending(&did, prover.remote_addr().map(|addr| addr.ip())).unwrap(), (1, 3));
+        assert!(auth.challenges.take(&response.auth_id, Utc::now(), CHALLENGE_TTL).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_challenges_need_a_puzzle_under_load() {
+        let policy = PuzzlePolicy { start_at: 1, full_at: 3, min_difficulty: 4, max_difficulty: 8, ..PuzzlePolicy::default() };
+        let auth = auth_impl().with_puzzle_policy(policy);
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        let did = did_for(zkp, &x);
+        auth.register(register_request(zkp, &x)).await.unwrap();
+        let challenge = || {
+            let (r1, r2) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+            Commitment::new(zkp, &r1, &r2).to_challenge_request(did.clone(), group_id())
+        };
+        let get_puzzle = || auth.get_puzzle(Request::new(GetPuzzleRequest { did: did.clone() }));
+
+        //no puzzle while nothing is open
+        assert_eq!(get_puzzle().await.unwrap().into_inner().puzzle, None);
+        auth.create_challenge(Request::new(challenge())).await.unwrap();
+
+        //one open challenge is load here, the next one needs the puzzle solved for its commitment
+        let status = auth.create_challenge(Request::new(challenge())).await.unwrap_err();
+        assert_eq!((status.code(), status.message()), (tonic::Code::ResourceExhausted, "The server is under load, solve a puzzle from GetPuzzle first"));
+        let puzzle = get_puzzle().await.unwrap().into_inner().puzzle.unwrap();
+        assert_eq!(puzzle.difficulty, 4);
+        let mut request = challenge();
+        ::zkp_auth::puzzle::solve_request(&mut request, puzzle);
+        auth.create_challenge(Request::new(request.clone())).await.unwrap();
+        //once only, and the difficulty rose with the second open challenge
+        assert_eq!(auth.create_challenge(Request::new(request)).await.unwrap_err().code(), tonic::Code::FailedPrecondition);
+        assert_eq!(get_puzzle().await.unwrap().into_inner().puzzle.unwrap().difficulty, 6);
+
+        let metrics = metrics(&auth).await.unwrap().into_inner();
+        assert_eq!((metrics.puzzles_issued, metrics.puzzles_solved, metrics.puzzles_refused, metrics.puzzle_difficulty), (2, 1, 2, 6));
+    }
+
+    #[tokio::test]
+    async fn test_peer_limit_in_front_of_the_service() {
+        use ::zkp_auth::zkp_proto::auth_client::AuthClient;
+        let limits = LimitPolicy { per_peer: Rate { burst: 2, per_minute: 1 }, ..LimitPolicy::default() };
+        let auth = Arc::new(auth_impl().with_limit_policy(limits));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        let server = Server::builder().layer(PeerLimitLayer::new(auth.limiter.clone())).add_service(AuthServer::from_arc(auth.clone()));
+        tokio::spawn(server.serve_with_incoming(incoming));
+
+        let mut client = AuthClient::connect(format!("http://{}", addr)).await.unwrap();
+        for _ in 0..2 {
+            client.get_jwks(GetJwksRequest {}).await.unwrap();
+        }
+        let status = client.get_jwks(GetJwksRequest {}).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.message(), "Too many requests from 127.0.0.1, slow down");
+        assert_eq!(metrics(&auth).await.unwrap().into_inner().limited_peers, 1);
+    }
+
+    //the log lines of a request through the server, as the JSON a log pipeline reads
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_logs_hash_identifiers() {
+        use ::zkp_auth::zkp_proto::auth_client::AuthClient;
+        use ::zkp_auth::telemetry::{RequestId, REQUEST_ID_HEADER};
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt().json().with_current_span(true).with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone()).finish();
+        //the test runtime runs the server on this thread, so it logs to this subscriber
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let auth = Arc::new(auth_impl());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        let server = Server::builder().layer(RpcTraceLayer).layer(PeerLimitLayer::new(auth.limiter.clone())).add_service(AuthServer::from_arc(auth.clone()));
+        tokio::spawn(server.serve_with_incoming(incoming));
+
+        let channel = tonic::transport::Channel::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+        let request_id = RequestId::new();
+        let mut client = AuthClient::with_interceptor(channel, request_id.clone());
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        let did = did_for(zkp, &x);
+        let response = client.register(register_request(zkp, &x).into_inner()).await.unwrap();
+        assert_eq!(response.metadata().get(REQUEST_ID_HEADER).unwrap(), request_id.as_str());
+        let k = zkp.group.random_scalar();
+        let (r1, r2) = zkp.exponentiate_pair(&k);
+        let challenge = client.create_challenge(Commitment::new(zkp, &r1, &r2).to_challenge_request(did.clone(), group_id())).await.unwrap().into_inner();
+        let session_id = client.verify_authentication(solution(zkp, &x, &k, &challenge).into_inner()).await.unwrap().into_inner().session_id;
+        //a refused call is logged with its code, not with the message that names the DID
+        client.create_challenge(Commitment::new(zkp, &r1, &r2).to_challenge_request(did.clone(), group_id())).await.unwrap_err();
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        for raw in [did.as_str(), &did[8..], &session_id, &challenge.auth_id] {
+            assert!(!logs.contains(raw), "{} is in the logs", raw);
+        }
+        let lines: Vec<serde_json::Value> = logs.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let finished: Vec<_> = lines.iter().filter(|line| line["fields"]["message"] == "rpc finished").collect();
+        assert_eq!(finished.len(), 3);
+        for line in &finished {
+            assert_eq!(line["span"]["request_id"], request_id.as_str());
+            assert_eq!(line["span"]["did"], telemetry::did(&did).as_str());
+        }
+        //both calls of the login share the hash of their auth_id
+        assert_eq!(finished[1]["span"]["login"], finished[2]["span"]["login"]);
+        assert!(lines.iter().any(|line| line["fields"]["session"] == telemetry::session(&session_id).as_str()));
+        assert!(lines.iter().any(|line| line["target"] == "audit" && line["fields"]["did"] == telemetry::did(&did).as_str()));
+        assert!(lines.iter().any(|line| line["fields"]["message"] == "rpc refused" && line["fields"]["code"] == "FailedPrecondition"));
+    }
+
+    //an interactive login from commitment to session
+    async fn interactive_login(auth: &AuthImpl<::zkp_auth::ModpGroup>, did: &str, x: &BigUint) -> std::result::Result<String, Status> {
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let k = zkp.group.random_scalar();
+        let (r1, r2) = zkp.exponentiate_pair(&k);
+        let request = Commitment::new(zkp, &r1, &r2).to_challenge_request(did.to_string(), group_id());
+        let response = auth.create_challenge(Request::new(request)).await?.into_inner();
+        let c = Challenge::from(&response).decode(zkp).unwrap();
+        let s = proof::Response::new(zkp, &zkp.solve(&k, &c, x)).s().to_vec();
+        Ok(auth.verify_authentication(Request::new(SolutionRequest { auth_id: response.auth_id, s })).await?.into_inner().session_id)
+    }
+
+    //registers a new DID and asks for a challenge, returns the secret, the nonce and the response
+    async fn open_challenge(auth: &AuthImpl<::zkp_auth::ModpGroup>) -> (BigUint, BigUint, ChallengeResponse) {
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        auth.register(register_request(zkp, &x)).await.unwrap();
+        let k = zkp.group.random_scalar();
+        let (r1, r2) = zkp.exponentiate_pair(&k);
+        let request = Commitment::new(zkp, &r1, &r2).to_challenge_request(did_for(zkp, &x), group_id());
+        (x, k, auth.create_challenge(Request::new(request)).await.unwrap().into_inner())
+    }
+
+    fn solution(zkp: &ZKP<::zkp_auth::ModpGroup>, x: &BigUint, k: &BigUint, response: &ChallengeResponse) -> Request<SolutionRequest> {
+        let c = Challenge::from(response).decode(zkp).unwrap();
+        let s = proof::Response::new(zkp, &zkp.solve(k, &c, x)).s().to_vec();
+        Request::new(SolutionRequest { auth_id: response.auth_id.clone(), s })
+    }
+
+    #[tokio::test]
+    async fn test_auth_id_is_single_use() {
+        let auth = auth_impl();
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, k, response) = open_challenge(&auth).await;
+
+        //a wrong answer uses the auth_id up, the right one afterwards is refused as a reuse
+        let wrong = Request::new(SolutionRequest { auth_id: response.auth_id.clone(), s: proof::Response::new(zkp, &zkp.group.random_scalar()).s().to_vec() });
+        assert_eq!(auth.verify_authentication(wrong).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        let status = auth.verify_authentication(solution(zkp, &x, &k, &response)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(status.message(), format!("AuthId: {} was already used, ask for a new challenge", response.auth_id));
+
+        let unknown = Request::new(SolutionRequest { auth_id: "nothing".to_string(), s: vec![1] });
+        assert_eq!(auth.verify_authentication(unknown).await.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_expired_challenge_is_refused() {
+        let auth = auth_impl().with_challenge_ttl(Duration::ZERO);
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, k, response) = open_challenge(&auth).await;
+
+        //a correct answer that comes too late, and again
+        for _ in 0..2 {
+            let status = auth.verify_authentication(solution(zkp, &x, &k, &response)).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        }
+        assert!(auth.challenges.is_empty().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sweeper_evicts_expired_challenges() {
+        let auth = auth_impl();
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, k, response) = open_challenge(&auth).await;
+        let now = Utc::now();
+
+        assert_eq!(auth.sweep(now).unwrap(), 0);
+        assert_eq!(auth.challenges.len().unwrap(), 1);
+        assert_eq!(auth.sweep(now + chrono::Duration::from_std(CHALLENGE_TTL).unwrap()).unwrap(), 1);
+        assert!(auth.challenges.is_empty().unwrap());
+        assert_eq!(auth.verify_authentication(solution(zkp, &x, &k, &response)).await.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+
+        //after a while the auth_id is forgotten altogether
+        auth.sweep(now + chrono::Duration::from_std(CHALLENGE_TTL + RETIRED_FOR).unwrap()).unwrap();
+        assert_eq!(auth.verify_authentication(solution(zkp, &x, &k, &response)).await.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    fn validate(session_id: &str) -> Request<ValidateSessionRequest> {
+        Request::new(ValidateSessionRequest { session_id: session_id.to_string() })
+    }
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        let auth = auth_impl();
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, k, response) = open_challenge(&auth).await;
+        let first = auth.verify_authentication(solution(zkp, &x, &k, &response)).await.unwrap().into_inner().session_id;
+        let second = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"second")).await.unwrap().into_inner().session_id;
+
+        let info = auth.validate_session(validate(&first)).await.unwrap().into_inner().session.unwrap();
+        assert_eq!(info.did, did_for(zkp, &x));
+        assert!(info.expires_at > info.created_at);
+        let refreshed = auth.refresh_session(Request::new(RefreshSessionRequest { session_id: first.clone() })).await.unwrap().into_inner().session.unwrap();
+        assert!(refreshed.last_seen >= info.last_seen);
+
+        let listed = auth.list_sessions(Request::new(ListSessionsRequest { session_id: second.clone() })).await.unwrap().into_inner().sessions;
+        assert_eq!(listed.iter().map(|s| s.session_id.clone()).collect::<Vec<_>>(), [first.clone(), second.clone()]);
+
+        //one logout ends that session only, the other one can then log out everywhere
+        let logout = |session_id: &str, everywhere| Request::new(LogoutRequest { session_id: session_id.to_string(), everywhere });
+        assert_eq!(auth.logout(logout(&first, false)).await.unwrap().into_inner().revoked, 1);
+        assert_eq!(auth.validate_session(validate(&first)).await.unwrap_err().code(), tonic::Code::NotFound);
+        auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"third")).await.unwrap();
+        assert_eq!(auth.logout(logout(&second, true)).await.unwrap().into_inner().revoked, 2);
+        assert!(auth.sessions.is_empty().unwrap());
+        assert_eq!(auth.logout(logout(&second, true)).await.unwrap_err().code(), tonic::Code::NotFound);
+        assert_eq!(auth.validate_session(validate("")).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_login_token_verifies_offline() {
+        use ::zkp_auth::token::{session_handle, Jwks, TokenVerifier};
+
+        let auth = auth_impl();
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, _, _) = open_challenge(&auth).await;
+        let login = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"token")).await.unwrap().into_inner();
+
+        //a service only needs the published keys
+        let jwks = Jwks::from(&auth.get_jwks(Request::new(GetJwksRequest {})).await.unwrap().into_inner());
+        let verifier = TokenVerifier::new(&auth.tokens.policy().issuer, &jwks);
+        let claims = verifier.verify(&login.token, Utc::now()).unwrap();
+        assert_eq!(claims.sub, did_for(zkp, &x));
+        assert_eq!(claims.sid, session_handle(&login.session_id));
+        assert!(!login.token.contains(&login.session_id));
+
+        //a live session can ask for a new one, an ended one cannot
+        let issue = |session_id: &str| Request::new(IssueTokenRequest { session_id: session_id.to_string() });
+        let issued = auth.issue_token(issue(&login.session_id)).await.unwrap().into_inner();
+        assert_eq!(verifier.verify(&issued.token, Utc::now()).unwrap().exp, issued.expires_at);
+        auth.logout(Request::new(LogoutRequest { session_id: login.session_id.clone(), everywhere: false })).await.unwrap();
+        assert_eq!(auth.issue_token(issue(&login.session_id)).await.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_session_timeouts_and_limit() {
+        let policy = SessionPolicy { idle_timeout: Duration::ZERO, absolute_timeout: Duration::from_secs(60), max_per_did: 2 };
+        let auth = auth_impl().with_session_policy(policy);
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, _, _) = open_challenge(&auth).await;
+
+        //nothing may be idle at all here, a session has ended by the time it is looked at
+        let session_id = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"idle")).await.unwrap().into_inner().session_id;
+        assert_eq!(auth.validate_session(validate(&session_id)).await.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+
+        let auth = auth_impl().with_session_policy(SessionPolicy { idle_timeout: Duration::from_secs(60), ..policy });
+        let (x, _, _) = open_challenge(&auth).await;
+        let sessions: Vec<_> = logins(&auth, &x, 3).await;
+        //the third login ended the first session
+        assert_eq!(auth.validate_session(validate(&sessions[0])).await.unwrap_err().code(), tonic::Code::NotFound);
+        assert!(auth.validate_session(validate(&sessions[2])).await.is_ok());
+        assert_eq!(auth.sessions.len().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_state_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("zkp_auth_server_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = StoreConfig::Sqlite(path.clone());
+        let auth = auth_impl().with_stores(store.open(SessionPolicy::default()).unwrap());
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let (x, k, response) = open_challenge(&auth).await;
+        let session_id = auth.login(login_request(zkp, &x, &zkp.group.random_scalar(), b"before")).await.unwrap().into_inner().session_id;
+        drop(auth);
+
+        //the DID, its open challenge and its session are all still there
+        let auth = auth_impl().with_stores(store.open(SessionPolicy::default()).unwrap());
+        assert!(auth.validate_session(validate(&session_id)).await.is_ok());
+        auth.verify_authentication(solution(zkp, &x, &k, &response)).await.unwrap();
+        //the registration proof and both logins
+        assert_eq!(auth.users.get(&did_for(zkp, &x)).unwrap().unwrap().history.len(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    async fn logins(auth: &AuthImpl<::zkp_auth::ModpGroup>, x: &BigUint, n: usize) -> Vec<String> {
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let mut sessions = Vec::new();
+        for i in 0..n {
+            let login = login_request(zkp, x, &zkp.group.random_scalar(), format!("login {}", i).as_bytes());
+            sessions.push(auth.login(login).await.unwrap().into_inner().session_id);
+        }
+        sessions
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallel_logins_of_one_did() {
+        //the queue and the limits are large enough for every task, these tests are about the state and not back-pressure
+        let limits = LimitPolicy { per_did: Rate { burst: 64, per_minute: 64 }, max_pending_per_did: 64, ..LimitPolicy::default() };
+        let auth = std::sync::Arc::new(auth_impl().with_pool(VerifierPool::new(4, 64)).with_limit_policy(limits));
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+        let x = zkp.group.random_scalar();
+        auth.register(register_request(zkp, &x)).await.unwrap();
+
+        //every login has its own challenge record, none of them overwrites another's
+        let logins: Vec<_> = (0..32).map(|_| {
+            let (auth, x) = (auth.clone(), x.clone());
+            tokio::spawn(async move { interactive_login(&auth, &did_for(zkp, &x), &x).await })
+        }).collect();
+        let mut sessions = std::collections::HashSet::new();
+        for login in logins {
+            sessions.insert(login.await.unwrap().unwrap());
+        }
+        assert_eq!(sessions.len(), 32);
+        assert!(auth.challenges.is_empty().unwrap());
+        //every login and the registration proof
+        assert_eq!(auth.users.get(&did_for(zkp, &x)).unwrap().unwrap().history.len(), 33);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallel_traffic_does_not_deadlock() {
+        //the queue is large enough for every task, these tests are about the state and not back-pressure
+        let auth = std::sync::Arc::new(auth_impl().with_pool(VerifierPool::new(4, 64)));
+        let zkp = auth.groups[RFC5114_1024_GROUP_ID];
+
+        //registrations, interactive and non-interactive logins and batches of many DIDs at once
+        let tasks: Vec<_> = (0..8).map(|_| {
+            let auth = auth.clone();
+            tokio::spawn(async move {
+                let x = zkp.group.random_scalar();
+                let did = did_for(zkp, &x);
+                auth.register(register_request(zkp, &x)).await.unwrap();
+
+                for round in 0..6 {
+                    let login = login_request(zkp, &x, &zkp.group.random_scalar(), format!("{} {}", did, round).as_bytes()).into_inner();
+                    match round % 3 {
+                        0 => { interactive_login(&auth, &did, &x).await.unwrap(); },
+                        1 => { auth.login(Request::new(login)).await.unwrap(); },
+                        _ => {
+                            let results = auth.batch_login(Request::new(BatchLoginRequest { logins: vec![login] })).await.unwrap().into_inner().results;
+                            assert_eq!(results[0].code, tonic::Code::Ok as i32);
+                        }
+                    }
+                }
+                did
+            })
+        }).collect();
+        for task in tasks {
+            //six logins and the registration proof
+            let user = auth.users.get(&task.await.unwrap()).unwrap().unwrap();
+            assert!(user.history.len() == 7 && !user.compromised);
+        }
+    }
+
+    //one interactive and one non-interactive login where every random value comes from a seed
+    //the login context carries a time, both runs are given the same one
+    //with refused, a challenge for an unknown DID is asked for first and refused
+    async fn seeded_run(seed: u64, now: DateTime<Utc>, refused: bool) -> Vec<String> {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let set = parameter_set(RFC5114_1024_GROUP_ID).unwrap();
+        let groups = HashMap::from([(set.id.to_string(), context::modp(set.id).unwrap())]);
+        let auth = AuthImpl::with_rng(groups, Box::new(StdRng::seed_from_u64(seed)));
+        let zkp = &auth.groups[RFC5114_1024_GROUP_ID];
+        let mut prover = StdRng::seed_from_u64(seed + 1);
+
+        let x = zkp.group.random_scalar_with(&mut prover);
+        let (y1, y2) = zkp.exponentiate_pair(&x);
+        let did = did_for(zkp, &x);
+        auth.register(enroll(registration::register_request_with(&mut prover, zkp, &did, &x, group_id()))).await.unwrap();
+
+        let (r1, r2, k) = zkp.commit_with(&mut prover, &x, &y1, &y2, b"challenge");
+        if refused {
+            let request = Request::new(Commitment::new(zkp, &r1, &r2).to_challenge_request("did:zkp:unknown".to_string(), group_id()));
+            assert_eq!(auth.create_challenge(request).await.unwrap_err().code(), tonic::Code::NotFound);
+        }
+        let request = Request::new(Commitment::new(zkp, &r1, &r2).to_challenge_request(did.clone(), group_id()));
+        let response = auth.create_challenge(request).await.unwrap().into_inner();
+        let c = Challenge::from(&response).decode(zkp).unwrap();
+        let s = proof::Response::new(zkp, &zkp.solve(&k, &c, &x)).s().to_vec();
+        let auth_id = response.auth_id.clone();
+        let session = auth.verify_authentication(Request::new(SolutionRequest { auth_id, s })).await.unwrap().into_inner();
+
+        let context = login::new_login_context_with(&mut prover, &did, now);
+        let (r1, r2, s) = zkp.prove_non_interactive_with(&mut prover, &x, &context);
+        let request = Request::new(Proof::new(zkp, &r1, &r2, &s).to_login_request(did, context, group_id()));
+        let login = auth.login(request).await.unwrap().into_inner();
+
+        vec![response.auth_id, hex::encode(response.c), session.session_id, hex::encode(Proof::new(zkp, &r1, &r2, &s).to_bytes()), login.session_id]
+    }
+
+    #[tokio::test]
+    async fn test_seeded_runs_replay_exactly() {
+        let now = Utc::now();
+        assert_eq!(seeded_run(7, now, false).await, seeded_run(7, now, false).await);
+        assert_ne!(seeded_run(7, now, false).await, seeded_run(8, now, false).await);
+        //a refused request draws nothing from the RNG, the run after it is the same
+        assert_eq!(seeded_run(7, now, false).await, seeded_run(7, now, true).await);
+    }
+
+    #[test]
+    fn test_parameter_sets_pass_start_up_check() {
+        let groups = PARAMETER_SETS.iter().map(|set| (set.id.to_string(), context::modp(set.id).unwrap())).collect();
+        assert!(check_parameters(&groups));
+    }
+
+    #[test]
+    fn test_legacy_group_is_opt_in() {
+        let defaults = Settings::from_args(&[]).unwrap();
+        assert!(!defaults.group_ids.iter().any(|id| id == RFC5114_1024_GROUP_ID));
+        assert!(defaults.group_ids.iter().any(|id| id == ::zkp_auth::RFC5114_2048_256_GROUP_ID));
+        let named = Settings::from_args(&["--group".to_string(), RFC5114_1024_GROUP_ID.to_string()]).unwrap();
+        assert_eq!(named.group_ids, vec![RFC5114_1024_GROUP_ID.to_string()]);
+    }
+}
@@ -0,0 +1,292 @@
+//The sessions the server hands out after a successful login.
+//A session ends when it is not used for the idle timeout, when it reaches the
+//absolute timeout however often it was refreshed, or when it is logged out.
+//A DID holds at most max_per_did sessions, a login beyond that ends its oldest one.
+//
+//Two sharded maps are kept, by session id for the lookups every request does and
+//by DID for the limit, the listing and logging out everywhere. Lock order: a
+//by_did entry, then a sessions entry. Nothing that holds a sessions entry touches by_did.
+//This is the in-memory store::SessionStore, the other backends follow the same rules.
+
+use crate::error::{Result, ZkpAuthError};
+use crate::store::SessionStore;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+//a session that is not used for this long ends
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+//no session lives longer than this, refreshing does not extend it
+pub const ABSOLUTE_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+//sessions a DID may have at the same time
+pub const MAX_SESSIONS_PER_DID: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionPolicy {
+    pub idle_timeout: Duration,
+    pub absolute_timeout: Duration,
+    pub max_per_did: usize,
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        Self { idle_timeout: IDLE_TIMEOUT, absolute_timeout: ABSOLUTE_TIMEOUT, max_per_did: MAX_SESSIONS_PER_DID }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub did: String,
+    pub created_at: DateTime<Utc>,
+    //the login or the last refresh
+    pub last_seen: DateTime<Utc>,
+}
+
+impl Session {
+    //the earlier of the idle and the absolute deadline
+    pub fn expires_at(&self, policy: &SessionPolicy) -> DateTime<Utc> {
+        let idle = self.last_seen + to_chrono(policy.idle_timeout);
+        let absolute = self.created_at + to_chrono(policy.absolute_timeout);
+        idle.min(absolute)
+    }
+
+    pub fn is_live(&self, policy: &SessionPolicy, now: DateTime<Utc>) -> bool {
+        now < self.expires_at(policy)
+    }
+}
+
+//timeouts beyond what chrono can hold are as good as never
+pub(crate) fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::MAX)
+}
+
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    policy: SessionPolicy,
+    sessions: DashMap<String, Session>,
+    //the ids of a DID's sessions, oldest first
+    by_did: DashMap<String, Vec<String>>,
+}
+
+impl MemorySessionStore {
+    pub fn new(policy: SessionPolicy) -> Self {
+        Self { policy, sessions: DashMap::new(), by_did: DashMap::new() }
+    }
+
+    //sessions read back from a file, each DID's oldest first
+    pub fn from_sessions(policy: SessionPolicy, mut sessions: Vec<Session>) -> Self {
+        sessions.sort_by_key(|session| session.created_at);
+        let store = Self::new(policy);
+        for session in sessions {
+            store.by_did.entry(session.did.clone()).or_default().push(session.id.clone());
+            store.sessions.insert(session.id.clone(), session);
+        }
+        store
+    }
+
+    pub fn sessions(&self) -> Vec<Session> {
+        self.sessions.iter().map(|session| session.clone()).collect()
+    }
+
+    fn end(&self, id: &str) -> Option<Session> {
+        let (_, session) = self.sessions.remove(id)?;
+        self.forget_ended(&session.did);
+        Some(session)
+    }
+
+    //drops the ids of a DID whose sessions are gone, and the DID once it has none
+    fn forget_ended(&self, did: &str) {
+        self.by_did.remove_if_mut(did, |_, ids| {
+            ids.retain(|id| self.sessions.contains_key(id));
+            ids.is_empty()
+        });
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn policy(&self) -> &SessionPolicy {
+        &self.policy
+    }
+
+    //starts a session with the given id, the caller draws it from its RNG
+    //returns the session and the ones that were ended to stay within the per-DID limit
+    fn create(&self, did: &str, id: String, now: DateTime<Utc>) -> Result<(Session, Vec<Session>)> {
+        let session = Session { id: id.clone(), did: did.to_string(), created_at: now, last_seen: now };
+        let mut ids = self.by_did.entry(did.to_string()).or_default();
+        //sessions that ended on their own still count until they are dropped here
+        ids.retain(|id| self.sessions.get(id).is_some_and(|session| session.is_live(&self.policy, now)));
+        let mut ended = Vec::new();
+        while ids.len() >= self.policy.max_per_did.max(1) {
+            let oldest = ids.remove(0);
+            ended.extend(self.sessions.remove(&oldest).map(|(_, session)| session));
+        }
+        ids.push(id.clone());
+        self.sessions.insert(id, session.clone());
+        Ok((session, ended))
+    }
+
+    //the session if it is live, it is not extended
+    fn validate(&self, id: &str, now: DateTime<Utc>) -> Result<Session> {
+        let session = self.sessions.get(id).map(|session| session.clone())
+            .ok_or_else(|| not_found(id))?;
+        if !session.is_live(&self.policy, now) {
+            self.end(id);
+            return Err(expired(id));
+        }
+        Ok(session)
+    }
+
+    //moves the idle deadline, the absolute one stays where it is
+    fn refresh(&self, id: &str, now: DateTime<Utc>) -> Result<Session> {
+        let refreshed = {
+            let mut session = self.sessions.get_mut(id).ok_or_else(|| not_found(id))?;
+            if session.is_live(&self.policy, now) {
+                session.last_seen = now;
+                Some(session.clone())
+            } else {
+                None
+            }
+        };
+        refreshed.ok_or_else(|| {
+            self.end(id);
+            expired(id)
+        })
+    }
+
+    //logs one session out
+    fn revoke(&self, id: &str) -> Result<Session> {
+        self.end(id).ok_or_else(|| not_found(id))
+    }
+
+    //logs every session of the DID out, returns how many there were
+    fn revoke_all(&self, did: &str) -> Result<usize> {
+        let ids = self.by_did.remove(did).map(|(_, ids)| ids).unwrap_or_default();
+        Ok(ids.iter().filter(|id| self.sessions.remove(*id).is_some()).count())
+    }
+
+    //the live sessions of the DID, oldest first
+    fn list(&self, did: &str, now: DateTime<Utc>) -> Result<Vec<Session>> {
+        let ids = self.by_did.get(did).map(|ids| ids.clone()).unwrap_or_default();
+        Ok(ids.iter()
+            .filter_map(|id| self.sessions.get(id).map(|session| session.clone()))
+            .filter(|session| session.is_live(&self.policy, now))
+            .collect())
+    }
+
+    //drops the sessions that ended, returns how many
+    fn sweep(&self, now: DateTime<Utc>) -> Result<usize> {
+        let mut ended = Vec::new();
+        self.sessions.retain(|_, session| {
+            let live = session.is_live(&self.policy, now);
+            if !live {
+                ended.push(session.did.clone());
+            }
+            live
+        });
+        for did in &ended {
+            self.forget_ended(did);
+        }
+        Ok(ended.len())
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.sessions.len())
+    }
+}
+
+fn not_found(id: &str) -> ZkpAuthError {
+    ZkpAuthError::NotFound(format!("Session: {} not found", id))
+}
+
+fn expired(id: &str) -> ZkpAuthError {
+    ZkpAuthError::Expired(format!("Session: {} expired, log in again", id))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn store(max_per_did: usize) -> MemorySessionStore {
+        MemorySessionStore::new(SessionPolicy { idle_timeout: Duration::from_secs(60), absolute_timeout: Duration::from_secs(300), max_per_did })
+    }
+
+    fn after(now: DateTime<Utc>, seconds: i64) -> DateTime<Utc> {
+        now + chrono::Duration::seconds(seconds)
+    }
+
+    #[test]
+    fn test_idle_and_absolute_timeouts() {
+        let sessions = store(10);
+        let now = Utc::now();
+        sessions.create("did:zkp:a", "one".to_string(), now).unwrap();
+
+        //validating does not count as activity, refreshing does
+        assert!(sessions.validate("one", after(now, 59)).is_ok());
+        assert!(sessions.refresh("one", after(now, 59)).is_ok());
+        assert!(sessions.validate("one", after(now, 100)).is_ok());
+
+        //refreshed every 50 seconds it still ends at the absolute timeout
+        for t in (110..300).step_by(50) {
+            assert!(sessions.refresh("one", after(now, t)).is_ok());
+        }
+        assert_eq!(sessions.validate("one", after(now, 299)).unwrap().expires_at(sessions.policy()), after(now, 300));
+        assert!(matches!(sessions.refresh("one", after(now, 300)), Err(ZkpAuthError::Expired(_))));
+        //an ended session is dropped, asking again finds nothing
+        assert!(matches!(sessions.validate("one", after(now, 300)), Err(ZkpAuthError::NotFound(_))));
+        assert!(sessions.list("did:zkp:a", after(now, 300)).unwrap().is_empty());
+
+        sessions.create("did:zkp:a", "two".to_string(), now).unwrap();
+        assert!(matches!(sessions.validate("two", after(now, 60)), Err(ZkpAuthError::Expired(_))));
+    }
+
+    #[test]
+    fn test_limit_per_did_ends_the_oldest() {
+        let sessions = store(2);
+        let now = Utc::now();
+        sessions.create("did:zkp:a", "one".to_string(), now).unwrap();
+        sessions.create("did:zkp:a", "two".to_string(), now).unwrap();
+        sessions.create("did:zkp:b", "other".to_string(), now).unwrap();
+        let (_, ended) = sessions.create("did:zkp:a", "three".to_string(), now).unwrap();
+        assert_eq!(ended.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(), ["one"]);
+
+        let ids: Vec<_> = sessions.list("did:zkp:a", now).unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, ["two", "three"]);
+
+        //a session that ended on its own makes room without ending another one
+        let (_, ended) = sessions.create("did:zkp:a", "four".to_string(), after(now, 61)).unwrap();
+        assert_eq!(ended.len(), 0);
+        assert_eq!(sessions.list("did:zkp:a", after(now, 61)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_logout_and_logout_everywhere() {
+        let sessions = store(10);
+        let now = Utc::now();
+        for id in ["one", "two", "three"] {
+            sessions.create("did:zkp:a", id.to_string(), now).unwrap();
+        }
+        sessions.create("did:zkp:b", "other".to_string(), now).unwrap();
+
+        assert_eq!(sessions.revoke("two").unwrap().did, "did:zkp:a");
+        assert!(matches!(sessions.revoke("two"), Err(ZkpAuthError::NotFound(_))));
+        assert_eq!(sessions.revoke_all("did:zkp:a").unwrap(), 2);
+        assert!(sessions.validate("one", now).is_err());
+        assert!(sessions.list("did:zkp:a", now).unwrap().is_empty());
+        assert!(sessions.validate("other", now).is_ok());
+    }
+
+    #[test]
+    fn test_sweep_drops_ended_sessions() {
+        let sessions = store(10);
+        let now = Utc::now();
+        sessions.create("did:zkp:a", "old".to_string(), now).unwrap();
+        sessions.create("did:zkp:a", "new".to_string(), after(now, 30)).unwrap();
+        assert_eq!(sessions.sweep(after(now, 60)).unwrap(), 1);
+        assert_eq!(sessions.len().unwrap(), 1);
+        assert_eq!(sessions.sweep(after(now, 90)).unwrap(), 1);
+        assert_eq!(sessions.len().unwrap(), 0);
+        assert!(sessions.by_did.is_empty());
+    }
+}
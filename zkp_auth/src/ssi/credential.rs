@@ -1,9 +1,12 @@
+use crate::error::{Result, ZkpAuthError};
 use serde::{Deserialize, Serialize};
 use num_bigint::BigUint;
 use sha2::{Sha256, Digest};
+use std::fmt;
+use base64::{Engine as _, engine::general_purpose};
 
 // W3C Verifiable Credential structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct VerifiableCredential {
     #[serde(rename = "@context")]
     pub context: Vec<String>,
@@ -25,7 +28,7 @@ pub struct VerifiableCredential {
 }
 
 // What the credential claims about the holder
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CredentialSubject {
     pub id: String,  // DID of holder
     pub name: String,
@@ -34,7 +37,7 @@ pub struct CredentialSubject {
 }
 
 // stores the ZKP public parameters with the credential
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ZKPProof {
     #[serde(rename = "type")]
     pub proof_type: String,
@@ -44,6 +47,19 @@ pub struct ZKPProof {
     pub y2: String,  // Base64 encoded
 }
 
+impl VerifiableCredential {
+    // checks the credential was issued to this DID for these public keys
+    pub fn check_holder(&self, did: &str, y1: &[u8], y2: &[u8]) -> Result<()> {
+        if self.credential_subject.id != did {
+            return Err(ZkpAuthError::Encoding(format!("the credential is for {}, not {}", self.credential_subject.id, did)));
+        }
+        if self.proof.y1 != general_purpose::STANDARD.encode(y1) || self.proof.y2 != general_purpose::STANDARD.encode(y2) {
+            return Err(ZkpAuthError::Encoding("the credential names other keys than y1 and y2".to_string()));
+        }
+        Ok(())
+    }
+}
+
 // DID structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DID {
@@ -54,7 +70,7 @@ pub struct DID {
 impl DID {
     // Generate DID from ZKP public keys
     pub fn from_zkp_params(y1: &BigUint, y2: &BigUint) -> Self {
-        let combined = format!("{}{}", y1.to_string(), y2.to_string());
+        let combined = format!("{}{}", y1, y2);
         let mut hasher = Sha256::new();
         hasher.update(combined.as_bytes());
         let hash = hasher.finalize();
@@ -66,21 +82,45 @@ impl DID {
         }
     }
     
-    // Convert DID to string format
-    pub fn to_string(&self) -> String {
-        format!("did:{}:{}", self.method, self.identifier)
+    // Generate DID from encoded public keys of any group
+    // the bytes are read as big-endian integers so mod p keys keep the DID from_zkp_params gives them
+    pub fn from_public_keys(y1: &[u8], y2: &[u8]) -> Self {
+        Self::from_zkp_params(&BigUint::from_bytes_be(y1), &BigUint::from_bytes_be(y2))
     }
     
     // Parse DID from string
-    pub fn from_string(did_str: &str) -> Option<Self> {
+    pub fn from_string(did_str: &str) -> Result<Self> {
         let parts: Vec<&str> = did_str.split(':').collect();
-        if parts.len() == 3 && parts[0] == "did" {
-            Some(Self {
+        if parts.len() == 3 && parts[0] == "did" && !parts[1].is_empty() && !parts[2].is_empty() {
+            Ok(Self {
                 method: parts[1].to_string(),
                 identifier: parts[2].to_string(),
             })
         } else {
-            None
+            Err(ZkpAuthError::Encoding(format!("{} is not a DID, expected did:method:identifier", did_str)))
+        }
+    }
+}
+
+// Convert DID to string format
+impl fmt::Display for DID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "did:{}:{}", self.method, self.identifier)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_did_from_string() {
+        let did = DID::from_string("did:zkp:dc42f52a472aaf0525242f995b71c4f2").unwrap();
+        assert_eq!(did.method, "zkp");
+        assert_eq!(did.to_string(), "did:zkp:dc42f52a472aaf0525242f995b71c4f2");
+
+        for bad in ["", "did:zkp", "did::abc", "urn:zkp:abc", "did:zkp:a:b"] {
+            assert!(matches!(DID::from_string(bad), Err(ZkpAuthError::Encoding(_))), "{}", bad);
         }
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,5 @@
 use super::credential::{VerifiableCredential, CredentialSubject, ZKPProof, DID};
-use num_bigint::BigUint;
+use crate::error::{Result, ZkpAuthError};
 use chrono::Utc;
 use base64::{Engine as _, engine::general_purpose};
 
@@ -8,17 +8,16 @@ pub struct Issuer;
 
 impl Issuer {
     // Issue a credential with hardcoded values and return both credential and DID
-    pub fn issue_credential(username: &str, y1: &BigUint, y2: &BigUint) -> (VerifiableCredential, DID) {
-        println!("\n🏛️ ISSUER: Pan-Atlantic University");
-        println!("  Issuing credential for: {}", username);
-        
+    // y1 and y2 are the public keys in the group's encoding
+    pub fn issue_credential(username: &str, y1: &[u8], y2: &[u8]) -> Result<(VerifiableCredential, DID)> {
+        if y1.is_empty() || y2.is_empty() {
+            return Err(ZkpAuthError::Encoding("public keys cannot be empty".to_string()));
+        }
+
         // Generate DID from ZKP parameters
-        let did = DID::from_zkp_params(y1, y2);
+        let did = DID::from_public_keys(y1, y2);
         let did_string = did.to_string();
         
-        println!("  📋 Generated DID: {}", did_string);
-        println!("  🔑 This DID is your unique decentralized identifier!");
-        
         // Create the credential
         let credential = VerifiableCredential {
             context: vec![
@@ -41,13 +40,14 @@ impl Issuer {
             proof: ZKPProof {
                 proof_type: "ZKPSignature2024".to_string(),
                 verification_method: format!("{}#zkp-key-1", did_string),
-                y1: general_purpose::STANDARD.encode(y1.to_bytes_be()),
-                y2: general_purpose::STANDARD.encode(y2.to_bytes_be()),
+                y1: general_purpose::STANDARD.encode(y1),
+                y2: general_purpose::STANDARD.encode(y2),
             },
         };
         
-        println!("  ✓ Credential created with ID: {}", credential.id);
+        // the subject's name and DID stay out of the logs
+        tracing::info!(did = %crate::telemetry::did(&did_string), issuer = %credential.issuer, "credential issued");
         
-        (credential, did)
+        Ok((credential, did))
     }
 }
\ No newline at end of file
@@ -0,0 +1,344 @@
+//The JSON file backend: the in-memory stores, written to one file after every change of a
+//user or a session. The whole file is written each time, to a temporary file that is then
+//renamed over it, so a crash leaves the old or the new state and never half of one. Meant for
+//a single small server, every login rewrites the file; use SQLite beyond that.
+//Challenges and retired auth_ids are not written on their own, only with the next user or
+//session change. A login makes one right after it retires its auth_id or context, so those
+//are on disk before the session is handed out; an open challenge a restart forgot is asked
+//for again. A registration that could not be written is undone, any other change that could
+//not be written stays in memory and reaches the file with the next one.
+//
+//The file has a version, a file from an older version is migrated when it is opened:
+//each entry of MIGRATIONS turns version n into n + 1. A new file starts at version 0.
+
+use super::memory::{MemoryChallengeStore, MemoryUserStore};
+use super::{ChallengeRecord, ChallengeStore, Retired, SessionStore, Taken, UserRecord, UserStore};
+use crate::error::{Result, ZkpAuthError};
+use crate::session::{MemorySessionStore, Session, SessionPolicy};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+type Migration = fn(&mut Map<String, Value>);
+
+const MIGRATIONS: &[Migration] = &[
+    //1: users, open challenges, retired auth_ids and sessions
+    |file| {
+        file.insert("users".to_string(), json!([]));
+        file.insert("challenges".to_string(), json!({}));
+        file.insert("retired".to_string(), json!({}));
+        file.insert("sessions".to_string(), json!([]));
+    },
+    //2: the enrollment of each user, null for the ones registered before
+    |file| {
+        if let Some(Value::Array(users)) = file.get_mut("users") {
+            for user in users.iter_mut().filter_map(Value::as_object_mut) {
+                user.insert("enrollment".to_string(), Value::Null);
+            }
+        }
+    },
+];
+
+//the version this server writes
+pub const VERSION: u64 = MIGRATIONS.len() as u64;
+
+#[derive(Serialize, Deserialize)]
+struct JsonFile {
+    version: u64,
+    users: Vec<UserRecord>,
+    challenges: BTreeMap<String, ChallengeRecord>,
+    retired: BTreeMap<String, (Retired, DateTime<Utc>)>,
+    sessions: Vec<Session>,
+}
+
+#[derive(Debug)]
+pub struct JsonFileStore {
+    path: PathBuf,
+    users: MemoryUserStore,
+    challenges: MemoryChallengeStore,
+    sessions: MemorySessionStore,
+    //one writer at a time, the state is read again under it so the last write has every change
+    writing: Mutex<()>,
+}
+
+impl JsonFileStore {
+    pub fn open(path: &Path, policy: SessionPolicy) -> Result<Self> {
+        let mut file = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice::<Map<String, Value>>(&bytes)
+                .map_err(|e| ZkpAuthError::Storage(format!("{} is not a store file: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Map::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let version = file.get("version").and_then(Value::as_u64).unwrap_or(0);
+        if version > VERSION {
+            return Err(ZkpAuthError::Storage(format!("{} is version {}, this server reads up to {}", path.display(), version, VERSION)));
+        }
+        for migration in &MIGRATIONS[version as usize..] {
+            migration(&mut file);
+        }
+        file.insert("version".to_string(), json!(VERSION));
+        let file: JsonFile = serde_json::from_value(Value::Object(file))
+            .map_err(|e| ZkpAuthError::Storage(format!("{} does not hold a valid state: {}", path.display(), e)))?;
+
+        let store = Self {
+            path: path.to_path_buf(),
+            users: MemoryUserStore::from_records(file.users),
+            challenges: MemoryChallengeStore::from_records(file.challenges, file.retired),
+            sessions: MemorySessionStore::from_sessions(policy, file.sessions),
+            writing: Mutex::new(()),
+        };
+        //a migrated or new file is written right away
+        if version < VERSION {
+            store.save()?;
+        }
+        Ok(store)
+    }
+
+    fn save(&self) -> Result<()> {
+        let _writing = self.writing.lock().map_err(|_| ZkpAuthError::Storage(format!("{} is unavailable", self.path.display())))?;
+        let file = JsonFile {
+            version: VERSION,
+            users: self.users.records(),
+            challenges: self.challenges.records().into_iter().collect(),
+            retired: self.challenges.retired().into_iter().collect(),
+            sessions: self.sessions.sessions(),
+        };
+        let temporary = self.path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(&file)?)?;
+        std::fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    //saves after a change, whether or not the change itself succeeded
+    fn saved<T>(&self, result: Result<T>) -> Result<T> {
+        self.save()?;
+        result
+    }
+}
+
+impl UserStore for JsonFileStore {
+    fn get(&self, did: &str) -> Result<Option<UserRecord>> {
+        self.users.get(did)
+    }
+
+    //like sqlite, a registration the file does not have did not happen
+    fn register(&self, did: &str, register: &mut dyn FnMut(Option<&UserRecord>) -> Result<UserRecord>) -> Result<()> {
+        let (mut before, mut written) = (None, None);
+        self.users.register(did, &mut |existing| {
+            before = existing.cloned();
+            let user = register(existing)?;
+            written = Some(user.clone());
+            Ok(user)
+        })?;
+        if let Err(e) = self.save() {
+            if let Some(written) = &written {
+                self.users.restore(did, written, before);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn update(&self, did: &str, update: &mut dyn FnMut(&mut UserRecord) -> Result<()>) -> Result<()> {
+        let result = self.users.update(did, update);
+        match result {
+            Err(ZkpAuthError::NotFound(_)) => result,
+            _ => self.saved(result),
+        }
+    }
+
+    fn len(&self) -> Result<usize> {
+        UserStore::len(&self.users)
+    }
+}
+
+impl ChallengeStore for JsonFileStore {
+    //none of these write the file, see the top of this file
+    fn insert(&self, auth_id: &str, challenge: ChallengeRecord) -> Result<()> {
+        self.challenges.insert(auth_id, challenge)
+    }
+
+    fn take(&self, auth_id: &str, now: DateTime<Utc>, ttl: Duration) -> Result<Taken> {
+        self.challenges.take(auth_id, now, ttl)
+    }
+
+    fn retire(&self, key: &str, now: DateTime<Utc>) -> Result<bool> {
+        self.challenges.retire(key, now)
+    }
+
+    fn sweep(&self, now: DateTime<Utc>, ttl: Duration, retired_for: Duration) -> Result<usize> {
+        self.challenges.sweep(now, ttl, retired_for)
+    }
+
+    fn len(&self) -> Result<usize> {
+        ChallengeStore::len(&self.challenges)
+    }
+
+    fn pending(&self, did: &str, peer: Option<IpAddr>) -> Result<(usize, usize)> {
+        self.challenges.pending(did, peer)
+    }
+}
+
+impl SessionStore for JsonFileStore {
+    fn policy(&self) -> &SessionPolicy {
+        self.sessions.policy()
+    }
+
+    fn create(&self, did: &str, id: String, now: DateTime<Utc>) -> Result<(Session, Vec<Session>)> {
+        let created = self.sessions.create(did, id, now);
+        self.saved(created)
+    }
+
+    //a session that ended is dropped by validate, that is a change too
+    fn validate(&self, id: &str, now: DateTime<Utc>) -> Result<Session> {
+        let session = self.sessions.validate(id, now);
+        match session {
+            Err(ZkpAuthError::Expired(_)) => self.saved(session),
+            _ => session,
+        }
+    }
+
+    fn refresh(&self, id: &str, now: DateTime<Utc>) -> Result<Session> {
+        let session = self.sessions.refresh(id, now);
+        match session {
+            Err(ZkpAuthError::NotFound(_)) => session,
+            _ => self.saved(session),
+        }
+    }
+
+    fn revoke(&self, id: &str) -> Result<Session> {
+        let session = self.sessions.revoke(id)?;
+        self.saved(Ok(session))
+    }
+
+    fn revoke_all(&self, did: &str) -> Result<usize> {
+        let revoked = self.sessions.revoke_all(did)?;
+        if revoked > 0 {
+            self.save()?;
+        }
+        Ok(revoked)
+    }
+
+    fn list(&self, did: &str, now: DateTime<Utc>) -> Result<Vec<Session>> {
+        self.sessions.list(did, now)
+    }
+
+    fn sweep(&self, now: DateTime<Utc>) -> Result<usize> {
+        let ended = self.sessions.sweep(now)?;
+        if ended > 0 {
+            self.save()?;
+        }
+        Ok(ended)
+    }
+
+    fn len(&self) -> Result<usize> {
+        SessionStore::len(&self.sessions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::conformance;
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("zkp_auth_{}_{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_json_store() {
+        let path = path("conformance");
+        let store = JsonFileStore::open(&path, conformance::policy()).unwrap();
+        conformance::users(&store);
+        conformance::challenges(&store);
+        conformance::sessions(&store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_json_store_survives_a_restart() {
+        let path = path("restart");
+        let now = Utc::now();
+        {
+            let store = JsonFileStore::open(&path, conformance::policy()).unwrap();
+            store.register("did:zkp:a", &mut |_| Ok(conformance::user("did:zkp:a"))).unwrap();
+            store.insert("auth", conformance::challenge("did:zkp:a", now)).unwrap();
+            store.create("did:zkp:a", "one".to_string(), now).unwrap();
+            store.create("did:zkp:a", "two".to_string(), conformance::after(now, 1)).unwrap();
+        }
+
+        let store = JsonFileStore::open(&path, conformance::policy()).unwrap();
+        assert_eq!(store.get("did:zkp:a").unwrap(), Some(conformance::user("did:zkp:a")));
+        assert!(matches!(store.take("auth", now, Duration::from_secs(60)).unwrap(), Taken::Open(_)));
+        let ids: Vec<_> = store.list("did:zkp:a", now).unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, ["one", "two"]);
+
+        //and the auth_id stays used after the next restart, it is written with the session of its login
+        store.create("did:zkp:a", "three".to_string(), conformance::after(now, 2)).unwrap();
+        let store = JsonFileStore::open(&path, conformance::policy()).unwrap();
+        assert_eq!(store.take("auth", now, Duration::from_secs(60)).unwrap(), Taken::Retired(Retired::Used));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_json_store_writes_users_and_sessions_only() {
+        let path = path("writes");
+        let now = Utc::now();
+        let store = JsonFileStore::open(&path, conformance::policy()).unwrap();
+        let written = || std::fs::read(&path).unwrap();
+        let before = written();
+        store.insert("auth", conformance::challenge("did:zkp:a", now)).unwrap();
+        store.take("auth", now, Duration::from_secs(60)).unwrap();
+        store.retire("login:a", now).unwrap();
+        ChallengeStore::sweep(&store, conformance::after(now, 3_600_000), Duration::from_secs(60), Duration::from_secs(600)).unwrap();
+        assert_eq!(written(), before);
+        store.register("did:zkp:a", &mut |_| Ok(conformance::user("did:zkp:a"))).unwrap();
+        assert_ne!(written(), before);
+
+        //a registration that could not be written is undone, the one before it is kept
+        let temporary = path.with_extension("tmp");
+        std::fs::create_dir(&temporary).unwrap();
+        let changed = UserRecord { compromised: true, ..conformance::user("did:zkp:a") };
+        assert!(store.register("did:zkp:a", &mut |_| Ok(changed.clone())).is_err());
+        assert!(store.register("did:zkp:b", &mut |_| Ok(conformance::user("did:zkp:b"))).is_err());
+        assert_eq!(store.get("did:zkp:a").unwrap(), Some(conformance::user("did:zkp:a")));
+        assert_eq!(store.get("did:zkp:b").unwrap(), None);
+        std::fs::remove_dir(&temporary).unwrap();
+        store.register("did:zkp:b", &mut |_| Ok(conformance::user("did:zkp:b"))).unwrap();
+        let store = JsonFileStore::open(&path, conformance::policy()).unwrap();
+        assert_eq!(store.get("did:zkp:b").unwrap(), Some(conformance::user("did:zkp:b")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_json_store_versions() {
+        let path = path("versions");
+        JsonFileStore::open(&path, conformance::policy()).unwrap();
+        let written: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written["version"], json!(VERSION));
+
+        //a file from a newer server is left alone
+        std::fs::write(&path, json!({ "version": VERSION + 1 }).to_string()).unwrap();
+        assert!(matches!(JsonFileStore::open(&path, conformance::policy()), Err(ZkpAuthError::Storage(_))));
+        std::fs::write(&path, "not json").unwrap();
+        assert!(matches!(JsonFileStore::open(&path, conformance::policy()), Err(ZkpAuthError::Storage(_))));
+
+        //a user from version 1 was registered before enrollments were recorded
+        let mut user = serde_json::to_value(conformance::user("did:zkp:a")).unwrap();
+        user.as_object_mut().unwrap().remove("enrollment");
+        let file = json!({ "version": 1, "users": [user], "challenges": {}, "retired": {}, "sessions": [] });
+        std::fs::write(&path, file.to_string()).unwrap();
+        let store = JsonFileStore::open(&path, conformance::policy()).unwrap();
+        assert_eq!(store.get("did:zkp:a").unwrap(), Some(conformance::user("did:zkp:a")));
+        let written: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written["users"][0]["enrollment"], Value::Null);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
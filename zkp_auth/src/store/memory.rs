@@ -0,0 +1,164 @@
+//The in-memory backend: sharded maps, a call only locks the shard of the entry it uses.
+//Lock order in MemoryChallengeStore: a challenges entry, then a retired entry.
+//The sessions are kept by MemorySessionStore in session.rs.
+
+use super::{unknown_did, ChallengeRecord, ChallengeStore, Retired, Taken, UserRecord, UserStore};
+use crate::error::Result;
+use crate::session::to_chrono;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use std::net::IpAddr;
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+pub struct MemoryUserStore {
+    users: DashMap<String, UserRecord>,
+}
+
+impl MemoryUserStore {
+    pub fn from_records(records: impl IntoIterator<Item = UserRecord>) -> Self {
+        Self { users: records.into_iter().map(|user| (user.did.clone(), user)).collect() }
+    }
+
+    pub fn records(&self) -> Vec<UserRecord> {
+        self.users.iter().map(|user| user.clone()).collect()
+    }
+
+    //puts back what a registration replaced, unless the record was changed again since
+    pub fn restore(&self, did: &str, written: &UserRecord, before: Option<UserRecord>) {
+        if let Entry::Occupied(mut entry) = self.users.entry(did.to_string())
+            && entry.get() == written
+        {
+            match before {
+                Some(before) => { entry.insert(before); }
+                None => { entry.remove(); }
+            }
+        }
+    }
+}
+
+impl UserStore for MemoryUserStore {
+    fn get(&self, did: &str) -> Result<Option<UserRecord>> {
+        Ok(self.users.get(did).map(|user| user.clone()))
+    }
+
+    //the entry holds its shard until it is written, no login can slip in between
+    fn register(&self, did: &str, register: &mut dyn FnMut(Option<&UserRecord>) -> Result<UserRecord>) -> Result<()> {
+        match self.users.entry(did.to_string()) {
+            Entry::Occupied(mut existing) => {
+                let user = register(Some(existing.get()))?;
+                existing.insert(user);
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(register(None)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn update(&self, did: &str, update: &mut dyn FnMut(&mut UserRecord) -> Result<()>) -> Result<()> {
+        let mut user = self.users.get_mut(did).ok_or_else(|| unknown_did(did))?;
+        update(&mut user)
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.users.len())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryChallengeStore {
+    challenges: DashMap<String, ChallengeRecord>,
+    //auth_ids that were answered or expired, and when
+    retired: DashMap<String, (Retired, DateTime<Utc>)>,
+}
+
+impl MemoryChallengeStore {
+    pub fn from_records(challenges: impl IntoIterator<Item = (String, ChallengeRecord)>, retired: impl IntoIterator<Item = (String, (Retired, DateTime<Utc>))>) -> Self {
+        Self { challenges: challenges.into_iter().collect(), retired: retired.into_iter().collect() }
+    }
+
+    pub fn records(&self) -> Vec<(String, ChallengeRecord)> {
+        self.challenges.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect()
+    }
+
+    pub fn retired(&self) -> Vec<(String, (Retired, DateTime<Utc>))> {
+        self.retired.iter().map(|entry| (entry.key().clone(), *entry.value())).collect()
+    }
+}
+
+impl ChallengeStore for MemoryChallengeStore {
+    fn insert(&self, auth_id: &str, challenge: ChallengeRecord) -> Result<()> {
+        self.challenges.insert(auth_id.to_string(), challenge);
+        Ok(())
+    }
+
+    //the auth_id is retired while its shard is still locked, so a second answer sent at the
+    //same time finds it used and not missing
+    fn take(&self, auth_id: &str, now: DateTime<Utc>, ttl: Duration) -> Result<Taken> {
+        Ok(match self.challenges.entry(auth_id.to_string()) {
+            Entry::Occupied(open) => {
+                let reason = if open.get().is_open(now, ttl) { Retired::Used } else { Retired::Expired };
+                self.retired.insert(auth_id.to_string(), (reason, now));
+                let challenge = open.remove();
+                match reason {
+                    Retired::Used => Taken::Open(challenge),
+                    Retired::Expired => Taken::Retired(reason),
+                }
+            }
+            Entry::Vacant(_) => match self.retired.get(auth_id) {
+                Some(retired) => Taken::Retired(retired.0),
+                None => Taken::Unknown,
+            },
+        })
+    }
+
+    fn retire(&self, key: &str, now: DateTime<Utc>) -> Result<bool> {
+        Ok(match self.retired.entry(key.to_string()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(vacant) => {
+                vacant.insert((Retired::Used, now));
+                true
+            }
+        })
+    }
+
+    fn sweep(&self, now: DateTime<Utc>, ttl: Duration, retired_for: Duration) -> Result<usize> {
+        let mut evicted = 0;
+        self.challenges.retain(|auth_id, challenge| {
+            let open = challenge.is_open(now, ttl);
+            if !open {
+                self.retired.insert(auth_id.clone(), (Retired::Expired, now));
+                evicted += 1;
+            }
+            open
+        });
+        self.retired.retain(|_, (_, at)| now < *at + to_chrono(retired_for));
+        Ok(evicted)
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.challenges.len())
+    }
+
+    //a scan, the number of open challenges is capped so it stays short
+    fn pending(&self, did: &str, peer: Option<IpAddr>) -> Result<(usize, usize)> {
+        Ok(self.challenges.iter().filter(|challenge| challenge.did == did)
+            .fold((0, 0), |(from_peer, all), challenge| (from_peer + usize::from(challenge.peer == peer), all + 1)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::conformance;
+    use crate::store::MemorySessionStore;
+
+    #[test]
+    fn test_memory_store() {
+        conformance::users(&MemoryUserStore::default());
+        conformance::challenges(&MemoryChallengeStore::default());
+        conformance::sessions(&MemorySessionStore::new(conformance::policy()));
+    }
+}
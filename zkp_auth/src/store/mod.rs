@@ -0,0 +1,382 @@
+//Where the server keeps its state: the registered DIDs, the open challenges and the sessions.
+//Each has a trait here, and three backends implement all three of them:
+//  memory   sharded maps, gone after a restart (memory.rs)
+//  json     the same maps, written to one JSON file after every change, for a small server (json.rs)
+//  sqlite   one SQLite database, every change is a transaction of its own (sqlite.rs)
+//StoreConfig picks one, e.g. from the --store flag of the server.
+//
+//The records hold the encoded values and not group elements, a store does not know the groups.
+//What is in them was validated before it was stored.
+//A store method never calls another store, and the closures given to register and update
+//must not either: the file and database backends hold their one lock while the closure runs.
+
+pub mod json;
+pub mod memory;
+pub mod sqlite;
+
+use crate::error::{Result, ZkpAuthError};
+use crate::history::CommitmentHistory;
+use crate::proof::{base64_bytes, Challenge, Commitment};
+use crate::session::{to_chrono, Session, SessionPolicy};
+use crate::ssi::credential::VerifiableCredential;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use json::JsonFileStore;
+pub use memory::{MemoryChallengeStore, MemoryUserStore};
+pub use sqlite::SqliteStore;
+pub use crate::session::MemorySessionStore;
+
+//a registered DID
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub did: String,
+    //the parameter set the keys were registered in
+    pub group: String,
+    #[serde(with = "base64_bytes")]
+    pub y1: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    pub y2: Vec<u8>,
+    //the last accepting transcripts, to catch a reused nonce
+    pub history: CommitmentHistory,
+    //set once the secret is known to have leaked, no login is accepted after that
+    pub compromised: bool,
+    //None for a DID registered before enrollments were recorded
+    pub enrollment: Option<Enrollment>,
+}
+
+//how a DID enrolled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Enrollment {
+    pub enrolled_at: DateTime<Utc>,
+    //the credential the wallet presented, issued to the DID for its current keys
+    pub credential: VerifiableCredential,
+    //when the keys were last replaced by a key update
+    pub keys_updated_at: Option<DateTime<Utc>>,
+}
+
+//a challenge handed out by CreateChallenge, each one is its own record so two logins
+//of the same DID at the same time do not overwrite each other's commitment and challenge
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChallengeRecord {
+    pub did: String,
+    //the group of the DID when the challenge was made
+    pub group: String,
+    pub commitment: Commitment,
+    pub c: Challenge,
+    pub created_at: DateTime<Utc>,
+    //the address the challenge was asked from, None without one or for a challenge stored before
+    #[serde(default)]
+    pub peer: Option<IpAddr>,
+}
+
+impl ChallengeRecord {
+    //a challenge can be answered for ttl after it was made
+    pub fn is_open(&self, now: DateTime<Utc>, ttl: Duration) -> bool {
+        now < self.created_at + to_chrono(ttl)
+    }
+}
+
+//why an auth_id can no longer be answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Retired {
+    //it was answered once, right or wrong, every auth_id is single use
+    Used,
+    //it was not answered within the challenge TTL
+    Expired,
+}
+
+//what ChallengeStore::take found under an auth_id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Taken {
+    //the challenge, it can be answered now and never again
+    Open(ChallengeRecord),
+    //the auth_id was used or ran out of time, just now or before
+    Retired(Retired),
+    //the auth_id was never handed out, or retired so long ago that it is forgotten
+    Unknown,
+}
+
+pub trait UserStore: Send + Sync {
+    fn get(&self, did: &str) -> Result<Option<UserRecord>>;
+
+    //registration as one step: `register` gets the DID's record, None for a new DID,
+    //and returns the record to store; nothing is written if it fails
+    fn register(&self, did: &str, register: &mut dyn FnMut(Option<&UserRecord>) -> Result<UserRecord>) -> Result<()>;
+
+    //changes the DID's record as one step and returns what `update` returned
+    //the record is written back even if `update` fails, so a DID marked compromised on the
+    //way to an error stays marked
+    fn update(&self, did: &str, update: &mut dyn FnMut(&mut UserRecord) -> Result<()>) -> Result<()>;
+
+    fn len(&self) -> Result<usize>;
+
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+pub trait ChallengeStore: Send + Sync {
+    fn insert(&self, auth_id: &str, challenge: ChallengeRecord) -> Result<()>;
+
+    //takes the challenge out for its one answer and retires the auth_id in the same step,
+    //as expired if the challenge is older than ttl
+    fn take(&self, auth_id: &str, now: DateTime<Utc>, ttl: Duration) -> Result<Taken>;
+
+    //retires a key that never was an auth_id, e.g. the context of a login that succeeded
+    //returns false if it was retired already, it is forgotten like a retired auth_id
+    fn retire(&self, key: &str, now: DateTime<Utc>) -> Result<bool>;
+
+    //evicts the challenges older than ttl and forgets the auth_ids retired more than
+    //retired_for ago, returns how many challenges were evicted
+    fn sweep(&self, now: DateTime<Utc>, ttl: Duration, retired_for: Duration) -> Result<usize>;
+
+    //the open challenges
+    fn len(&self) -> Result<usize>;
+
+    //the open challenges of one DID asked from peer, and from anywhere
+    //expired ones count until they are swept
+    fn pending(&self, did: &str, peer: Option<IpAddr>) -> Result<(usize, usize)>;
+
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+//see MemorySessionStore for what each of these does
+pub trait SessionStore: Send + Sync {
+    fn policy(&self) -> &SessionPolicy;
+
+    //starts a session, returns it and the sessions ended to stay within the per-DID limit
+    fn create(&self, did: &str, id: String, now: DateTime<Utc>) -> Result<(Session, Vec<Session>)>;
+
+    fn validate(&self, id: &str, now: DateTime<Utc>) -> Result<Session>;
+
+    fn refresh(&self, id: &str, now: DateTime<Utc>) -> Result<Session>;
+
+    fn revoke(&self, id: &str) -> Result<Session>;
+
+    fn revoke_all(&self, did: &str) -> Result<usize>;
+
+    fn list(&self, did: &str, now: DateTime<Utc>) -> Result<Vec<Session>>;
+
+    fn sweep(&self, now: DateTime<Utc>) -> Result<usize>;
+
+    fn len(&self) -> Result<usize>;
+
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+//the three stores a server runs with
+#[derive(Clone)]
+pub struct Stores {
+    pub users: Arc<dyn UserStore>,
+    pub challenges: Arc<dyn ChallengeStore>,
+    pub sessions: Arc<dyn SessionStore>,
+}
+
+impl Stores {
+    pub fn memory(policy: SessionPolicy) -> Self {
+        Self {
+            users: Arc::new(MemoryUserStore::default()),
+            challenges: Arc::new(MemoryChallengeStore::default()),
+            sessions: Arc::new(MemorySessionStore::new(policy)),
+        }
+    }
+
+    //one backend that keeps all three
+    pub fn shared<S: UserStore + ChallengeStore + SessionStore + 'static>(store: S) -> Self {
+        let store = Arc::new(store);
+        Self { users: store.clone(), challenges: store.clone(), sessions: store }
+    }
+}
+
+//which backend to use, written as memory, json:<path> or sqlite:<path>
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StoreConfig {
+    #[default]
+    Memory,
+    Json(PathBuf),
+    Sqlite(PathBuf),
+}
+
+impl StoreConfig {
+    //creates the file or database if it is not there and migrates it if it is older
+    pub fn open(&self, policy: SessionPolicy) -> Result<Stores> {
+        Ok(match self {
+            StoreConfig::Memory => Stores::memory(policy),
+            StoreConfig::Json(path) => Stores::shared(JsonFileStore::open(path, policy)?),
+            StoreConfig::Sqlite(path) => Stores::shared(SqliteStore::open(path, policy)?),
+        })
+    }
+}
+
+impl FromStr for StoreConfig {
+    type Err = ZkpAuthError;
+
+    fn from_str(config: &str) -> Result<Self> {
+        match config.split_once(':') {
+            None if config == "memory" => Ok(StoreConfig::Memory),
+            Some(("json", path)) if !path.is_empty() => Ok(StoreConfig::Json(PathBuf::from(path))),
+            Some(("sqlite", path)) if !path.is_empty() => Ok(StoreConfig::Sqlite(PathBuf::from(path))),
+            _ => Err(ZkpAuthError::Encoding(format!("a store is memory, json:<path> or sqlite:<path>, not {}", config))),
+        }
+    }
+}
+
+impl std::fmt::Display for StoreConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreConfig::Memory => write!(f, "memory"),
+            StoreConfig::Json(path) => write!(f, "json:{}", path.display()),
+            StoreConfig::Sqlite(path) => write!(f, "sqlite:{}", path.display()),
+        }
+    }
+}
+
+pub(crate) fn unknown_did(did: &str) -> ZkpAuthError {
+    ZkpAuthError::NotFound(format!("DID: {} not found in database", did))
+}
+
+//the checks every backend has to pass, each backend's tests run them
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+    use chrono::SubsecRound;
+    use crate::proof::{Challenge, Commitment};
+    use crate::{Group, ZKP};
+
+    pub fn user(did: &str) -> UserRecord {
+        UserRecord { did: did.to_string(), group: "rfc5114-1024-160".to_string(), y1: vec![1; 4], y2: vec![2; 4], history: CommitmentHistory::default(), compromised: false, enrollment: None }
+    }
+
+    pub fn challenge(did: &str, created_at: DateTime<Utc>) -> ChallengeRecord {
+        let zkp = ZKP::new(ZKP::default_group().unwrap());
+        let (r1, r2) = zkp.exponentiate_pair(&zkp.group.random_scalar());
+        ChallengeRecord {
+            did: did.to_string(),
+            group: "rfc5114-1024-160".to_string(),
+            commitment: Commitment::new(&zkp, &r1, &r2),
+            c: Challenge::new(&zkp, &zkp.group.random_scalar()),
+            created_at,
+            peer: None,
+        }
+    }
+
+    pub fn after(now: DateTime<Utc>, seconds: i64) -> DateTime<Utc> {
+        now + chrono::Duration::seconds(seconds)
+    }
+
+    pub fn policy() -> SessionPolicy {
+        SessionPolicy { idle_timeout: Duration::from_secs(60), absolute_timeout: Duration::from_secs(300), max_per_did: 2 }
+    }
+
+    pub fn users(store: &dyn UserStore) {
+        assert_eq!(store.get("did:zkp:a").unwrap(), None);
+        assert!(matches!(store.update("did:zkp:a", &mut |_| Ok(())), Err(ZkpAuthError::NotFound(_))));
+        store.register("did:zkp:a", &mut |existing| {
+            assert!(existing.is_none());
+            Ok(user("did:zkp:a"))
+        }).unwrap();
+
+        //a failed registration writes nothing
+        let refused = store.register("did:zkp:a", &mut |existing| {
+            assert_eq!(existing.unwrap().y1, vec![1; 4]);
+            Err(ZkpAuthError::Protocol("no".to_string()))
+        });
+        assert!(matches!(refused, Err(ZkpAuthError::Protocol(_))));
+        assert_eq!(store.get("did:zkp:a").unwrap(), Some(user("did:zkp:a")));
+
+        //the enrollment is kept with the credential as it was presented
+        let (credential, _) = crate::ssi::issuer::Issuer::issue_credential("b", &[1; 4], &[2; 4]).unwrap();
+        let enrolled = UserRecord {
+            enrollment: Some(Enrollment { enrolled_at: Utc::now(), credential, keys_updated_at: None }),
+            ..user("did:zkp:b")
+        };
+        store.register("did:zkp:b", &mut |_| Ok(enrolled.clone())).unwrap();
+        assert_eq!(store.get("did:zkp:b").unwrap(), Some(enrolled));
+
+        //a failed update still writes what it changed
+        let marked = store.update("did:zkp:a", &mut |user| {
+            user.compromised = true;
+            Err(ZkpAuthError::Crypto("leaked".to_string()))
+        });
+        assert!(matches!(marked, Err(ZkpAuthError::Crypto(_))));
+        assert!(store.get("did:zkp:a").unwrap().unwrap().compromised);
+        assert_eq!(store.len().unwrap(), 2);
+    }
+
+    pub fn challenges(store: &dyn ChallengeStore) {
+        let (ttl, retired_for) = (Duration::from_secs(60), Duration::from_secs(600));
+        //whole milliseconds, what every backend keeps
+        let now = Utc::now().trunc_subsecs(3);
+        let open = challenge("did:zkp:a", now);
+        store.insert("one", open.clone()).unwrap();
+        store.insert("two", challenge("did:zkp:a", now)).unwrap();
+        let peer = Some(IpAddr::from([10, 0, 0, 1]));
+        store.insert("three", ChallengeRecord { peer, ..challenge("did:zkp:a", now) }).unwrap();
+        store.insert("four", challenge("did:zkp:b", now)).unwrap();
+        assert_eq!(store.len().unwrap(), 4);
+        assert_eq!((store.pending("did:zkp:a", None).unwrap(), store.pending("did:zkp:b", None).unwrap(), store.pending("did:zkp:c", None).unwrap()), ((2, 3), (1, 1), (0, 0)));
+        //counted for the address it was asked from
+        assert_eq!((store.pending("did:zkp:a", peer).unwrap(), store.pending("did:zkp:b", peer).unwrap()), ((1, 3), (0, 1)));
+        assert!(matches!(store.take("four", now, ttl).unwrap(), Taken::Open(_)));
+
+        //single use
+        assert_eq!(store.take("one", after(now, 59), ttl).unwrap(), Taken::Open(open));
+        assert_eq!(store.take("one", after(now, 59), ttl).unwrap(), Taken::Retired(Retired::Used));
+        assert_eq!(store.take("two", after(now, 60), ttl).unwrap(), Taken::Retired(Retired::Expired));
+        assert_eq!(store.take("two", after(now, 60), ttl).unwrap(), Taken::Retired(Retired::Expired));
+        assert_eq!(store.take("missing", now, ttl).unwrap(), Taken::Unknown);
+
+        //a key is retired once
+        assert!(store.retire("login:a", now).unwrap());
+        assert!(!store.retire("login:a", after(now, 1)).unwrap());
+
+        assert_eq!(store.sweep(after(now, 59), ttl, retired_for).unwrap(), 0);
+        assert_eq!(store.pending("did:zkp:a", peer).unwrap(), (1, 1));
+        assert_eq!(store.sweep(after(now, 60), ttl, retired_for).unwrap(), 1);
+        assert_eq!((store.len().unwrap(), store.pending("did:zkp:a", peer).unwrap()), (0, (0, 0)));
+        assert_eq!(store.take("three", after(now, 61), ttl).unwrap(), Taken::Retired(Retired::Expired));
+        //retired auth_ids are forgotten after a while
+        store.sweep(after(now, 660), ttl, retired_for).unwrap();
+        assert_eq!(store.take("one", after(now, 660), ttl).unwrap(), Taken::Unknown);
+        assert_eq!(store.take("three", after(now, 660), ttl).unwrap(), Taken::Unknown);
+    }
+
+    pub fn sessions(store: &dyn SessionStore) {
+        let now = Utc::now();
+        store.create("did:zkp:a", "one".to_string(), now).unwrap();
+        store.create("did:zkp:a", "two".to_string(), after(now, 1)).unwrap();
+        store.create("did:zkp:b", "other".to_string(), now).unwrap();
+        let (created, ended) = store.create("did:zkp:a", "three".to_string(), after(now, 2)).unwrap();
+        assert_eq!(created.did, "did:zkp:a");
+        assert_eq!(ended.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(), ["one"]);
+        let ids = |did| store.list(did, after(now, 2)).unwrap().into_iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids("did:zkp:a"), ["two", "three"]);
+
+        assert!(store.refresh("two", after(now, 50)).is_ok());
+        assert_eq!(store.validate("two", after(now, 100)).unwrap().did, "did:zkp:a");
+        assert!(matches!(store.validate("three", after(now, 62)), Err(ZkpAuthError::Expired(_))));
+        assert!(matches!(store.validate("three", after(now, 62)), Err(ZkpAuthError::NotFound(_))));
+
+        assert_eq!(store.revoke("other").unwrap().did, "did:zkp:b");
+        assert!(matches!(store.revoke("other"), Err(ZkpAuthError::NotFound(_))));
+        assert_eq!(store.sweep(after(now, 109)).unwrap(), 0);
+        assert_eq!(store.sweep(after(now, 110)).unwrap(), 1);
+        assert_eq!(store.len().unwrap(), 0);
+
+        store.create("did:zkp:a", "four".to_string(), now).unwrap();
+        store.create("did:zkp:a", "five".to_string(), now).unwrap();
+        assert_eq!(store.revoke_all("did:zkp:a").unwrap(), 2);
+        assert_eq!(store.len().unwrap(), 0);
+    }
+}
@@ -0,0 +1,461 @@
+//The SQLite backend: one database for the users, the challenges and the sessions.
+//Every change is a transaction of its own, taken with BEGIN IMMEDIATE so two servers on the
+//same file queue up instead of failing half way. The one connection is behind a mutex, the
+//calls are short and the handlers do not hold it across an .await.
+//Times are unix milliseconds, the history and the proof values are stored as their JSON.
+//
+//The schema version is kept in PRAGMA user_version. Opening a database applies the entries
+//of MIGRATIONS it is missing in one transaction, entry n takes it from version n to n + 1.
+
+use super::{unknown_did, ChallengeRecord, ChallengeStore, Retired, SessionStore, Taken, UserRecord, UserStore};
+use crate::error::{Result, ZkpAuthError};
+use crate::session::{Session, SessionPolicy};
+use chrono::{DateTime, SubsecRound, Utc};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction, TransactionBehavior};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+const MIGRATIONS: &[&str] = &[
+    //1: users, open challenges, retired auth_ids and sessions
+    "CREATE TABLE users (
+        did TEXT PRIMARY KEY,
+        grp TEXT NOT NULL,
+        y1 BLOB NOT NULL,
+        y2 BLOB NOT NULL,
+        history TEXT NOT NULL,
+        compromised INTEGER NOT NULL
+    );
+    CREATE TABLE challenges (
+        auth_id TEXT PRIMARY KEY,
+        did TEXT NOT NULL,
+        grp TEXT NOT NULL,
+        commitment TEXT NOT NULL,
+        c TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE retired (
+        auth_id TEXT PRIMARY KEY,
+        reason TEXT NOT NULL,
+        at INTEGER NOT NULL
+    );
+    CREATE TABLE sessions (
+        id TEXT PRIMARY KEY,
+        did TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL
+    );
+    CREATE INDEX sessions_by_did ON sessions (did, created_at);",
+    //2: the enrollment of each DID, NULL for the ones registered before
+    "ALTER TABLE users ADD COLUMN enrollment TEXT;",
+    //3: the open challenges of a DID are counted for every new one
+    "CREATE INDEX challenges_by_did ON challenges (did);",
+    //4: the address a challenge was asked from, its open challenges are capped too
+    "ALTER TABLE challenges ADD COLUMN peer TEXT;",
+];
+
+//the version this server writes
+pub const VERSION: usize = MIGRATIONS.len();
+
+//how long a call waits for another server that holds the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+    policy: SessionPolicy,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path, policy: SessionPolicy) -> Result<Self> {
+        let mut connection = Connection::open(path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        migrate(&mut connection)?;
+        Ok(Self { connection: Mutex::new(connection), policy })
+    }
+
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.connection.lock().map_err(|_| ZkpAuthError::Storage("the database connection is unavailable".to_string()))
+    }
+
+    //runs `change` in a transaction, it is committed if `change` returns Ok
+    fn transaction<T>(&self, change: impl FnOnce(&Transaction) -> Result<T>) -> Result<T> {
+        let mut connection = self.connection()?;
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let result = change(&transaction)?;
+        transaction.commit()?;
+        Ok(result)
+    }
+}
+
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version: usize = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > VERSION {
+        return Err(ZkpAuthError::Storage(format!("the database is version {}, this server reads up to {}", version, VERSION)));
+    }
+    for migration in &MIGRATIONS[version..] {
+        transaction.execute_batch(migration)?;
+    }
+    transaction.pragma_update(None, "user_version", VERSION)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+fn millis(time: DateTime<Utc>) -> i64 {
+    time.timestamp_millis()
+}
+
+fn duration_millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
+fn time(row: &Row, index: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let millis: i64 = row.get(index)?;
+    DateTime::from_timestamp_millis(millis).ok_or(rusqlite::Error::IntegralValueOutOfRange(index, millis))
+}
+
+fn address(row: &Row, index: usize) -> rusqlite::Result<Option<IpAddr>> {
+    let text: Option<String> = row.get(index)?;
+    text.map(|text| text.parse().map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))).transpose()
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text).map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+fn user(row: &Row) -> rusqlite::Result<UserRecord> {
+    Ok(UserRecord {
+        did: row.get(0)?,
+        group: row.get(1)?,
+        y1: row.get(2)?,
+        y2: row.get(3)?,
+        history: from_json(row, 4)?,
+        compromised: row.get(5)?,
+        enrollment: row.get::<_, Option<String>>(6)?.map(|_| from_json(row, 6)).transpose()?,
+    })
+}
+
+fn get_user(connection: &Connection, did: &str) -> Result<Option<UserRecord>> {
+    Ok(connection.query_row("SELECT did, grp, y1, y2, history, compromised, enrollment FROM users WHERE did = ?1", [did], user).optional()?)
+}
+
+fn put_user(transaction: &Transaction, user: &UserRecord) -> Result<()> {
+    transaction.execute(
+        "INSERT OR REPLACE INTO users (did, grp, y1, y2, history, compromised, enrollment) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![user.did, user.group, user.y1, user.y2, serde_json::to_string(&user.history)?, user.compromised, user.enrollment.as_ref().map(serde_json::to_string).transpose()?],
+    )?;
+    Ok(())
+}
+
+impl UserStore for SqliteStore {
+    fn get(&self, did: &str) -> Result<Option<UserRecord>> {
+        get_user(&*self.connection()?, did)
+    }
+
+    fn register(&self, did: &str, register: &mut dyn FnMut(Option<&UserRecord>) -> Result<UserRecord>) -> Result<()> {
+        self.transaction(|transaction| {
+            let existing = get_user(transaction, did)?;
+            put_user(transaction, &register(existing.as_ref())?)
+        })
+    }
+
+    fn update(&self, did: &str, update: &mut dyn FnMut(&mut UserRecord) -> Result<()>) -> Result<()> {
+        self.transaction(|transaction| {
+            let mut user = get_user(transaction, did)?.ok_or_else(|| unknown_did(did))?;
+            let before = user.clone();
+            let result = update(&mut user);
+            if user != before {
+                put_user(transaction, &user)?;
+            }
+            Ok(result)
+        })?
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.connection()?.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?)
+    }
+}
+
+fn reason(retired: Retired) -> &'static str {
+    match retired {
+        Retired::Used => "used",
+        Retired::Expired => "expired",
+    }
+}
+
+impl ChallengeStore for SqliteStore {
+    fn insert(&self, auth_id: &str, challenge: ChallengeRecord) -> Result<()> {
+        self.connection()?.execute(
+            "INSERT INTO challenges (auth_id, did, grp, commitment, c, created_at, peer) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![auth_id, challenge.did, challenge.group, serde_json::to_string(&challenge.commitment)?, serde_json::to_string(&challenge.c)?, millis(challenge.created_at),
+                challenge.peer.map(|peer| peer.to_string())],
+        )?;
+        Ok(())
+    }
+
+    fn take(&self, auth_id: &str, now: DateTime<Utc>, ttl: Duration) -> Result<Taken> {
+        self.transaction(|transaction| {
+            let challenge = transaction.query_row(
+                "SELECT did, grp, commitment, c, created_at, peer FROM challenges WHERE auth_id = ?1",
+                [auth_id],
+                |row| Ok(ChallengeRecord { did: row.get(0)?, group: row.get(1)?, commitment: from_json(row, 2)?, c: from_json(row, 3)?, created_at: time(row, 4)?, peer: address(row, 5)? }),
+            ).optional()?;
+
+            let Some(challenge) = challenge else {
+                let retired: Option<String> = transaction.query_row("SELECT reason FROM retired WHERE auth_id = ?1", [auth_id], |row| row.get(0)).optional()?;
+                return Ok(match retired.as_deref() {
+                    Some("used") => Taken::Retired(Retired::Used),
+                    Some(_) => Taken::Retired(Retired::Expired),
+                    None => Taken::Unknown,
+                });
+            };
+            let retired = if challenge.is_open(now, ttl) { Retired::Used } else { Retired::Expired };
+            transaction.execute("DELETE FROM challenges WHERE auth_id = ?1", [auth_id])?;
+            transaction.execute("INSERT OR REPLACE INTO retired (auth_id, reason, at) VALUES (?1, ?2, ?3)", params![auth_id, reason(retired), millis(now)])?;
+            Ok(match retired {
+                Retired::Used => Taken::Open(challenge),
+                Retired::Expired => Taken::Retired(retired),
+            })
+        })
+    }
+
+    fn retire(&self, key: &str, now: DateTime<Utc>) -> Result<bool> {
+        let inserted = self.connection()?.execute(
+            "INSERT OR IGNORE INTO retired (auth_id, reason, at) VALUES (?1, ?2, ?3)",
+            params![key, reason(Retired::Used), millis(now)],
+        )?;
+        Ok(inserted == 1)
+    }
+
+    fn sweep(&self, now: DateTime<Utc>, ttl: Duration, retired_for: Duration) -> Result<usize> {
+        let now = millis(now);
+        let expired_before = now.saturating_sub(duration_millis(ttl));
+        self.transaction(|transaction| {
+            transaction.execute(
+                "INSERT OR REPLACE INTO retired (auth_id, reason, at) SELECT auth_id, ?1, ?2 FROM challenges WHERE created_at <= ?3",
+                params![reason(Retired::Expired), now, expired_before],
+            )?;
+            let evicted = transaction.execute("DELETE FROM challenges WHERE created_at <= ?1", [expired_before])?;
+            transaction.execute("DELETE FROM retired WHERE at <= ?1", [now.saturating_sub(duration_millis(retired_for))])?;
+            Ok(evicted)
+        })
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.connection()?.query_row("SELECT COUNT(*) FROM challenges", [], |row| row.get(0))?)
+    }
+
+    fn pending(&self, did: &str, peer: Option<IpAddr>) -> Result<(usize, usize)> {
+        Ok(self.connection()?.query_row(
+            "SELECT COUNT(*) FILTER (WHERE peer IS ?2), COUNT(*) FROM challenges WHERE did = ?1",
+            params![did, peer.map(|peer| peer.to_string())],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?)
+    }
+}
+
+fn session(row: &Row) -> rusqlite::Result<Session> {
+    Ok(Session { id: row.get(0)?, did: row.get(1)?, created_at: time(row, 2)?, last_seen: time(row, 3)? })
+}
+
+fn get_session(connection: &Connection, id: &str) -> Result<Option<Session>> {
+    Ok(connection.query_row("SELECT id, did, created_at, last_seen FROM sessions WHERE id = ?1", [id], session).optional()?)
+}
+
+//a DID's sessions, oldest first
+fn sessions_of(connection: &Connection, did: &str) -> Result<Vec<Session>> {
+    let mut statement = connection.prepare("SELECT id, did, created_at, last_seen FROM sessions WHERE did = ?1 ORDER BY created_at, rowid")?;
+    let sessions = statement.query_map([did], session)?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(sessions)
+}
+
+fn delete_session(connection: &Connection, id: &str) -> Result<()> {
+    connection.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+fn not_found(id: &str) -> ZkpAuthError {
+    ZkpAuthError::NotFound(format!("Session: {} not found", id))
+}
+
+fn expired(id: &str) -> ZkpAuthError {
+    ZkpAuthError::Expired(format!("Session: {} expired, log in again", id))
+}
+
+impl SessionStore for SqliteStore {
+    fn policy(&self) -> &SessionPolicy {
+        &self.policy
+    }
+
+    fn create(&self, did: &str, id: String, now: DateTime<Utc>) -> Result<(Session, Vec<Session>)> {
+        //what the database keeps, so the session handed out is the one read back later
+        let now = now.trunc_subsecs(3);
+        let session = Session { id, did: did.to_string(), created_at: now, last_seen: now };
+        self.transaction(|transaction| {
+            let mut live = Vec::new();
+            for old in sessions_of(transaction, did)? {
+                if old.is_live(&self.policy, now) {
+                    live.push(old);
+                } else {
+                    delete_session(transaction, &old.id)?;
+                }
+            }
+            let over = (live.len() + 1).saturating_sub(self.policy.max_per_did.max(1));
+            let ended: Vec<_> = live.into_iter().take(over).collect();
+            for old in &ended {
+                delete_session(transaction, &old.id)?;
+            }
+            transaction.execute(
+                "INSERT INTO sessions (id, did, created_at, last_seen) VALUES (?1, ?2, ?3, ?4)",
+                params![session.id, session.did, millis(now), millis(now)],
+            )?;
+            Ok((session.clone(), ended))
+        })
+    }
+
+    fn validate(&self, id: &str, now: DateTime<Utc>) -> Result<Session> {
+        let connection = self.connection()?;
+        let session = get_session(&connection, id)?.ok_or_else(|| not_found(id))?;
+        if !session.is_live(&self.policy, now) {
+            delete_session(&connection, id)?;
+            return Err(expired(id));
+        }
+        Ok(session)
+    }
+
+    fn refresh(&self, id: &str, now: DateTime<Utc>) -> Result<Session> {
+        let now = now.trunc_subsecs(3);
+        self.transaction(|transaction| {
+            let session = get_session(transaction, id)?.ok_or_else(|| not_found(id))?;
+            if !session.is_live(&self.policy, now) {
+                delete_session(transaction, id)?;
+                return Ok(Err(expired(id)));
+            }
+            transaction.execute("UPDATE sessions SET last_seen = ?1 WHERE id = ?2", params![millis(now), id])?;
+            Ok(Ok(Session { last_seen: now, ..session }))
+        })?
+    }
+
+    fn revoke(&self, id: &str) -> Result<Session> {
+        self.transaction(|transaction| {
+            let session = get_session(transaction, id)?.ok_or_else(|| not_found(id))?;
+            delete_session(transaction, id)?;
+            Ok(session)
+        })
+    }
+
+    fn revoke_all(&self, did: &str) -> Result<usize> {
+        Ok(self.connection()?.execute("DELETE FROM sessions WHERE did = ?1", [did])?)
+    }
+
+    fn list(&self, did: &str, now: DateTime<Utc>) -> Result<Vec<Session>> {
+        let sessions = sessions_of(&*self.connection()?, did)?;
+        Ok(sessions.into_iter().filter(|session| session.is_live(&self.policy, now)).collect())
+    }
+
+    fn sweep(&self, now: DateTime<Utc>) -> Result<usize> {
+        let now = millis(now);
+        Ok(self.connection()?.execute(
+            "DELETE FROM sessions WHERE last_seen <= ?1 OR created_at <= ?2",
+            params![now.saturating_sub(duration_millis(self.policy.idle_timeout)), now.saturating_sub(duration_millis(self.policy.absolute_timeout))],
+        )?)
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.connection()?.query_row("SELECT COUNT(*) FROM sessions", [], |row| row.get(0))?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::conformance;
+    use std::path::PathBuf;
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("zkp_auth_{}_{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_sqlite_store() {
+        let store = SqliteStore::open(Path::new(":memory:"), conformance::policy()).unwrap();
+        conformance::users(&store);
+        conformance::challenges(&store);
+        conformance::sessions(&store);
+    }
+
+    #[test]
+    fn test_sqlite_store_survives_a_restart() {
+        let path = path("restart");
+        let now = Utc::now();
+        {
+            let store = SqliteStore::open(&path, conformance::policy()).unwrap();
+            store.register("did:zkp:a", &mut |_| Ok(conformance::user("did:zkp:a"))).unwrap();
+            store.insert("auth", conformance::challenge("did:zkp:a", now)).unwrap();
+            store.create("did:zkp:a", "one".to_string(), now).unwrap();
+        }
+
+        let store = SqliteStore::open(&path, conformance::policy()).unwrap();
+        assert_eq!(store.get("did:zkp:a").unwrap(), Some(conformance::user("did:zkp:a")));
+        let Taken::Open(challenge) = store.take("auth", now, Duration::from_secs(60)).unwrap() else {
+            panic!("the challenge was lost");
+        };
+        assert_eq!(challenge.created_at, DateTime::from_timestamp_millis(millis(now)).unwrap());
+        assert_eq!(store.validate("one", now).unwrap().did, "did:zkp:a");
+        drop(store);
+
+        let store = SqliteStore::open(&path, conformance::policy()).unwrap();
+        assert_eq!(store.take("auth", now, Duration::from_secs(60)).unwrap(), Taken::Retired(Retired::Used));
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_sqlite_migrations() {
+        let path = path("migrations");
+        SqliteStore::open(&path, conformance::policy()).unwrap();
+        let connection = Connection::open(&path).unwrap();
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, VERSION);
+
+        //opening again applies nothing twice
+        SqliteStore::open(&path, conformance::policy()).unwrap();
+
+        //a database from a newer server is left alone
+        connection.pragma_update(None, "user_version", VERSION + 1).unwrap();
+        assert!(matches!(SqliteStore::open(&path, conformance::policy()), Err(ZkpAuthError::Storage(_))));
+        drop(connection);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_sqlite_keeps_users_of_version_1() {
+        let path = path("version1");
+        let connection = Connection::open(&path).unwrap();
+        connection.execute_batch(MIGRATIONS[0]).unwrap();
+        connection.pragma_update(None, "user_version", 1).unwrap();
+        let history = serde_json::to_string(&conformance::user("did:zkp:a").history).unwrap();
+        connection.execute("INSERT INTO users VALUES ('did:zkp:a', 'rfc5114-1024-160', ?1, ?2, ?3, 0)", params![vec![1u8; 4], vec![2u8; 4], history]).unwrap();
+        drop(connection);
+
+        //it was registered before enrollments were recorded
+        let store = SqliteStore::open(&path, conformance::policy()).unwrap();
+        assert_eq!(store.get("did:zkp:a").unwrap(), Some(conformance::user("did:zkp:a")));
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_registration_is_rolled_back() {
+        let store = SqliteStore::open(Path::new(":memory:"), conformance::policy()).unwrap();
+        let failed = store.register("did:zkp:a", &mut |_| Err(ZkpAuthError::Protocol("no".to_string())));
+        assert!(failed.is_err());
+        assert_eq!(UserStore::len(&store).unwrap(), 0);
+    }
+}
@@ -0,0 +1,253 @@
+//Logging for the server, the client and the wallet, all through tracing.
+//RUST_LOG picks what is logged, as an EnvFilter (e.g. "info" or "zkp_auth=debug,tower=warn"),
+//each binary gives its own default. ZKP_AUTH_LOG_FORMAT=json (or the server's --log-json)
+//writes one JSON object per line instead, for a log pipeline.
+//
+//Nothing that names a user or grants access is logged as it is: DIDs, session ids and auth_ids
+//go in as a keyed hash ("did#3f9a0c21b7de"), the same value always gives the same hash so the
+//lines of one DID can be followed without the DID. ZKP_AUTH_LOG_KEY is the key, servers whose
+//logs are read together share it; without it the hash is a plain one and a known DID can be
+//found in the logs. Keys, proofs, tokens and request bodies are never logged.
+//
+//Every RPC runs in a span with the method and a request id, the x-request-id a client sent or a
+//new one, which goes back in the response. The client sends the same one for every call of a
+//login (RequestId), so its lines and the server's share it. The two calls of an interactive login also
+//share the hash of their auth_id in the span's login field.
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tonic::codegen::BoxFuture;
+use tonic::codegen::http::{self, HeaderValue};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
+use tower::{Layer, Service};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const FORMAT_ENV: &str = "ZKP_AUTH_LOG_FORMAT";
+pub const KEY_ENV: &str = "ZKP_AUTH_LOG_KEY";
+//bytes of a hash in the logs, enough to tell the users of one server apart
+const HASH_LEN: usize = 6;
+//a request id a client sends is used if it is this short and plain
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+static LOG_KEY: OnceLock<Vec<u8>> = OnceLock::new();
+
+//sets up the subscriber once, a later call changes nothing
+//default is the filter used without RUST_LOG, json also comes from ZKP_AUTH_LOG_FORMAT
+pub fn init(default: &str, json: bool) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default));
+    let json = json || std::env::var(FORMAT_ENV).is_ok_and(|format| format.eq_ignore_ascii_case("json"));
+    //colours only on a terminal, not in a file the output is sent to
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter).with_target(true).with_ansi(std::io::stdout().is_terminal());
+    let _ = if json {
+        subscriber.json().flatten_event(true).with_current_span(true).with_span_list(false).try_init()
+    } else {
+        subscriber.compact().try_init()
+    };
+}
+
+fn key() -> &'static [u8] {
+    LOG_KEY.get_or_init(|| std::env::var(KEY_ENV).unwrap_or_default().into_bytes())
+}
+
+//what a value of this kind is logged as
+pub fn redact(kind: &str, value: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key()).expect("HMAC takes keys of any length");
+    mac.update(kind.as_bytes());
+    mac.update(&[0]);
+    mac.update(value.as_bytes());
+    format!("{}#{}", kind, hex::encode(&mac.finalize().into_bytes()[..HASH_LEN]))
+}
+
+pub fn did(did: &str) -> String {
+    redact("did", did)
+}
+
+pub fn session(id: &str) -> String {
+    redact("session", id)
+}
+
+pub fn auth_id(auth_id: &str) -> String {
+    redact("login", auth_id)
+}
+
+//puts the hashed DID on the RPC span the handler runs in
+pub fn record_did(value: &str) {
+    tracing::Span::current().record("did", did(value).as_str());
+}
+
+//puts the hashed auth_id on the RPC span, it is the same in both calls of an interactive login
+pub fn record_login(value: &str) {
+    tracing::Span::current().record("login", auth_id(value).as_str());
+}
+
+pub fn new_request_id() -> String {
+    let mut id = [0u8; 8];
+    OsRng.fill_bytes(&mut id);
+    hex::encode(id)
+}
+
+//a request id from a client goes into every log line, so only a short plain one is taken
+pub fn is_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+//puts the same request id on every call of a client, e.g. AuthClient::with_interceptor(channel, RequestId::new())
+#[derive(Debug, Clone)]
+pub struct RequestId(MetadataValue<Ascii>);
+
+impl RequestId {
+    pub fn new() -> Self {
+        Self(new_request_id().parse().expect("a hex id is valid metadata"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.to_str().unwrap_or_default()
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interceptor for RequestId {
+    fn call(&mut self, mut request: tonic::Request<()>) -> std::result::Result<tonic::Request<()>, tonic::Status> {
+        request.metadata_mut().insert(REQUEST_ID_HEADER, self.0.clone());
+        Ok(request)
+    }
+}
+
+//a span per RPC, e.g. Server::builder().layer(RpcTraceLayer).layer(PeerLimitLayer::new(limiter))
+//it is the outer layer so a request the limits refuse is logged too
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RpcTraceLayer;
+
+impl<S> Layer<S> for RpcTraceLayer {
+    type Service = RpcTrace<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcTrace { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcTrace<S> {
+    inner: S,
+}
+
+impl<S, B, R> Service<http::Request<B>> for RpcTrace<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<R>>,
+    S::Future: Send + 'static,
+    S::Error: std::fmt::Display,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let rpc = request.uri().path().rsplit('/').next().unwrap_or_default().to_string();
+        let request_id = match request.headers().get(REQUEST_ID_HEADER).and_then(|id| id.to_str().ok()) {
+            Some(id) if is_request_id(id) => id.to_string(),
+            _ => new_request_id(),
+        };
+        let span = tracing::info_span!("rpc", rpc = %rpc, request_id = %request_id, did = tracing::field::Empty, login = tracing::field::Empty);
+        let future = span.in_scope(|| self.inner.call(request));
+        let started = Instant::now();
+        Box::pin(async move {
+            let mut response = match future.await {
+                Ok(response) => response,
+                Err(e) => {
+                    tracing::error!(error = %e, "rpc failed");
+                    return Err(e);
+                }
+            };
+            //a unary call that failed has its status in the headers, one that succeeded in the trailers
+            //the message is not logged, it names the DID
+            let code = response.headers().get("grpc-status")
+                .and_then(|code| code.to_str().ok()?.parse::<i32>().ok())
+                .map_or(tonic::Code::Ok, tonic::Code::from);
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            match code {
+                tonic::Code::Ok => tracing::info!(elapsed_ms, "rpc finished"),
+                tonic::Code::Internal | tonic::Code::Unknown => tracing::error!(code = ?code, elapsed_ms, "rpc failed"),
+                _ => tracing::warn!(code = ?code, elapsed_ms, "rpc refused"),
+            }
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                response.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            Ok(response)
+        }.instrument(span))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_redacted_values() {
+        let did = "did:zkp:dc42f52a472aaf0525242f995b71c4f2";
+        let hashed = super::did(did);
+        assert_eq!(hashed, super::did(did));
+        assert!(hashed.starts_with("did#") && hashed.len() == 4 + 2 * HASH_LEN);
+        assert!(!hashed.contains("dc42f52a"));
+        //the kind is hashed too, a session id that happens to be a DID gives another hash
+        assert_ne!(session(did)[8..], hashed[4..]);
+        assert_ne!(super::did("did:zkp:a"), super::did("did:zkp:b"));
+    }
+
+    #[test]
+    fn test_request_ids() {
+        assert!(is_request_id(&new_request_id()));
+        assert!(is_request_id("login-42_a"));
+        assert!(!is_request_id(""));
+        assert!(!is_request_id("a b"));
+        assert!(!is_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+        //every call through the interceptor carries the same id
+        let mut id = RequestId::new();
+        assert!(is_request_id(id.as_str()));
+        let first = id.call(tonic::Request::new(())).unwrap();
+        let second = id.call(tonic::Request::new(())).unwrap();
+        assert_eq!(first.metadata().get(REQUEST_ID_HEADER).unwrap(), id.as_str());
+        assert_eq!(second.metadata().get(REQUEST_ID_HEADER), first.metadata().get(REQUEST_ID_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_layer_answers_with_the_request_id() {
+        let service = RpcTraceLayer.layer(tower::service_fn(|_: http::Request<()>| async {
+            Ok::<_, std::convert::Infallible>(http::Response::new(()))
+        }));
+        let call = |id: Option<&'static str>| {
+            let mut request = http::Request::builder().uri("/zkp_auth.Auth/Login");
+            if let Some(id) = id {
+                request = request.header(REQUEST_ID_HEADER, id);
+            }
+            service.clone().oneshot(request.body(()).unwrap())
+        };
+        let id = |response: http::Response<()>| response.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+
+        assert_eq!(id(call(Some("login-42")).await.unwrap()), "login-42");
+        //one that is not plain is replaced, like a missing one
+        let replaced = id(call(Some("{\"x\": 1}")).await.unwrap());
+        assert!(is_request_id(&replaced) && replaced.len() == 16);
+        assert_ne!(id(call(None).await.unwrap()), replaced);
+    }
+}
@@ -0,0 +1,240 @@
+//Signs session tokens and rotates the signing key.
+//A key goes through three stages:
+//  published  from when it is made, so services that fetch the JWKS know it
+//             before the first token signed with it reaches them
+//  signing    from its active_from until the next key becomes active
+//  retired    still published for `overlap` after that, the tokens it signed stay verifiable
+//rotate makes the next key active `overlap` from now, so both sides of a rotation
+//have a window in which the old and the new key are published together.
+
+use super::{session_handle, Claims, Header, Jwk, Jwks, ALGORITHM};
+use crate::error::{Result, ZkpAuthError};
+use crate::session::to_chrono;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signer, SigningKey};
+use rand::{CryptoRng, RngCore};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+//the iss of every token
+pub const ISSUER: &str = "zkp-auth-server";
+//how long a token is good for, it is never good for longer than its session
+pub const TOKEN_TTL: Duration = Duration::from_secs(5 * 60);
+//how long a key signs before the next one takes over
+pub const ROTATE_EVERY: Duration = Duration::from_secs(24 * 60 * 60);
+//how long a key is published before it signs and after it stops, at least TOKEN_TTL
+pub const KEY_OVERLAP: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenPolicy {
+    pub issuer: String,
+    pub ttl: Duration,
+    pub rotate_every: Duration,
+    pub overlap: Duration,
+    //the scope every token is granted
+    pub scope: Vec<String>,
+}
+
+impl Default for TokenPolicy {
+    fn default() -> Self {
+        Self {
+            issuer: ISSUER.to_string(),
+            ttl: TOKEN_TTL,
+            rotate_every: ROTATE_EVERY,
+            overlap: KEY_OVERLAP,
+            scope: vec!["login".to_string()],
+        }
+    }
+}
+
+struct Key {
+    kid: String,
+    signing: SigningKey,
+    active_from: DateTime<Utc>,
+}
+
+pub struct TokenIssuer {
+    policy: TokenPolicy,
+    //oldest first, and so in the order they become active
+    keys: RwLock<Vec<Key>>,
+}
+
+//the keys are secret, only their ids are printed
+impl std::fmt::Debug for TokenIssuer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kids: Vec<_> = self.read().iter().map(|key| key.kid.clone()).collect();
+        f.debug_struct("TokenIssuer").field("policy", &self.policy).field("kids", &kids).finish()
+    }
+}
+
+fn new_key<R: RngCore + CryptoRng + ?Sized>(rng: &mut R, active_from: DateTime<Utc>) -> Key {
+    let mut secret = [0u8; 32];
+    rng.fill_bytes(&mut secret);
+    let signing = SigningKey::from_bytes(&secret);
+    Key { kid: Jwk::new(&signing.verifying_key()).kid, signing, active_from }
+}
+
+impl TokenIssuer {
+    //the first key signs right away
+    pub fn new<R: RngCore + CryptoRng + ?Sized>(policy: TokenPolicy, rng: &mut R, now: DateTime<Utc>) -> Self {
+        Self { policy: Self::checked(policy), keys: RwLock::new(vec![new_key(rng, now)]) }
+    }
+
+    //the same keys under another policy
+    pub fn with_policy(self, policy: TokenPolicy) -> Self {
+        Self { policy: Self::checked(policy), ..self }
+    }
+
+    //a key has to stay published for as long as a token it signed can live
+    fn checked(policy: TokenPolicy) -> TokenPolicy {
+        TokenPolicy { overlap: policy.overlap.max(policy.ttl), ..policy }
+    }
+
+    pub fn policy(&self) -> &TokenPolicy {
+        &self.policy
+    }
+
+    //the keys change by push and drain only, a panic elsewhere cannot leave them half written
+    fn read(&self) -> RwLockReadGuard<'_, Vec<Key>> {
+        self.keys.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Vec<Key>> {
+        self.keys.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    //a token for a session, it expires after the policy's ttl or at not_after, whichever is first
+    pub fn issue(&self, did: &str, session_id: &str, auth_time: DateTime<Utc>, not_after: DateTime<Utc>, now: DateTime<Utc>) -> Result<(String, Claims)> {
+        let claims = Claims {
+            iss: self.policy.issuer.clone(),
+            sub: did.to_string(),
+            sid: session_handle(session_id),
+            auth_time: auth_time.timestamp(),
+            iat: now.timestamp(),
+            exp: (now + to_chrono(self.policy.ttl)).min(not_after).timestamp(),
+            scope: self.policy.scope.clone(),
+        };
+
+        let keys = self.read();
+        let key = keys.iter().rev().find(|key| key.active_from <= now)
+            .ok_or_else(|| ZkpAuthError::Storage("no token signing key is active yet".to_string()))?;
+        let header = Header { alg: ALGORITHM.to_string(), typ: "JWT".to_string(), kid: key.kid.clone() };
+        let signed = format!("{}.{}", URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?), URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?));
+        let signature = key.signing.sign(signed.as_bytes());
+        Ok((format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.to_bytes())), claims))
+    }
+
+    //publishes the next key, it signs from `overlap` after now
+    pub fn rotate<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R, now: DateTime<Utc>) -> String {
+        let key = new_key(rng, now + to_chrono(self.policy.overlap));
+        let kid = key.kid.clone();
+        self.write().push(key);
+        kid
+    }
+
+    //called now and then: rotates once the newest key is due and drops keys retired for longer than the overlap
+    //returns the kid of a key it added
+    pub fn maintain<R: RngCore + CryptoRng + ?Sized>(&self, rng: &mut R, now: DateTime<Utc>) -> Option<String> {
+        let overlap = to_chrono(self.policy.overlap);
+        {
+            let mut keys = self.write();
+            let retired = keys.windows(2).take_while(|pair| pair[1].active_from + overlap <= now).count();
+            keys.drain(..retired);
+        }
+        let due = self.read().last().is_some_and(|newest| newest.active_from + to_chrono(self.policy.rotate_every) <= now);
+        due.then(|| self.rotate(rng, now))
+    }
+
+    //every key that signs, will sign or signed a token that may still be live
+    pub fn jwks(&self) -> Jwks {
+        Jwks { keys: self.read().iter().map(|key| Jwk::new(&key.signing.verifying_key())).collect() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token::TokenVerifier;
+    use rand::rngs::OsRng;
+
+    fn after(now: DateTime<Utc>, seconds: i64) -> DateTime<Utc> {
+        now + chrono::Duration::seconds(seconds)
+    }
+
+    fn policy() -> TokenPolicy {
+        TokenPolicy { ttl: Duration::from_secs(60), rotate_every: Duration::from_secs(3600), overlap: Duration::from_secs(120), ..TokenPolicy::default() }
+    }
+
+    #[test]
+    fn test_issued_token_verifies_offline() {
+        let now = Utc::now();
+        let issuer = TokenIssuer::new(policy(), &mut OsRng, now);
+        let (token, claims) = issuer.issue("did:zkp:test", "session", now, after(now, 3600), now).unwrap();
+        assert_eq!(claims.exp, after(now, 60).timestamp());
+        assert_eq!(claims.sid, session_handle("session"));
+
+        let verifier = TokenVerifier::new(ISSUER, &issuer.jwks());
+        assert_eq!(verifier.verify(&token, after(now, 59)).unwrap(), claims);
+        assert!(matches!(verifier.verify(&token, after(now, 60 + crate::token::verifier::LEEWAY)), Err(ZkpAuthError::Expired(_))));
+
+        //a token never outlives its session
+        let (_, claims) = issuer.issue("did:zkp:test", "session", now, after(now, 10), now).unwrap();
+        assert_eq!(claims.exp, after(now, 10).timestamp());
+    }
+
+    #[test]
+    fn test_tampered_tokens_are_refused() {
+        let now = Utc::now();
+        let issuer = TokenIssuer::new(policy(), &mut OsRng, now);
+        let (token, claims) = issuer.issue("did:zkp:test", "session", now, after(now, 3600), now).unwrap();
+        let verifier = TokenVerifier::new(ISSUER, &issuer.jwks());
+
+        //another DID in the payload, with the original signature
+        let parts: Vec<_> = token.split('.').collect();
+        let forged = Claims { sub: "did:zkp:someone".to_string(), ..claims };
+        let forged = format!("{}.{}.{}", parts[0], URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap()), parts[2]);
+        assert!(matches!(verifier.verify(&forged, now), Err(ZkpAuthError::Crypto(_))));
+
+        //"none" in the header
+        let header = Header { alg: "none".to_string(), typ: "JWT".to_string(), kid: issuer.jwks().keys[0].kid.clone() };
+        let none = format!("{}.{}.", URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).unwrap()), parts[1]);
+        assert!(matches!(verifier.verify(&none, now), Err(ZkpAuthError::Crypto(_))));
+
+        //a key of another issuer, and another issuer name
+        let other = TokenIssuer::new(policy(), &mut OsRng, now);
+        let (token, _) = other.issue("did:zkp:test", "session", now, after(now, 3600), now).unwrap();
+        assert!(matches!(verifier.verify(&token, now), Err(ZkpAuthError::Crypto(_))));
+        assert!(TokenVerifier::new("someone else", &other.jwks()).verify(&token, now).is_err());
+        assert!(verifier.verify("a.b", now).is_err());
+    }
+
+    #[test]
+    fn test_rotation_overlaps() {
+        let now = Utc::now();
+        let issuer = TokenIssuer::new(policy(), &mut OsRng, now);
+        let first = issuer.jwks().keys[0].kid.clone();
+        assert_eq!(issuer.maintain(&mut OsRng, after(now, 3599)), None);
+
+        //the next key is published at once but signs only after the overlap
+        let next = issuer.maintain(&mut OsRng, after(now, 3600)).unwrap();
+        let verifier = TokenVerifier::new(ISSUER, &issuer.jwks());
+        let (old_token, _) = issuer.issue("did:zkp:test", "session", now, after(now, 9000), after(now, 3719)).unwrap();
+        let (new_token, _) = issuer.issue("did:zkp:test", "session", now, after(now, 9000), after(now, 3720)).unwrap();
+        assert!(old_token.starts_with(&URL_SAFE_NO_PAD.encode(serde_json::to_vec(&Header { alg: ALGORITHM.to_string(), typ: "JWT".to_string(), kid: first.clone() }).unwrap())));
+        assert!(verifier.verify(&old_token, after(now, 3720)).is_ok());
+        assert!(verifier.verify(&new_token, after(now, 3720)).is_ok());
+
+        //the old key stays published for the overlap after it stopped signing, then it goes
+        issuer.maintain(&mut OsRng, after(now, 3839));
+        assert_eq!(issuer.jwks().keys.len(), 2);
+        issuer.maintain(&mut OsRng, after(now, 3840));
+        assert_eq!(issuer.jwks().keys.iter().map(|key| key.kid.clone()).collect::<Vec<_>>(), [next]);
+    }
+
+    #[test]
+    fn test_overlap_covers_the_token_ttl() {
+        let issuer = TokenIssuer::new(TokenPolicy { overlap: Duration::from_secs(1), ..policy() }, &mut OsRng, Utc::now());
+        assert_eq!(issuer.policy().overlap, Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,125 @@
+//Signed session tokens that other services check without calling the auth server.
+//A token is a JWT (RFC 7519) signed with Ed25519, "alg": "EdDSA" as in RFC 8037.
+//The server signs with the newest of its keys and publishes every key that may
+//still have signed a live token as a JWKS (RFC 7517), see issuer.rs.
+//A service only needs verifier.rs: it is given the keys and checks tokens offline.
+
+pub mod issuer;
+pub mod verifier;
+
+use crate::error::{Result, ZkpAuthError};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub use issuer::{TokenIssuer, TokenPolicy, ISSUER};
+pub use verifier::TokenVerifier;
+
+pub const ALGORITHM: &str = "EdDSA";
+
+//what a token says about the one who logged in, times are unix seconds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    pub iss: String,
+    //the DID
+    pub sub: String,
+    //a digest of the session id (see session_handle), the token does not carry the session's bearer secret
+    pub sid: String,
+    //when the zero-knowledge proof was verified
+    pub auth_time: i64,
+    pub iat: i64,
+    pub exp: i64,
+    //what the holder is allowed to do
+    #[serde(default)]
+    pub scope: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub alg: String,
+    pub typ: String,
+    pub kid: String,
+}
+
+//an Ed25519 public key as a JWK (RFC 8037)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    pub kid: String,
+    #[serde(default)]
+    pub alg: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+impl Jwk {
+    pub fn new(key: &VerifyingKey) -> Self {
+        let x = URL_SAFE_NO_PAD.encode(key.as_bytes());
+        Self { kid: thumbprint(&x), kty: "OKP".to_string(), crv: "Ed25519".to_string(), x, alg: ALGORITHM.to_string() }
+    }
+
+    pub fn verifying_key(&self) -> Result<VerifyingKey> {
+        if self.kty != "OKP" || self.crv != "Ed25519" {
+            return Err(ZkpAuthError::Encoding(format!("key {} is {} {}, not an Ed25519 key", self.kid, self.kty, self.crv)));
+        }
+        let bytes: [u8; 32] = URL_SAFE_NO_PAD.decode(&self.x)?.try_into()
+            .map_err(|_| ZkpAuthError::Encoding(format!("key {} is not 32 bytes", self.kid)))?;
+        VerifyingKey::from_bytes(&bytes).map_err(|e| ZkpAuthError::Encoding(format!("key {}: {}", self.kid, e)))
+    }
+}
+
+//the RFC 7638 thumbprint of an Ed25519 key, the members in lexicographic order, used as its kid
+fn thumbprint(x: &str) -> String {
+    let canonical = format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, x);
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+}
+
+//what a token names a session by, a service can match it against a session it was shown
+pub fn session_handle(session_id: &str) -> String {
+    URL_SAFE_NO_PAD.encode(&Sha256::digest(session_id.as_bytes())[..16])
+}
+
+impl From<&crate::zkp_proto::Jwk> for Jwk {
+    fn from(jwk: &crate::zkp_proto::Jwk) -> Self {
+        Self { kty: jwk.kty.clone(), crv: jwk.crv.clone(), x: jwk.x.clone(), kid: jwk.kid.clone(), alg: jwk.alg.clone() }
+    }
+}
+
+impl From<&Jwk> for crate::zkp_proto::Jwk {
+    fn from(jwk: &Jwk) -> Self {
+        Self { kty: jwk.kty.clone(), crv: jwk.crv.clone(), x: jwk.x.clone(), kid: jwk.kid.clone(), alg: jwk.alg.clone() }
+    }
+}
+
+impl From<&Jwks> for crate::zkp_proto::GetJwksResponse {
+    fn from(jwks: &Jwks) -> Self {
+        Self { keys: jwks.keys.iter().map(Into::into).collect() }
+    }
+}
+
+impl From<&crate::zkp_proto::GetJwksResponse> for Jwks {
+    fn from(response: &crate::zkp_proto::GetJwksResponse) -> Self {
+        Self { keys: response.keys.iter().map(Jwk::from).collect() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rfc8037_thumbprint() {
+        //appendix A.3 of RFC 8037
+        let x = "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo";
+        assert_eq!(thumbprint(x), "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k");
+
+        let jwk = Jwk { kty: "OKP".to_string(), crv: "Ed25519".to_string(), x: x.to_string(), kid: String::new(), alg: String::new() };
+        assert_eq!(Jwk::new(&jwk.verifying_key().unwrap()).kid, "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k");
+    }
+}
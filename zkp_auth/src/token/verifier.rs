@@ -0,0 +1,70 @@
+//Checks session tokens offline, this is the part a service embeds.
+//It is given the issuer's JWKS, from the GetJwks RPC or anywhere else, and refreshes
+//it with set_keys now and then: the issuer publishes a new key before signing with
+//it, so a copy that is a little old still knows every key in use.
+
+use super::{Claims, Header, Jwks, ALGORITHM};
+use crate::error::{Result, ZkpAuthError};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use std::collections::HashMap;
+
+//seconds a clock may be off between the issuer and a service
+pub const LEEWAY: i64 = 30;
+
+#[derive(Debug, Clone)]
+pub struct TokenVerifier {
+    issuer: String,
+    keys: HashMap<String, VerifyingKey>,
+}
+
+impl TokenVerifier {
+    //keys that are not Ed25519 or do not decode are left out, a token signed with one is refused
+    pub fn new(issuer: &str, jwks: &Jwks) -> Self {
+        let mut verifier = Self { issuer: issuer.to_string(), keys: HashMap::new() };
+        verifier.set_keys(jwks);
+        verifier
+    }
+
+    pub fn set_keys(&mut self, jwks: &Jwks) {
+        self.keys = jwks.keys.iter()
+            .filter_map(|jwk| Some((jwk.kid.clone(), jwk.verifying_key().ok()?)))
+            .collect();
+    }
+
+    //the claims of a token that this issuer signed and that has not expired
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Result<Claims> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(ZkpAuthError::Encoding("a token has three parts".to_string()));
+        };
+        //the signature covers the first two parts as they were sent
+        let signed = &token[..header.len() + 1 + payload.len()];
+
+        let header: Header = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header)?)?;
+        //the algorithm is fixed, whatever the token says, so it cannot pick a weaker one
+        if header.alg != ALGORITHM {
+            return Err(ZkpAuthError::Crypto(format!("tokens are signed with {}, not {}", ALGORITHM, header.alg)));
+        }
+        let key = self.keys.get(&header.kid)
+            .ok_or_else(|| ZkpAuthError::Crypto(format!("the token is signed with an unknown key {}", header.kid)))?;
+        let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(signature)?)
+            .map_err(|_| ZkpAuthError::Encoding("the token signature is not 64 bytes".to_string()))?;
+        key.verify_strict(signed.as_bytes(), &signature)
+            .map_err(|_| ZkpAuthError::Crypto("the token signature is not valid".to_string()))?;
+
+        let claims: Claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)?;
+        if claims.iss != self.issuer {
+            return Err(ZkpAuthError::Crypto(format!("the token is from {}, not {}", claims.iss, self.issuer)));
+        }
+        let now = now.timestamp();
+        if now >= claims.exp + LEEWAY {
+            return Err(ZkpAuthError::Expired(format!("the token of {} expired", claims.sub)));
+        }
+        if claims.iat > now + LEEWAY {
+            return Err(ZkpAuthError::Crypto("the token is issued in the future".to_string()));
+        }
+        Ok(claims)
+    }
+}
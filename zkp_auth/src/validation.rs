@@ -0,0 +1,104 @@
+//Checks on values received from the other party and on the group parameters.
+//A value that fails here must never reach verify_solution, since degenerate
+//keys or commitments (zero, one, elements of small order) can make a proof
+//verify without knowledge of the secret.
+
+use num_bigint::{BigUint, RandBigInt};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    Empty,
+    //every element and scalar has one fixed width encoding
+    WrongLength { expected: usize, got: usize },
+    //not a valid encoding of an element or scalar of the group
+    InvalidEncoding,
+    Zero,
+    //the neutral element (1 mod p, or the identity point)
+    Identity,
+    //not less than p for elements, not less than q for scalars
+    OutOfRange,
+    NotInSubgroup,
+
+    //errors in the group parameters, found by the start-up check
+    NotPrime(&'static str),
+    OrderDoesNotDivide,
+    BadGenerator(&'static str),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Empty => write!(f, "is empty"),
+            ValidationError::WrongLength { expected, got } => write!(f, "is {} bytes, expected {}", got, expected),
+            ValidationError::InvalidEncoding => write!(f, "is not a valid encoding"),
+            ValidationError::Zero => write!(f, "is zero"),
+            ValidationError::Identity => write!(f, "is the identity element"),
+            ValidationError::OutOfRange => write!(f, "is out of range"),
+            ValidationError::NotInSubgroup => write!(f, "is not in the order q subgroup"),
+            ValidationError::NotPrime(name) => write!(f, "{} is not prime", name),
+            ValidationError::OrderDoesNotDivide => write!(f, "q does not divide p-1"),
+            ValidationError::BadGenerator(name) => write!(f, "{} does not have order q", name),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+//Miller-Rabin with random bases, the chance a composite passes is at most 4^-rounds
+pub fn is_probable_prime(n: &BigUint, rounds: usize) -> bool {
+    let one = BigUint::from(1u32);
+    let two = BigUint::from(2u32);
+
+    //trial division gets rid of small numbers and most composites cheaply
+    for small in [2u32, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37] {
+        let small = BigUint::from(small);
+        if *n == small {
+            return true;
+        }
+        if n % &small == BigUint::from(0u32) {
+            return false;
+        }
+    }
+    if *n < two {
+        return false;
+    }
+
+    //n - 1 = d * 2^r with d odd
+    let n_minus_one = n - &one;
+    let r = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> r;
+
+    let mut rng = rand::thread_rng();
+    'witness: for _ in 0..rounds {
+        let a = rng.gen_biguint_range(&two, &n_minus_one);
+        let mut x = a.modpow(&d, n);
+        if x == one || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..r {
+            x = x.modpow(&two, n);
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_probable_prime() {
+        for prime in [2u32, 3, 23, 7919, 2147483647] {
+            assert!(is_probable_prime(&BigUint::from(prime), 20), "{}", prime);
+        }
+        //561 is a Carmichael number, it fools the Fermat test but not Miller-Rabin
+        for composite in [0u32, 1, 4, 561, 7917, 2147483649] {
+            assert!(!is_probable_prime(&BigUint::from(composite), 20), "{}", composite);
+        }
+    }
+}
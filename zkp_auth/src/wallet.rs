@@ -1,15 +1,15 @@
 use ::zkp_auth::ssi::credential::{VerifiableCredential, DID};
 use ::zkp_auth::ssi::issuer::Issuer;
-use num_bigint::BigUint;
 use std::fs;
-use ::zkp_auth::ZKP;
+use ::zkp_auth::{context, Commitment, Group, ModpGroup, Response, ZKP, ZkpAuthError, RFC5114_1024_GROUP_ID, RFC5114_2048_256_GROUP_ID, RISTRETTO255_GROUP_ID};
+use ::zkp_auth::error::Result;
+use ::zkp_auth::params::{self, PARAMETER_SETS};
+use ::zkp_auth::telemetry;
 use serde::{Serialize, Deserialize};
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
 use base64::{Engine as _, engine::general_purpose};
 
-pub mod zkp_auth {
-    include!("zkp_proto.rs");
-}
-
 // The wallet stores credentials and generates ZKP proofs
 
 // Store wallet data including secret and DID
@@ -17,21 +17,31 @@ pub mod zkp_auth {
 pub struct WalletData {
     pub did: String,                        // Store DID as string
     pub credential: VerifiableCredential,
-    pub secret: String,                     // Store as string for JSON
+    pub secret: String,                     // Base64 encoded, like y1 and y2
+    #[serde(default = "default_group_id")]
+    pub group: String,                      // Group the keys live in
+}
+
+// wallets created before the group was recorded are in the 1024-bit group
+fn default_group_id() -> String {
+    RFC5114_1024_GROUP_ID.to_string()
 }
 
-pub struct Wallet {
+// The wallet works with any group, the secret is a scalar of that group
+pub struct Wallet<G: Group = ModpGroup> {
     username: String,
+    group: String,
     did: Option<DID>,
     credential: Option<VerifiableCredential>,
-    zkp_secret: Option<BigUint>,
+    zkp_secret: Option<G::Scalar>,
 }
 
-impl Wallet {
-    // Create new wallet for a user
-    pub fn new(username: String) -> Self {
+impl<G: Group> Wallet<G> {
+    // Create new wallet for a user, keys are generated in the given group
+    pub fn new(username: String, group: String) -> Self {
         Self {
             username,
+            group,
             did: None,
             credential: None,
             zkp_secret: None,
@@ -39,45 +49,66 @@ impl Wallet {
     }
     
     // Generate a new secret for this wallet
-    pub fn generate_secret(&mut self, q: &BigUint) -> BigUint {
-        let secret = ZKP::generate_random_number_less_than(q);
+    pub fn generate_secret(&mut self, zkp: &ZKP<G>) -> G::Scalar {
+        self.generate_secret_with(zkp, &mut OsRng)
+    }
+
+    // the same from a given RNG, e.g. a seeded one to recreate a wallet in a test
+    pub fn generate_secret_with<R: RngCore + CryptoRng + ?Sized>(&mut self, zkp: &ZKP<G>, rng: &mut R) -> G::Scalar {
+        let secret = zkp.group.random_scalar_with(rng);
         self.zkp_secret = Some(secret.clone());
-        println!("✓ Generated new secret for wallet");
+        tracing::debug!(group = %self.group, "generated a new secret");
         secret
     }
     
+    // the secret, an error until generate_secret has run
+    fn secret(&self) -> Result<&G::Scalar> {
+        self.zkp_secret.as_ref()
+            .ok_or_else(|| ZkpAuthError::Protocol(format!("wallet for {} has no secret yet", self.username)))
+    }
+
+    // the credential, an error until store_credential has run
+    fn credential(&self) -> Result<&VerifiableCredential> {
+        self.credential.as_ref()
+            .ok_or_else(|| ZkpAuthError::Protocol(format!("wallet for {} has no credential yet", self.username)))
+    }
+    
     // Store credential, DID AND secret
-    pub fn store_credential(&mut self, credential: VerifiableCredential, did: DID) {
+    pub fn store_credential(&mut self, credential: VerifiableCredential, did: DID, zkp: &ZKP<G>) -> Result<()> {
+        let secret_bytes = zkp.group.scalar_to_bytes(self.secret()?);
         let wallet_data = WalletData {
             did: did.to_string(),
             credential: credential.clone(),
-            secret: self.zkp_secret.as_ref().unwrap().to_string(),
+            secret: general_purpose::STANDARD.encode(secret_bytes),
+            group: self.group.clone(),
         };
         
-        // Use DID-based filename instead of username
-        let did_hash = did.to_string().replace(":", "_");
-        let filename = format!("{}_wallet.json", did_hash);
-        let json = serde_json::to_string_pretty(&wallet_data).unwrap();
-        fs::write(&filename, json).unwrap();
+        let filename = wallet_file(&wallet_data.did);
+        let json = serde_json::to_string_pretty(&wallet_data)?;
+        fs::write(&filename, json)?;
         
+        tracing::info!(did = %telemetry::did(&wallet_data.did), group = %self.group, "wallet saved");
         self.credential = Some(credential);
         self.did = Some(did);
-        println!("✓ Wallet saved with credential, DID and secret");
-        println!("📁 Wallet file: {}", filename);
+        Ok(())
     }
     
     // Load wallet including secret and DID
-    pub fn load_by_did(did_string: &str) -> Option<WalletData> {
-        let did_hash = did_string.replace(":", "_");
-        let filename = format!("{}_wallet.json", did_hash);
-        
-        if let Ok(data) = fs::read_to_string(&filename) {
-            if let Ok(wallet_data) = serde_json::from_str::<WalletData>(&data) {
-                println!("✓ Loaded wallet for DID: {}", did_string);
-                return Some(wallet_data);
-            }
-        }
-        None
+    // a missing file is NotFound, a file that does not parse is an encoding error
+    pub fn load_by_did(did_string: &str) -> Result<WalletData> {
+        let filename = wallet_file(did_string);
+        let data = fs::read_to_string(&filename).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ZkpAuthError::NotFound(format!("wallet file {}", filename)),
+            _ => ZkpAuthError::from(e),
+        })?;
+        let wallet_data = serde_json::from_str::<WalletData>(&data)
+            .map_err(|e| ZkpAuthError::Encoding(format!("wallet file {} is corrupted: {}", filename, e)))?;
+        // a wallet made before beta was hashed into the subgroup can not log in any more
+        let proof = &wallet_data.credential.proof;
+        let (y1, y2) = (general_purpose::STANDARD.decode(&proof.y1)?, general_purpose::STANDARD.decode(&proof.y2)?);
+        params::check_current_keys(&wallet_data.group, did_string, &y1, &y2)?;
+        tracing::debug!(did = %telemetry::did(did_string), group = %wallet_data.group, "wallet loaded");
+        Ok(wallet_data)
     }
     
     // Get the DID for this wallet
@@ -86,51 +117,70 @@ impl Wallet {
     }
     
     // Generate complete authentication data
-    pub fn generate_auth_data(&self, zkp: &ZKP) -> Option<(BigUint, BigUint, BigUint)> {
-        if let (Some(cred), Some(secret)) = (&self.credential, &self.zkp_secret) {
-            // Generate k for this authentication
-            let k = ZKP::generate_random_number_less_than(&zkp.q);
-            
-            // Get y1, y2 from credential proof (decode from base64)
-            let y1_bytes = general_purpose::STANDARD.decode(&cred.proof.y1).unwrap();
-            let y2_bytes = general_purpose::STANDARD.decode(&cred.proof.y2).unwrap();
-            let y1 = BigUint::from_bytes_be(&y1_bytes);
-            let y2 = BigUint::from_bytes_be(&y2_bytes);
-            
-            // Compute r1, r2
-            let r1 = ZKP::exponentiate(&zkp.alpha, &k, &zkp.p);
-            let r2 = ZKP::exponentiate(&zkp.beta, &k, &zkp.p);
-            
-            Some((r1, r2, k))
-        } else {
-            None
-        }
+    // the commitment goes to the server, k stays in the wallet until the challenge comes back
+    // the context names this login, k is derived from it and the secret so a bad RNG cannot repeat it
+    pub fn generate_auth_data(&self, zkp: &ZKP<G>, context: &[u8]) -> Result<(Commitment, G::Scalar)> {
+        let (y1, y2) = self.get_zkp_params(zkp)?;
+
+        // Derive k for this authentication and compute r1, r2
+        let (r1, r2, k) = zkp.commit(self.secret()?, &y1, &y2, context);
+        
+        Ok((Commitment::new(zkp, &r1, &r2), k))
     }
     
     // Generate proof given challenge
-    pub fn generate_proof(&self, k: &BigUint, challenge: &BigUint, zkp: &ZKP) -> BigUint {
-        let secret = self.zkp_secret.as_ref().unwrap();
-        zkp.solve(k, challenge, secret)
+    pub fn generate_proof(&self, k: &G::Scalar, challenge: &G::Scalar, zkp: &ZKP<G>) -> Result<Response> {
+        Ok(Response::new(zkp, &zkp.solve(k, challenge, self.secret()?)))
     }
     
     // Get ZKP params for registration
-    pub fn get_zkp_params(&self) -> Option<(BigUint, BigUint)> {
-        if let Some(cred) = &self.credential {
-            // Decode from base64
-            let y1_bytes = general_purpose::STANDARD.decode(&cred.proof.y1).unwrap();
-            let y2_bytes = general_purpose::STANDARD.decode(&cred.proof.y2).unwrap();
-            let y1 = BigUint::from_bytes_be(&y1_bytes);
-            let y2 = BigUint::from_bytes_be(&y2_bytes);
-            Some((y1, y2))
-        } else {
-            None
-        }
+    pub fn get_zkp_params(&self, zkp: &ZKP<G>) -> Result<(G::Element, G::Element)> {
+        let cred = self.credential()?;
+        // Decode from base64
+        let y1_bytes = general_purpose::STANDARD.decode(&cred.proof.y1)?;
+        let y2_bytes = general_purpose::STANDARD.decode(&cred.proof.y2)?;
+        let y1 = zkp.decode_element(&y1_bytes).map_err(|e| ZkpAuthError::invalid("y1", e))?;
+        let y2 = zkp.decode_element(&y2_bytes).map_err(|e| ZkpAuthError::invalid("y2", e))?;
+        Ok((y1, y2))
     }
 }
 
+// Use DID-based filename instead of username
+fn wallet_file(did: &str) -> String {
+    format!("{}_wallet.json", did.replace(":", "_"))
+}
+
+// Creates the keys, gets them a credential and saves the wallet
+fn create_wallet<G: Group>(username: String, group_id: &str, zkp: &ZKP<G>) -> Result<()> {
+    let _span = tracing::info_span!("create_wallet", group = %group_id).entered();
+    let mut wallet = Wallet::new(username.clone(), group_id.to_string());
+    
+    let secret = wallet.generate_secret(zkp);
+    let (y1, y2) = zkp.exponentiate_pair(&secret);
+    println!("✓ Generated new secret for wallet");
+    
+    println!("\n🏛️ ISSUER: Pan-Atlantic University");
+    println!("  Issuing credential for: {}", username);
+    let (credential, did) = Issuer::issue_credential(&username, &zkp.group.element_to_bytes(&y1), &zkp.group.element_to_bytes(&y2))?;
+    println!("  📋 Generated DID: {}", did);
+    println!("  ✓ Credential created with ID: {}", credential.id);
+    wallet.store_credential(credential, did.clone(), zkp)?;
+    println!("✓ Wallet saved with credential, DID and secret for {}", username);
+    println!("📁 Wallet file: {}", wallet_file(&did.to_string()));
+    
+    println!("\n✅ New SSI wallet created!");
+    println!("\n🆔 YOUR NEW DECENTRALIZED IDENTIFIER (DID):");
+    println!("   {}", did);
+    println!("   📝 Save this DID - it's your unique digital identity!");
+    println!("\n📤 Use this DID with the client application for authentication");
+    Ok(())
+}
+
 // Wallet executable main function
 #[tokio::main]
 async fn main() {
+    // RUST_LOG=debug shows what the wallet does, the hashed DID matches the server's logs
+    telemetry::init("warn", false);
     let mut buf = String::new();
     
     println!("🔐 SSI WALLET - Self-Sovereign Identity System");
@@ -153,21 +203,26 @@ async fn main() {
             let username = buf.trim().to_string();
             buf.clear();
 
-            let mut wallet = Wallet::new(username.clone());
-            
-            let (alpha, beta, p, q) = ::zkp_auth::ZKP::get_zkp_constants();
-            let secret = wallet.generate_secret(&q);
-            let y1 = ::zkp_auth::ZKP::exponentiate(&alpha, &secret, &p);
-            let y2 = ::zkp_auth::ZKP::exponentiate(&beta, &secret, &p);
-            
-            let (credential, did) = Issuer::issue_credential(&username, &y1, &y2);
-            wallet.store_credential(credential, did.clone());
-            
-            println!("\n✅ New SSI wallet created!");
-            println!("\n🆔 YOUR NEW DECENTRALIZED IDENTIFIER (DID):");
-            println!("   {}", did.to_string());
-            println!("   📝 Save this DID - it's your unique digital identity!");
-            println!("\n📤 Use this DID with the client application for authentication");
+            println!("Choose a group for your keys (press Enter for {}):", RFC5114_2048_256_GROUP_ID);
+            for set in PARAMETER_SETS {
+                println!("   {} - {}", set.id, set.description);
+            }
+            println!("   {} - smaller and faster proofs", RISTRETTO255_GROUP_ID);
+            std::io::stdin().read_line(&mut buf).expect("could not read group");
+            let group_id = match buf.trim() {
+                "" => RFC5114_2048_256_GROUP_ID.to_string(),
+                id => id.to_string(),
+            };
+            buf.clear();
+
+            let result = if group_id == RISTRETTO255_GROUP_ID {
+                create_wallet(username, &group_id, context::ristretto255())
+            } else {
+                context::modp(&group_id).and_then(|zkp| create_wallet(username, &group_id, zkp))
+            };
+            if let Err(e) = result {
+                println!("❌ Could not create the wallet: {}", e);
+            }
         },
         "2" => {
             // Load existing wallet by DID
@@ -181,18 +236,26 @@ async fn main() {
                 return;
             }
 
-            if let Some(_wallet_data) = Wallet::load_by_did(&did_string) {
-                println!("\n🆔 YOUR DECENTRALIZED IDENTIFIER (DID):");
-                println!("   {}", did_string);
-                println!("   This is your unique, self-sovereign identity!");
-                
-                println!("\nWallet ready for authentication");
-                println!("📤 Copy your DID above and paste it into the client application");
-                println!("Press Enter when done...");
-                std::io::stdin().read_line(&mut buf).unwrap();
-            } else {
-                println!("❌ Wallet not found for DID: {}", did_string);
-                println!("Make sure you have the correct DID or create a new wallet");
+            match Wallet::<ModpGroup>::load_by_did(&did_string) {
+                Ok(_wallet_data) => {
+                    println!("✓ Loaded wallet for DID: {}", did_string);
+                    println!("\n🆔 YOUR DECENTRALIZED IDENTIFIER (DID):");
+                    println!("   {}", did_string);
+                    println!("   This is your unique, self-sovereign identity!");
+                    
+                    println!("\nWallet ready for authentication");
+                    println!("📤 Copy your DID above and paste it into the client application");
+                    println!("🪪 The client enrolls the DID with the server once, every later run only logs in");
+                    println!("Press Enter when done...");
+                    std::io::stdin().read_line(&mut buf).expect("could not read input");
+                },
+                Err(ZkpAuthError::NotFound(_)) => {
+                    println!("❌ Wallet not found for DID: {}", did_string);
+                    println!("Make sure you have the correct DID or create a new wallet");
+                },
+                Err(e) => {
+                    println!("❌ Could not load the wallet: {}", e);
+                }
             }
         },
         _ => {